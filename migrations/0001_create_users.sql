@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS users (
+    first_name TEXT NOT NULL,
+    last_name TEXT NOT NULL,
+    username TEXT NOT NULL,
+    email TEXT NOT NULL,
+    CONSTRAINT users_username_key UNIQUE (username)
+);
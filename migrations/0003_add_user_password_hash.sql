@@ -0,0 +1,2 @@
+-- Argon2id PHC string, NULL for users created without a password
+ALTER TABLE users ADD COLUMN password_hash TEXT;
@@ -0,0 +1,4 @@
+-- existing users become viewers, admins are assigned explicitly
+ALTER TABLE users
+    ADD COLUMN role TEXT NOT NULL DEFAULT 'viewer'
+    CONSTRAINT users_role_check CHECK (role IN ('admin', 'editor', 'viewer'));
@@ -0,0 +1,12 @@
+-- API keys for service-to-service calls, only the SHA-256 hash of the key is stored
+CREATE TABLE api_keys (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL CONSTRAINT api_keys_key_hash_key UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_by UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);
@@ -0,0 +1,9 @@
+-- TOTP secrets and hashed one-time recovery codes, one row per enrolled user
+CREATE TABLE user_two_factor (
+    user_id UUID PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    secret TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL,
+    recovery_code_hashes TEXT[] NOT NULL,
+    last_used_step BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
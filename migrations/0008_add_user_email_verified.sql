@@ -0,0 +1,2 @@
+-- existing users have to verify their address like new ones
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,7 @@
+-- ids of single-use tokens that were already used, kept until the tokens expire
+CREATE TABLE used_tokens (
+    jti TEXT PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX used_tokens_expires_at_idx ON used_tokens (expires_at);
@@ -0,0 +1,2 @@
+-- second factor codes tried since the last successful login
+ALTER TABLE user_two_factor ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- unix time until which second factor codes are refused, 0 when not locked out
+ALTER TABLE user_two_factor ADD COLUMN locked_until BIGINT NOT NULL DEFAULT 0;
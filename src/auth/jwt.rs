@@ -0,0 +1,393 @@
+use std::{ collections::HashMap, future::{ ready, Ready } };
+
+use actix_web::{
+    body::{ EitherBody, MessageBody },
+    dev::{ Payload, ServiceRequest, ServiceResponse },
+    http::header::{ self, HeaderValue },
+    middleware::Next,
+    web,
+    Error,
+    FromRequest,
+    HttpMessage,
+    HttpRequest,
+    ResponseError,
+};
+use chrono::Utc;
+use jsonwebtoken::{ decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation };
+use log::{ error, warn };
+use rand::{ distributions::Alphanumeric, Rng };
+use serde::{ Deserialize, Serialize };
+use uuid::Uuid;
+
+use crate::{ configs::settings::JwtSettings, models::error_model::ApiErrorType };
+
+// Lifetime of the tokens handed out between the password check and the second factor.
+pub const CHALLENGE_EXPIRY_SECONDS: i64 = 300;
+// Wrong second factor codes allowed before the user is locked out.
+pub const MAX_TWO_FACTOR_ATTEMPTS: i32 = 5;
+// How long a user is locked out once the attempts are used up. Each further wrong code
+// starts the lockout again, until a code is accepted.
+pub const TWO_FACTOR_LOCKOUT_SECONDS: i64 = 900;
+
+/// Claims of a verified access token. The `jwt_auth` middleware puts them into the request
+/// extensions, `rbac::Principal` turns them into the caller's identity.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String,
+    // the role's permissions, space separated as in RFC 8693
+    #[serde(default)]
+    pub scope: String,
+    // the address a mailed token was sent to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+/// What a token mailed to a user is good for. Each purpose has its own audience,
+/// so the tokens can't be used for another purpose or as access tokens.
+#[derive(Clone, Copy, Debug)]
+pub enum EmailTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl EmailTokenPurpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            EmailTokenPurpose::VerifyEmail => "verify-email",
+            EmailTokenPurpose::ResetPassword => "reset-password",
+        }
+    }
+}
+
+impl FromRequest for Claims {
+    type Error = ApiErrorType;
+    type Future = Ready<Result<Self, ApiErrorType>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<Claims>().cloned().ok_or(ApiErrorType::AuthenticationError))
+    }
+}
+
+/// Signing and verification settings for access tokens.
+///
+/// `JWT_KEYS` holds comma separated `kid:secret` pairs and `jwt.active_kid` picks the one
+/// used for signing. Older keys stay valid for verification, so keys can be rotated by
+/// adding a new pair, switching the active kid and removing the old pair once its tokens expired.
+/// `jwt.refresh_expiry_seconds` sets the lifetime of the refresh tokens issued next to them.
+pub struct JwtConfig {
+    pub issuer: String,
+    pub audience: String,
+    pub expiry_seconds: i64,
+    pub refresh_expiry_seconds: i64,
+    active_kid: String,
+    encoding_key: EncodingKey,
+    decoding_keys: HashMap<String, DecodingKey>,
+}
+
+impl JwtConfig {
+    /// Builds the config from validated `settings`.
+    pub fn new(settings: &JwtSettings) -> Self {
+        let mut secrets: Vec<(String, String)> = settings.keys
+            .iter()
+            .map(|(kid, secret)| (kid.to_owned(), secret.to_owned()))
+            .collect();
+        if secrets.is_empty() {
+            // NOTE: tokens signed with a random key are invalidated on restart,
+            // `Settings::validate` allows it outside the prod profile only.
+            warn!("JWT_KEYS not set, signing access tokens with a random key");
+            let secret = rand::thread_rng().sample_iter(&Alphanumeric).take(64).map(char::from).collect();
+            secrets.push(("ephemeral".to_owned(), secret));
+        }
+        for (kid, secret) in &secrets {
+            if secret.len() < 32 {
+                warn!("JWT key {kid} is shorter than 32 bytes");
+            }
+        }
+
+        let active_kid = settings.active_kid.clone().unwrap_or_else(|| secrets[0].0.clone());
+        let active_secret = secrets
+            .iter()
+            .find(|(kid, _)| *kid == active_kid)
+            .map(|(_, secret)| secret.clone())
+            .expect("jwt.active_kid is checked by Settings::validate");
+
+        JwtConfig {
+            issuer: settings.issuer.clone(),
+            audience: settings.audience.clone(),
+            expiry_seconds: settings.expiry_seconds,
+            refresh_expiry_seconds: settings.refresh_expiry_seconds,
+            active_kid,
+            encoding_key: EncodingKey::from_secret(active_secret.as_bytes()),
+            decoding_keys: secrets
+                .iter()
+                .map(|(kid, secret)| (kid.clone(), DecodingKey::from_secret(secret.as_bytes())))
+                .collect(),
+        }
+    }
+
+    /// Signs an access token for `subject` with the active key.
+    pub fn issue(&self, subject: &str, scopes: &[&str]) -> Result<String, ApiErrorType> {
+        self.sign(subject, &self.audience, self.expiry_seconds, scopes.join(" "), None)
+    }
+
+    /// Signs a token proving that `subject` passed the password check of a login that
+    /// still needs the second factor. It has its own audience, so it is no access token.
+    pub fn issue_challenge(&self, subject: &str) -> Result<String, ApiErrorType> {
+        let audience = self.challenge_audience();
+        self.sign(subject, &audience, CHALLENGE_EXPIRY_SECONDS, String::new(), None)
+    }
+
+    /// Verifies a token from `issue_challenge`. Its `jti` still has to be marked used.
+    pub fn verify_challenge(&self, token: &str) -> Result<Claims, ApiErrorType> {
+        self.verify_audience(token, &self.challenge_audience())
+    }
+
+    fn challenge_audience(&self) -> String {
+        format!("{}:two-factor", self.audience)
+    }
+
+    /// Signs a token for `purpose` that is mailed to `subject` at `email`.
+    /// It only works as long as the user's email is still `email`.
+    pub fn issue_email_token(
+        &self,
+        purpose: EmailTokenPurpose,
+        subject: &str,
+        email: &str,
+        expiry_seconds: i64
+    ) -> Result<String, ApiErrorType> {
+        let audience = self.email_audience(purpose);
+        self.sign(subject, &audience, expiry_seconds, String::new(), Some(email.to_owned()))
+    }
+
+    /// Verifies a token from `issue_email_token`. Its `jti` still has to be marked used.
+    pub fn verify_email_token(
+        &self,
+        purpose: EmailTokenPurpose,
+        token: &str
+    ) -> Result<Claims, ApiErrorType> {
+        let claims = self.verify_audience(token, &self.email_audience(purpose))?;
+        if claims.email.is_none() {
+            return Err(ApiErrorType::AuthenticationError);
+        }
+        Ok(claims)
+    }
+
+    fn email_audience(&self, purpose: EmailTokenPurpose) -> String {
+        format!("{}:{}", self.audience, purpose.as_str())
+    }
+
+    fn sign(
+        &self,
+        subject: &str,
+        audience: &str,
+        expiry_seconds: i64,
+        scope: String,
+        email: Option<String>
+    ) -> Result<String, ApiErrorType> {
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            sub: subject.to_owned(),
+            iss: self.issuer.clone(),
+            aud: audience.to_owned(),
+            iat: now,
+            exp: now + expiry_seconds,
+            jti: Uuid::new_v4().to_string(),
+            scope,
+            email,
+        };
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(self.active_kid.clone());
+        encode(&header, &claims, &self.encoding_key).map_err(|err| {
+            error!("Error: {}", err);
+            ApiErrorType::InternalServerError
+        })
+    }
+
+    /// Verifies signature, expiry, issuer and audience, picking the key by the token's `kid`.
+    pub fn verify(&self, token: &str) -> Result<Claims, ApiErrorType> {
+        self.verify_audience(token, &self.audience)
+    }
+
+    fn verify_audience(&self, token: &str, audience: &str) -> Result<Claims, ApiErrorType> {
+        let header = decode_header(token).map_err(|_| ApiErrorType::AuthenticationError)?;
+        let key = header.kid
+            .and_then(|kid| self.decoding_keys.get(&kid))
+            .ok_or(ApiErrorType::AuthenticationError)?;
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        decode::<Claims>(token, key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| ApiErrorType::AuthenticationError)
+    }
+}
+
+/// Verifies `Authorization: Bearer` tokens and stores their `Claims` in the request extensions.
+/// Requests without the header pass through, invalid tokens are rejected with `AuthenticationError`.
+pub async fn jwt_auth(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let token = match req.headers().get(header::AUTHORIZATION) {
+        Some(value) =>
+            match value.to_str().ok().and_then(|value| value.strip_prefix("Bearer ")) {
+                Some(token) => Some(token.trim().to_owned()),
+                None => {
+                    return Ok(unauthorized(req));
+                }
+            }
+        None => None,
+    };
+
+    if let Some(token) = token {
+        let verified = match req.app_data::<web::Data<JwtConfig>>() {
+            Some(config) => config.verify(&token),
+            None => Err(ApiErrorType::AuthenticationError),
+        };
+        match verified {
+            Ok(claims) => {
+                req.extensions_mut().insert(claims);
+            }
+            Err(_) => {
+                return Ok(unauthorized(req));
+            }
+        }
+    }
+
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+fn unauthorized<B>(req: ServiceRequest) -> ServiceResponse<EitherBody<B>> {
+    let mut resp = ApiErrorType::AuthenticationError.error_response();
+    resp.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_static("Bearer error=\"invalid_token\"")
+    );
+    req.into_response(resp).map_into_right_body()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        middleware::from_fn,
+        test::{ call_and_read_body, call_service, init_service, TestRequest },
+        App,
+        HttpResponse,
+    };
+
+    use super::*;
+
+    const OLD_KEY: &str = "old:0123456789abcdef0123456789abcdef";
+    const NEW_KEY: &str = "new:fedcba9876543210fedcba9876543210";
+
+    fn config(keys: &str, active_kid: Option<&str>) -> JwtConfig {
+        JwtConfig::new(
+            &(JwtSettings {
+                keys: keys.parse().unwrap(),
+                active_kid: active_kid.map(str::to_owned),
+                ..JwtSettings::default()
+            })
+        )
+    }
+
+    fn kid(token: &str) -> String {
+        decode_header(token).unwrap().kid.unwrap()
+    }
+
+    #[test]
+    fn old_tokens_verify_until_their_key_is_removed() {
+        let before = config(OLD_KEY, None);
+        let old_token = before.issue("user", &["users:read"]).unwrap();
+        assert_eq!(kid(&old_token), "old");
+
+        // the new key signs, the old one still verifies
+        let rotated = config(&format!("{OLD_KEY},{NEW_KEY}"), Some("new"));
+        let new_token = rotated.issue("user", &["users:read"]).unwrap();
+        assert_eq!(kid(&new_token), "new");
+        assert_eq!(rotated.verify(&old_token).unwrap().scope, "users:read");
+        assert!(rotated.verify(&new_token).is_ok());
+        assert!(before.verify(&new_token).is_err());
+
+        let after = config(NEW_KEY, None);
+        assert!(after.verify(&new_token).is_ok());
+        assert!(after.verify(&old_token).is_err());
+    }
+
+    #[test]
+    fn tokens_signed_with_another_secret_are_rejected() {
+        let token = config(OLD_KEY, None).issue("user", &[]).unwrap();
+        // same kid, different secret
+        let other = config("old:ffffffffffffffffffffffffffffffff", None);
+        assert!(other.verify(&token).is_err());
+        assert!(other.verify("not a token").is_err());
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let mut jwt = config(OLD_KEY, None);
+        // past the default leeway of a minute
+        jwt.expiry_seconds = -120;
+        let token = jwt.issue("user", &[]).unwrap();
+        assert!(jwt.verify(&token).is_err());
+    }
+
+    #[test]
+    fn tokens_only_work_for_their_purpose() {
+        let jwt = config(OLD_KEY, None);
+        let access = jwt.issue("user", &[]).unwrap();
+        let challenge = jwt.issue_challenge("user").unwrap();
+        let verify_email = jwt
+            .issue_email_token(EmailTokenPurpose::VerifyEmail, "user", "a@example.com", 60)
+            .unwrap();
+
+        assert!(jwt.verify_challenge(&access).is_err());
+        assert!(jwt.verify(&challenge).is_err());
+        assert!(jwt.verify(&verify_email).is_err());
+        assert!(jwt.verify_email_token(EmailTokenPurpose::ResetPassword, &verify_email).is_err());
+        let claims = jwt.verify_email_token(EmailTokenPurpose::VerifyEmail, &verify_email).unwrap();
+        assert_eq!(claims.email.as_deref(), Some("a@example.com"));
+    }
+
+    #[actix_web::test]
+    async fn middleware_rejects_invalid_bearer_tokens() {
+        let jwt = web::Data::new(config(OLD_KEY, None));
+        let token = jwt.issue("user", &["users:read"]).unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(jwt)
+                .wrap(from_fn(jwt_auth))
+                .route(
+                    "/",
+                    web::get().to(|claims: Option<Claims>| async move {
+                        HttpResponse::Ok().body(claims.map(|claims| claims.sub).unwrap_or_default())
+                    })
+                )
+        ).await;
+        let call_with = |authorization: Option<String>| {
+            let request = TestRequest::get().uri("/");
+            match authorization {
+                Some(value) => request.insert_header((header::AUTHORIZATION, value)),
+                None => request,
+            }.to_request()
+        };
+
+        let body = call_and_read_body(&app, call_with(Some(format!("Bearer {token}")))).await;
+        assert_eq!(body, "user");
+        // requests without a token are left to the route guards
+        let body = call_and_read_body(&app, call_with(None)).await;
+        assert_eq!(body, "");
+        for value in ["Bearer nonsense".to_owned(), format!("Basic {token}")] {
+            let response = call_service(&app, call_with(Some(value))).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod password;
+pub mod jwt;
+pub mod token;
+pub mod rbac;
+pub mod totp;
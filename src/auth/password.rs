@@ -0,0 +1,57 @@
+use std::sync::OnceLock;
+
+use argon2::{
+    password_hash::{ self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString },
+    Argon2,
+};
+use rand::rngs::OsRng;
+
+/// Hashes `password` with Argon2id and a random salt, in PHC string format.
+/// This is slow on purpose, call it from `web::block`.
+pub fn hash_password(password: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+/// Checks `password` against a stored PHC hash. The comparison is constant time.
+/// Without a hash, a dummy one is verified anyway so unknown usernames take as long
+/// as wrong passwords. Call it from `web::block`.
+pub fn verify_password(password: &str, hash: Option<&str>) -> bool {
+    let parsed = hash.and_then(|hash| PasswordHash::new(hash).ok());
+    match parsed {
+        Some(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        None => {
+            if let Ok(dummy) = PasswordHash::new(dummy_hash()) {
+                let _ = Argon2::default().verify_password(password.as_bytes(), &dummy);
+            }
+            false
+        }
+    }
+}
+
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password("dummy password").unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_are_salted_argon2id() {
+        let first = hash_password("correct horse").unwrap();
+        let second = hash_password("correct horse").unwrap();
+        assert!(first.starts_with("$argon2id$"));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn only_the_right_password_verifies() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(verify_password("correct horse", Some(&hash)));
+        assert!(!verify_password("wrong horse", Some(&hash)));
+        assert!(!verify_password("correct horse", Some("not a hash")));
+        assert!(!verify_password("dummy password", None));
+    }
+}
@@ -0,0 +1,334 @@
+use std::{ future::{ ready, Future, Ready }, pin::Pin, rc::Rc };
+
+use actix_session::SessionExt;
+use actix_web::{
+    body::{ EitherBody, MessageBody },
+    dev::{ forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform },
+    web,
+    Error,
+    FromRequest,
+    HttpMessage,
+    HttpRequest,
+    ResponseError,
+};
+
+use crate::{
+    configs::db::AppStates,
+    constants::{ API_KEY_HEADER, SESSION_USER_ID },
+    models::{ error_model::ApiErrorType, role_model::Permission, user_model::User },
+    repositories::user_repository::UserKey,
+};
+use super::{ jwt::Claims, token::hash_token };
+
+/// Who is calling: a user, or a service with an API key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Subject {
+    User(String),
+    ApiKey(String),
+}
+
+/// The authenticated caller and what it may do, from a bearer token,
+/// an `X-API-Key` header or the cookie session.
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub subject: Subject,
+    scopes: Vec<String>,
+}
+
+impl Principal {
+    /// Resolves the caller of `req`. Bearer token claims win over API keys, which win over
+    /// the session. The session's user is loaded so role changes apply right away.
+    /// The result is cached in the request extensions.
+    pub async fn resolve(req: &HttpRequest) -> Result<Principal, ApiErrorType> {
+        let cached = req.extensions().get::<Principal>().cloned();
+        if let Some(principal) = cached {
+            return Ok(principal);
+        }
+
+        let claims = req.extensions().get::<Claims>().cloned();
+        let api_key = req.headers().get(API_KEY_HEADER).cloned();
+        let principal = match (claims, api_key) {
+            (Some(claims), _) =>
+                Principal {
+                    subject: Subject::User(claims.sub),
+                    scopes: claims.scope.split_whitespace().map(str::to_owned).collect(),
+                },
+            (None, Some(api_key)) => {
+                let api_key = api_key.to_str().map_err(|_| ApiErrorType::AuthenticationError)?;
+                Principal::from_api_key(app_states(req)?, api_key).await?
+            }
+            (None, None) => {
+                let user_id = req
+                    .get_session()
+                    .get::<String>(SESSION_USER_ID)
+                    .ok()
+                    .flatten()
+                    .ok_or(ApiErrorType::AuthenticationError)?;
+                match app_states(req)?.users.find(&UserKey::parse(user_id)).await? {
+                    Some(user) =>
+                        Principal {
+                            subject: Subject::User(user.id),
+                            scopes: user.role
+                                .scopes()
+                                .into_iter()
+                                .map(str::to_owned)
+                                .collect(),
+                        },
+                    None => {
+                        return Err(ApiErrorType::AuthenticationError);
+                    }
+                }
+            }
+        };
+        req.extensions_mut().insert(principal.clone());
+        Ok(principal)
+    }
+
+    // Looks the key up by its hash and records the use. Revoked keys are rejected.
+    async fn from_api_key(states: &AppStates, key: &str) -> Result<Principal, ApiErrorType> {
+        let api_key = match states.api_keys.find_by_hash(&hash_token(key.trim())).await? {
+            Some(api_key) if api_key.revoked_at.is_none() => api_key,
+            _ => {
+                return Err(ApiErrorType::AuthenticationError);
+            }
+        };
+        states.api_keys.touch(&api_key.id).await?;
+        Ok(Principal {
+            subject: Subject::ApiKey(api_key.id),
+            scopes: api_key.scopes
+                .iter()
+                .map(|scope| scope.as_str().to_owned())
+                .collect(),
+        })
+    }
+
+    /// Id of the calling user, `None` for API keys.
+    pub fn user_id(&self) -> Option<&str> {
+        match &self.subject {
+            Subject::User(id) => Some(id),
+            Subject::ApiKey(_) => None,
+        }
+    }
+
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.scopes.iter().any(|scope| scope == permission.as_str())
+    }
+
+    /// Fails with `AuthorizationError` unless the caller was granted `permission`.
+    pub fn require(&self, permission: Permission) -> Result<(), ApiErrorType> {
+        if self.has_permission(permission) {
+            Ok(())
+        } else {
+            Err(ApiErrorType::AuthorizationError)
+        }
+    }
+
+    /// Admins may act on any user, everyone else only on their own record.
+    pub fn require_self_or_admin(&self, user: &User) -> Result<(), ApiErrorType> {
+        if self.user_id() == Some(user.id.as_str()) || self.has_permission(Permission::UsersAdmin) {
+            Ok(())
+        } else {
+            Err(ApiErrorType::AuthorizationError)
+        }
+    }
+}
+
+impl FromRequest for Principal {
+    type Error = ApiErrorType;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, ApiErrorType>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move { Principal::resolve(&req).await })
+    }
+}
+
+fn app_states(req: &HttpRequest) -> Result<&web::Data<AppStates>, ApiErrorType> {
+    req.app_data::<web::Data<AppStates>>().ok_or(ApiErrorType::InternalServerError)
+}
+
+/// Guard for route registration: `web::get().to(handler).wrap(require(Permission::UsersRead))`.
+/// Anonymous callers get `AuthenticationError`, callers without `permission` `AuthorizationError`.
+pub fn require(permission: Permission) -> RequirePermission {
+    RequirePermission { permission }
+}
+
+pub struct RequirePermission {
+    permission: Permission,
+}
+
+impl<S, B> Transform<S, ServiceRequest>
+    for RequirePermission
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+        B: MessageBody + 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequirePermissionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(
+            Ok(RequirePermissionMiddleware {
+                service: Rc::new(service),
+                permission: self.permission,
+            })
+        )
+    }
+}
+
+pub struct RequirePermissionMiddleware<S> {
+    service: Rc<S>,
+    permission: Permission,
+}
+
+impl<S, B> Service<ServiceRequest>
+    for RequirePermissionMiddleware<S>
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+        B: MessageBody + 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let permission = self.permission;
+        Box::pin(async move {
+            let allowed = Principal::resolve(req.request()).await.and_then(|principal|
+                principal.require(permission)
+            );
+            match allowed {
+                Ok(()) => Ok(service.call(req).await?.map_into_left_body()),
+                Err(err) => Ok(req.into_response(err.error_response()).map_into_right_body()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_http::Request;
+    use actix_web::{
+        dev::{ Service, ServiceResponse },
+        http::{ header, StatusCode },
+        middleware::from_fn,
+        test,
+        App,
+    };
+    use serde_json::json;
+
+    use crate::{
+        auth::jwt::{ jwt_auth, JwtConfig },
+        configs::settings::JwtSettings,
+        handlers::user_handler::{ delete_user, set_user_role },
+        models::role_model::Role,
+        test_support::{ create_user, memory_states },
+    };
+    use super::*;
+
+    // The user routes of `main`, with the same guards.
+    async fn app(
+        states: web::Data<AppStates>,
+        jwt: web::Data<JwtConfig>
+    ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+        test::init_service(
+            App::new()
+                .app_data(states)
+                .app_data(jwt)
+                .wrap(from_fn(jwt_auth))
+                .service(
+                    web
+                        ::resource("/user/{user}")
+                        .route(
+                            web::delete().to(delete_user).wrap(require(Permission::UsersWrite))
+                        )
+                )
+                .service(
+                    web
+                        ::resource("/user/{user}/role")
+                        .route(web::put().to(set_user_role).wrap(require(Permission::UsersAdmin)))
+                )
+        ).await
+    }
+
+    fn delete_as(jwt: &JwtConfig, caller: Option<&User>, target: &User) -> Request {
+        let request = test::TestRequest::delete().uri(&format!("/user/{}", target.id));
+        match caller {
+            Some(caller) => {
+                let token = jwt.issue(&caller.id, &caller.role.scopes()).unwrap();
+                request.insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            }
+            None => request,
+        }.to_request()
+    }
+
+    #[actix_web::test]
+    async fn viewer_may_only_modify_itself() {
+        let states = web::Data::new(memory_states());
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        let viewer = create_user(&states, "viewer", Role::Viewer).await;
+        let other = create_user(&states, "other", Role::Viewer).await;
+        let app = app(states.clone(), jwt.clone()).await;
+
+        let response = test::call_service(&app, delete_as(&jwt, Some(&viewer), &other)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(states.users.find(&UserKey::parse(other.id)).await.unwrap().is_some());
+
+        let response = test::call_service(&app, delete_as(&jwt, Some(&viewer), &viewer)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn admin_may_modify_anyone() {
+        let states = web::Data::new(memory_states());
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        let admin = create_user(&states, "admin", Role::Admin).await;
+        let other = create_user(&states, "other", Role::Viewer).await;
+        let app = app(states.clone(), jwt.clone()).await;
+
+        let response = test::call_service(&app, delete_as(&jwt, Some(&admin), &other)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(states.users.find(&UserKey::parse(other.id)).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn anonymous_caller_is_unauthenticated() {
+        let states = web::Data::new(memory_states());
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        let other = create_user(&states, "other", Role::Viewer).await;
+        let app = app(states.clone(), jwt.clone()).await;
+
+        let response = test::call_service(&app, delete_as(&jwt, None, &other)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn guard_requires_the_permission() {
+        let states = web::Data::new(memory_states());
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        let admin = create_user(&states, "admin", Role::Admin).await;
+        let editor = create_user(&states, "editor", Role::Editor).await;
+        let app = app(states.clone(), jwt.clone()).await;
+        let set_role_as = |caller: &User| {
+            let token = jwt.issue(&caller.id, &caller.role.scopes()).unwrap();
+            test::TestRequest
+                ::put()
+                .uri(&format!("/user/{}/role", editor.id))
+                .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+                .set_json(json!({ "role": "admin" }))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, set_role_as(&editor)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = test::call_service(&app, set_role_as(&admin)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
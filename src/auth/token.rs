@@ -0,0 +1,14 @@
+use rand::{ distributions::Alphanumeric, rngs::OsRng, Rng };
+use sha2::{ Digest, Sha256 };
+
+/// Generates an opaque token for refresh tokens and API keys. It is only ever returned
+/// to the client, the server stores its `hash_token`.
+pub fn generate_token() -> String {
+    OsRng.sample_iter(&Alphanumeric).take(64).map(char::from).collect()
+}
+
+/// Hex encoded SHA-256 of `token`. The tokens are random and long, so a fast hash
+/// is enough and lets the token be looked up by its hash.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
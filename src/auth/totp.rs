@@ -0,0 +1,108 @@
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use rand::{ rngs::OsRng, Rng, RngCore };
+use totp_rs::{ Algorithm, Secret, TotpUrlError, TOTP };
+
+// RFC 6238 defaults, the only parameters every authenticator app supports.
+const DIGITS: usize = 6;
+const STEP_SECONDS: u64 = 30;
+// 160 bits, as recommended for HMAC-SHA1 by RFC 4226.
+const SECRET_BYTES: usize = 20;
+// Codes of the previous and the next step are accepted as well, for clock drift.
+const ALLOWED_DRIFT_STEPS: u64 = 1;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Generates a new base32 encoded TOTP secret.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut secret);
+    Secret::Raw(secret.to_vec()).to_encoded().to_string()
+}
+
+fn totp(secret: &str, issuer: &str, account: &str) -> Result<TOTP, TotpUrlError> {
+    let secret = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .map_err(|_| TotpUrlError::Secret(secret.to_owned()))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        DIGITS,
+        0,
+        STEP_SECONDS,
+        secret,
+        Some(issuer.to_owned()),
+        account.to_owned()
+    )
+}
+
+/// The `otpauth://` URL authenticator apps scan to enroll `secret`.
+pub fn provisioning_url(secret: &str, issuer: &str, account: &str) -> Result<String, TotpUrlError> {
+    Ok(totp(secret, issuer, account)?.get_url())
+}
+
+/// Checks `code` against `secret` and returns the time step it was generated for,
+/// so callers can reject a code that was already used.
+pub fn verify_code(secret: &str, code: &str) -> Option<i64> {
+    let totp = totp(secret, "", "").ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let current = now / STEP_SECONDS;
+    (current.saturating_sub(ALLOWED_DRIFT_STEPS)..=current + ALLOWED_DRIFT_STEPS)
+        .find(|step| totp.check(code, step * STEP_SECONDS))
+        .and_then(|step| i64::try_from(step).ok())
+}
+
+/// Whether `code` looks like a TOTP code rather than a recovery code.
+pub fn is_totp_code(code: &str) -> bool {
+    code.len() == DIGITS && code.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Generates one-time recovery codes like `k7hm2-xq9rd`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = (0..10)
+                .map(|_| {
+                    char::from(RECOVERY_CODE_ALPHABET[OsRng.gen_range(0..RECOVERY_CODE_ALPHABET.len())])
+                })
+                .collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared without dashes, whitespace and case.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The code an authenticator app shows for `secret` right now.
+#[cfg(test)]
+pub fn current_code(secret: &str) -> String {
+    totp(secret, "", "").unwrap().generate_current().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_code_verifies() {
+        let secret = generate_secret();
+        let code = current_code(&secret);
+        assert!(is_totp_code(&code));
+        assert!(verify_code(&secret, &code).is_some());
+        assert_eq!(verify_code(&secret, "12345"), None);
+    }
+
+    #[test]
+    fn recovery_codes_are_normalized() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|code| !is_totp_code(code)));
+        assert_eq!(normalize_recovery_code(" K7HM2-xq9rd "), "k7hm2xq9rd");
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::{ Path, PathBuf };
+
+use clap::{ builder::RangedU64ValueParser, Args, Parser, Subcommand, ValueEnum };
+
+use crate::configs::settings::ServerSettings;
+
+/// actxol API server. Without a subcommand it serves HTTP.
+#[derive(Parser)]
+#[command(name = "actxol", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the HTTP server
+    Serve(ServeArgs),
+    /// Apply the PostgreSQL migrations or create the MongoDB indexes
+    Migrate,
+    /// Load fixture users from a JSON or CSV file
+    Seed(SeedArgs),
+    /// Create a user with the admin role
+    CreateAdmin(CreateAdminArgs),
+    /// Validate the configuration without connecting to anything
+    CheckConfig,
+}
+
+#[derive(Args, Default)]
+pub struct ServeArgs {
+    /// Overrides `server.host`
+    #[arg(long)]
+    pub host: Option<String>,
+    /// Overrides `server.port`
+    #[arg(long, value_parser = RangedU64ValueParser::<u16>::new().range(1..))]
+    pub port: Option<u16>,
+    /// Overrides `server.workers`
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub workers: Option<usize>,
+}
+
+impl ServeArgs {
+    pub fn apply(self, server: &mut ServerSettings) {
+        if let Some(host) = self.host {
+            server.host = host;
+        }
+        if let Some(port) = self.port {
+            server.port = port;
+        }
+        if let Some(workers) = self.workers {
+            server.workers = workers;
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SeedFormat {
+    Json,
+    Csv,
+}
+
+impl SeedFormat {
+    /// Guesses the format from the file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "json" => Some(SeedFormat::Json),
+            "csv" => Some(SeedFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct SeedArgs {
+    /// Users with `first_name`, `last_name`, `username`, `email` and optionally `password`,
+    /// `role` and `email_verified`
+    pub file: PathBuf,
+    /// Format of the file, guessed from its extension by default
+    #[arg(long, value_enum)]
+    pub format: Option<SeedFormat>,
+}
+
+#[derive(Args)]
+pub struct CreateAdminArgs {
+    #[arg(long)]
+    pub username: String,
+    #[arg(long)]
+    pub email: String,
+    /// Read from `ADMIN_PASSWORD` when not given, so it stays out of the shell history
+    #[arg(long, env = "ADMIN_PASSWORD", hide_env_values = true)]
+    pub password: String,
+    #[arg(long, default_value = "Admin")]
+    pub first_name: String,
+    #[arg(long, default_value = "User")]
+    pub last_name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn cli_is_well_formed() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn serve_flags_override_the_settings() {
+        let cli = Cli::try_parse_from(["actxol", "serve", "--port", "9000", "--workers", "2"])
+            .unwrap();
+        let Some(Command::Serve(args)) = cli.command else {
+            panic!("expected serve");
+        };
+        let mut server = ServerSettings::default();
+        let host = server.host.clone();
+        args.apply(&mut server);
+        assert_eq!((server.host, server.port, server.workers), (host, 9000, 2));
+
+        assert!(Cli::try_parse_from(["actxol"]).unwrap().command.is_none());
+        assert!(Cli::try_parse_from(["actxol", "serve", "--port", "0"]).is_err());
+        assert!(Cli::try_parse_from(["actxol", "serve", "--workers", "0"]).is_err());
+    }
+
+    #[test]
+    fn seed_format_follows_the_extension() {
+        assert!(matches!(SeedFormat::from_path(Path::new("users.JSON")), Some(SeedFormat::Json)));
+        assert!(matches!(SeedFormat::from_path(Path::new("a/users.csv")), Some(SeedFormat::Csv)));
+        assert!(SeedFormat::from_path(Path::new("users.txt")).is_none());
+        assert!(SeedFormat::from_path(Path::new("users")).is_none());
+    }
+}
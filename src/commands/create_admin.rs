@@ -0,0 +1,73 @@
+use anyhow::{ anyhow, bail, Context };
+use validator::Validate;
+
+use crate::{
+    auth::password::hash_password,
+    configs::db::AppStates,
+    models::{ auth_model::RegisterPayload, role_model::Role, user_model::{ User, UserPayload } },
+    repositories::user_repository::RepositoryError,
+};
+use super::cli::CreateAdminArgs;
+
+/// Creates an admin that can log in right away, its email counts as verified.
+pub async fn create_admin(states: &AppStates, args: CreateAdminArgs) -> anyhow::Result<User> {
+    let CreateAdminArgs { username, email, password, first_name, last_name } = args;
+    let payload = RegisterPayload {
+        user: UserPayload { first_name, last_name, username, email },
+        password,
+    };
+    payload.validate().context("Invalid admin")?;
+
+    let password_hash = hash_password(&payload.password).map_err(|err| anyhow!("{err}"))?;
+    let mut user = User::new(payload.user);
+    user.role = Role::Admin;
+    user.email_verified = true;
+    match states.users.insert(user.clone(), Some(password_hash)).await {
+        Ok(()) => Ok(user),
+        Err(RepositoryError::DuplicateKey) => bail!("The username {} is taken", user.username),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::password::verify_password,
+        repositories::user_repository::UserKey,
+        test_support::memory_states,
+    };
+
+    fn args(username: &str, password: &str) -> CreateAdminArgs {
+        CreateAdminArgs {
+            username: username.to_owned(),
+            email: format!("{username}@example.com"),
+            password: password.to_owned(),
+            first_name: "Admin".to_owned(),
+            last_name: "User".to_owned(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn admin_can_log_in_right_away() {
+        let states = memory_states();
+        let admin = create_admin(&states, args("root", "password123")).await.unwrap();
+        assert_eq!(admin.role, Role::Admin);
+        assert!(admin.email_verified);
+        let password_hash = states.users
+            .password_hash(&UserKey::Username("root".to_owned())).await
+            .unwrap();
+        assert!(verify_password("password123", password_hash.as_deref()));
+
+        let err = create_admin(&states, args("root", "password123")).await.unwrap_err();
+        assert_eq!(err.to_string(), "The username root is taken");
+    }
+
+    #[actix_web::test]
+    async fn invalid_admin_is_rejected() {
+        let states = memory_states();
+        assert!(create_admin(&states, args("root", "short")).await.is_err());
+        assert!(create_admin(&states, args("r", "password123")).await.is_err());
+        assert_eq!(states.users.count().await.unwrap(), 0);
+    }
+}
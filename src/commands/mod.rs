@@ -0,0 +1,3 @@
+pub mod cli;
+pub mod create_admin;
+pub mod seed;
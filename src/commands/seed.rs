@@ -0,0 +1,192 @@
+use std::{ fs, path::Path };
+
+use anyhow::{ anyhow, Context };
+use log::warn;
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    auth::password::hash_password,
+    configs::db::AppStates,
+    models::{ auth_model::RegisterPayload, role_model::Role, user_model::{ User, UserPayload } },
+    repositories::user_repository::RepositoryError,
+};
+use super::cli::SeedFormat;
+
+// A fixture user, a CSV row or an element of a JSON array.
+#[derive(Deserialize)]
+struct SeedUser {
+    first_name: String,
+    last_name: String,
+    username: String,
+    email: String,
+    // users without a password log in after a password reset
+    password: Option<String>,
+    role: Option<Role>,
+    email_verified: Option<bool>,
+}
+
+impl SeedUser {
+    fn validate(&self) -> Result<(), validator::ValidationErrors> {
+        let user = UserPayload {
+            first_name: self.first_name.clone(),
+            last_name: self.last_name.clone(),
+            username: self.username.clone(),
+            email: self.email.clone(),
+        };
+        match &self.password {
+            Some(password) => RegisterPayload { user, password: password.clone() }.validate(),
+            None => user.validate(),
+        }
+    }
+}
+
+/// Inserts the users of `path` and returns how many were inserted. Every user is validated
+/// before the first insert. Taken usernames are skipped, so seeding twice is harmless.
+pub async fn seed(
+    states: &AppStates,
+    path: &Path,
+    format: Option<SeedFormat>
+) -> anyhow::Result<usize> {
+    let format = format
+        .or_else(|| SeedFormat::from_path(path))
+        .context("Unknown file extension, pass --format json or --format csv")?;
+    let users = read_users(path, format).with_context(|| format!("Reading {}", path.display()))?;
+    for (index, user) in users.iter().enumerate() {
+        user.validate().with_context(|| format!("Invalid user {} ({})", index + 1, user.username))?;
+    }
+
+    let mut inserted = 0;
+    for seed_user in users {
+        let password_hash = match &seed_user.password {
+            Some(password) => Some(hash_password(password).map_err(|err| anyhow!("{err}"))?),
+            None => None,
+        };
+        let mut user = User::new(UserPayload {
+            first_name: seed_user.first_name,
+            last_name: seed_user.last_name,
+            username: seed_user.username,
+            email: seed_user.email,
+        });
+        user.role = seed_user.role.unwrap_or_default();
+        user.email_verified = seed_user.email_verified.unwrap_or(false);
+        match states.users.insert(user.clone(), password_hash).await {
+            Ok(()) => {
+                inserted += 1;
+            }
+            Err(RepositoryError::DuplicateKey) => {
+                warn!("Skipping {}, the username is taken", user.username);
+            }
+            Err(err) => {
+                return Err(err.into());
+            }
+        }
+    }
+    Ok(inserted)
+}
+
+fn read_users(path: &Path, format: SeedFormat) -> anyhow::Result<Vec<SeedUser>> {
+    match format {
+        SeedFormat::Json => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+        SeedFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_path(path)?;
+            Ok(reader.deserialize().collect::<Result<_, _>>()?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ env, path::PathBuf };
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        auth::password::verify_password,
+        repositories::user_repository::UserKey,
+        test_support::memory_states,
+    };
+
+    // A fixture file, removed again when dropped.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(extension: &str, content: &str) -> Self {
+            let path = env::temp_dir().join(format!("actxol-seed-{}.{extension}", Uuid::new_v4()));
+            fs::write(&path, content).unwrap();
+            Fixture(path)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[actix_web::test]
+    async fn json_users_are_seeded_once() {
+        let states = memory_states();
+        let fixture = Fixture::new(
+            "json",
+            r#"[
+                { "first_name": "Ada", "last_name": "Admin", "username": "ada",
+                  "email": "ada@example.com", "password": "password123", "role": "admin",
+                  "email_verified": true },
+                { "first_name": "Bob", "last_name": "Viewer", "username": "bob",
+                  "email": "bob@example.com" }
+            ]"#
+        );
+
+        assert_eq!(seed(&states, &fixture.0, None).await.unwrap(), 2);
+        assert_eq!(seed(&states, &fixture.0, None).await.unwrap(), 0);
+
+        let key = UserKey::Username("ada".to_owned());
+        let ada = states.users.find(&key).await.unwrap().unwrap();
+        assert_eq!(ada.role, Role::Admin);
+        assert!(ada.email_verified);
+        let password_hash = states.users.password_hash(&key).await.unwrap();
+        assert!(verify_password("password123", password_hash.as_deref()));
+        let key = UserKey::Username("bob".to_owned());
+        assert_eq!(states.users.find(&key).await.unwrap().unwrap().role, Role::Viewer);
+        assert!(states.users.password_hash(&key).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn csv_users_are_seeded() {
+        let states = memory_states();
+        let fixture = Fixture::new(
+            "csv",
+            "first_name, last_name, username, email, password, role, email_verified\n\
+             Ada, Admin, ada, ada@example.com, password123, editor, true\n\
+             Bob, Viewer, bob, bob@example.com, , , \n"
+        );
+
+        assert_eq!(seed(&states, &fixture.0, None).await.unwrap(), 2);
+        let key = UserKey::Username("ada".to_owned());
+        assert_eq!(states.users.find(&key).await.unwrap().unwrap().role, Role::Editor);
+    }
+
+    #[actix_web::test]
+    async fn invalid_users_stop_the_seed_before_any_insert() {
+        let states = memory_states();
+        let fixture = Fixture::new(
+            "json",
+            r#"[
+                { "first_name": "Ada", "last_name": "Admin", "username": "ada",
+                  "email": "ada@example.com" },
+                { "first_name": "Bob", "last_name": "Viewer", "username": "bob",
+                  "email": "not an email" }
+            ]"#
+        );
+
+        let err = seed(&states, &fixture.0, None).await.unwrap_err();
+        assert!(err.to_string().starts_with("Invalid user 2 (bob)"), "{err}");
+        assert_eq!(states.users.count().await.unwrap(), 0);
+
+        let unknown = Fixture::new("txt", "[]");
+        assert!(seed(&states, &unknown.0, None).await.is_err());
+        assert_eq!(seed(&states, &unknown.0, Some(SeedFormat::Json)).await.unwrap(), 0);
+    }
+}
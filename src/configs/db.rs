@@ -1,39 +1,207 @@
-use log::error;
-use mongodb::{ Client, Database };
-use std::borrow::ToOwned;
-use std::env;
-use postgres::{ Client as ClientPos, NoTls };
+use actix_web::rt::time::sleep;
+use anyhow::{ anyhow, Context };
+use log::warn;
+use mongodb::{ bson::doc, event::EventHandler, options::ClientOptions, Client, Database };
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::{ atomic::{ AtomicBool, Ordering }, Arc };
+use std::time::Duration;
+use deadpool_postgres::{ Config as PoolConfig, CreatePoolError, Pool, PoolConfig as PoolSize, Runtime };
+use tokio_postgres::NoTls;
+
+use crate::{
+    repositories::{
+        api_key_repository::ApiKeyRepository,
+        refresh_token_repository::RefreshTokenRepository,
+        two_factor_repository::TwoFactorRepository,
+        used_token_repository::UsedTokenRepository,
+        user_repository::UserRepository,
+    },
+    sessions::session_repository::SessionRepository,
+    telemetry::metrics::METRICS,
+};
+use super::settings::{ DatabaseSettings, MongoSettings };
+
+// Upper bound of the wait between connection attempts.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
 
 pub struct AppStates {
-    // pub postgres_db: ClientPos,
-    // pub client_mongo: Client,
-    pub mongo_db: Database,
+    pub users: Arc<dyn UserRepository>,
+    pub refresh_tokens: Arc<dyn RefreshTokenRepository>,
+    pub api_keys: Arc<dyn ApiKeyRepository>,
+    pub two_factor: Arc<dyn TwoFactorRepository>,
+    pub used_tokens: Arc<dyn UsedTokenRepository>,
+    // only with `SESSION_BACKEND=mongo`, set in `main`
+    pub sessions: Option<Arc<dyn SessionRepository>>,
+    // when MongoDB is used for data or sessions
+    pub mongo: Option<Database>,
+    pub postgres: Option<Pool>,
+    pub db_status: Arc<DbStatus>,
 }
 
-// MongoDB initialize function.
-// Get DB connection url from environment file and connect.
-pub async fn init() -> Client {
-    let uri = match env::var("MONGODB_URI") {
-        Ok(uri) => uri,
-        Err(_) => {
-            error!("Error loading env info for MongoDB connection");
-            "Error loading env variables to connect to MongoDB".to_owned()
+/// Whether the database is connected and set up. Stays false while a server
+/// started in degraded mode waits for MongoDB.
+#[derive(Default)]
+pub struct DbStatus {
+    ready: AtomicBool,
+}
+
+impl DbStatus {
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+}
+
+// Storage backend for users, `database.backend` in the settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbBackend {
+    #[serde(alias = "mongodb")]
+    Mongo,
+    #[serde(alias = "postgresql")]
+    Postgres,
+    Memory,
+}
+
+impl FromStr for DbBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "mongo" | "mongodb" => Ok(DbBackend::Mongo),
+            "postgres" | "postgresql" => Ok(DbBackend::Postgres),
+            "memory" => Ok(DbBackend::Memory),
+            other => Err(format!("unknown backend `{other}`, expected mongo, postgres or memory")),
         }
-    };
+    }
+}
 
-    // panic if not able to connect to DB.
-    let client = Client::with_uri_str(uri).await.expect("Error connecting to backend database");
-    client
+// MongoDB initialize function.
+// Builds a client for `database.mongodb_uri` with the `database.mongo` settings.
+// The driver connects on first use, see `wait_for_mongo`.
+pub async fn init(settings: &DatabaseSettings) -> anyhow::Result<Client> {
+    let uri = settings.mongodb_uri
+        .as_deref()
+        .ok_or_else(|| anyhow!("database.mongodb_uri (MONGODB_URI) is required to use MongoDB"))?;
+    let mut options = ClientOptions::parse(uri).await.context("Invalid MongoDB URI")?;
+
+    let mongo = &settings.mongo;
+    options.app_name = Some(mongo.app_name.clone());
+    options.connect_timeout = Some(Duration::from_secs(mongo.connect_timeout_seconds));
+    options.server_selection_timeout = Some(
+        Duration::from_secs(mongo.server_selection_timeout_seconds)
+    );
+    if mongo.min_pool_size.is_some() {
+        options.min_pool_size = mongo.min_pool_size;
+    }
+    if mongo.max_pool_size.is_some() {
+        options.max_pool_size = mongo.max_pool_size;
+    }
+    options.command_event_handler = Some(
+        EventHandler::callback(|event| METRICS.observe_mongo_command(event))
+    );
+    Ok(Client::with_options(options)?)
 }
 
-pub async fn connect() -> Result<postgres::Client, postgres::Error> {
-    let uri = match env::var("POSGRES_URI") {
-        Ok(uri) => uri,
-        Err(_) => {
-            error!("Error loading env info for PostgresDB connection");
-            "Error loading env variables to connect to PostgresDB".to_owned()
+/// Pings MongoDB until it answers. Waits `retry_backoff_seconds` after the first failure and
+/// twice as long after every further one, for at most `startup_retries` retries.
+pub async fn wait_for_mongo(
+    client: &Client,
+    settings: &MongoSettings
+) -> mongodb::error::Result<()> {
+    let mut backoff = Duration::from_secs(settings.retry_backoff_seconds);
+    let mut retries = 0;
+    loop {
+        match ping_mongo(client).await {
+            Ok(()) => {
+                return Ok(());
+            }
+            Err(err) if retries < settings.startup_retries => {
+                retries += 1;
+                warn!(
+                    "MongoDB is not reachable, retry {} of {} in {}s: {}",
+                    retries,
+                    settings.startup_retries,
+                    backoff.as_secs(),
+                    err
+                );
+                sleep(backoff).await;
+                backoff = next_backoff(backoff);
+            }
+            Err(err) => {
+                return Err(err);
+            }
         }
-    };
-    let client = ClientPos::connect(&uri, NoTls);
-    client
+    }
+}
+
+pub async fn ping_mongo(client: &Client) -> mongodb::error::Result<()> {
+    client.database("admin").run_command(doc! { "ping": 1 }).await?;
+    Ok(())
+}
+
+/// Runs a trivial query on a pooled connection.
+pub async fn ping_postgres(pool: &Pool) -> anyhow::Result<()> {
+    pool.get().await?.simple_query("SELECT 1").await?;
+    Ok(())
+}
+
+/// Doubles the wait between connection attempts, up to 30 seconds.
+pub fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(MAX_RETRY_BACKOFF)
+}
+
+// PostgreSQL pool function.
+// Connections are opened lazily, so this only fails on an invalid configuration.
+pub fn connect(settings: &DatabaseSettings) -> Result<Pool, CreatePoolError> {
+    let mut config = PoolConfig::new();
+    config.url = settings.postgres_uri.clone();
+    config.pool = Some(PoolSize::new(settings.postgres_pool_size));
+    config.create_pool(Some(Runtime::Tokio1), NoTls)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(next_backoff(Duration::from_secs(1)), Duration::from_secs(2));
+        assert_eq!(next_backoff(Duration::from_secs(20)), MAX_RETRY_BACKOFF);
+        assert_eq!(next_backoff(MAX_RETRY_BACKOFF), MAX_RETRY_BACKOFF);
+    }
+
+    #[actix_web::test]
+    async fn unreachable_mongo_fails_after_the_retries() {
+        let settings = DatabaseSettings {
+            // nothing listens on the discard port
+            mongodb_uri: Some("mongodb://127.0.0.1:9/?directConnection=true".to_owned()),
+            mongo: MongoSettings {
+                connect_timeout_seconds: 1,
+                server_selection_timeout_seconds: 1,
+                startup_retries: 1,
+                retry_backoff_seconds: 1,
+                ..MongoSettings::default()
+            },
+            ..DatabaseSettings::default()
+        };
+        let client = init(&settings).await.unwrap();
+
+        let started = Instant::now();
+        assert!(wait_for_mongo(&client, &settings.mongo).await.is_err());
+        // two pings of a second each and the backoff in between
+        assert!(started.elapsed() >= Duration::from_secs(3), "{:?}", started.elapsed());
+    }
+
+    #[actix_web::test]
+    async fn mongo_is_required_for_init() {
+        let settings = DatabaseSettings { mongodb_uri: None, ..DatabaseSettings::default() };
+        assert!(init(&settings).await.is_err());
+    }
 }
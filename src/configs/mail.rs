@@ -0,0 +1,71 @@
+use std::{ str::FromStr, sync::Arc };
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::mailers::{ file_mailer::FileMailer, mailer::Mailer, smtp_mailer::SmtpMailer };
+use super::settings::MailSettings;
+
+// Mail delivery, selected with `mail.mailer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailerBackend {
+    Smtp,
+    File,
+}
+
+impl FromStr for MailerBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "smtp" => Ok(MailerBackend::Smtp),
+            "file" => Ok(MailerBackend::File),
+            other => Err(format!("unknown mailer `{other}`, expected smtp or file")),
+        }
+    }
+}
+
+/// Settings for the mails sent to verify email addresses and reset passwords.
+///
+/// Links in the mails point to `mail.base_url`, the page there posts the token back to the API.
+/// With `mail.require_verified_email` users can't log in before they verified their address.
+pub struct MailConfig {
+    pub base_url: String,
+    pub verification_expiry_seconds: i64,
+    pub reset_expiry_seconds: i64,
+    pub require_verified_email: bool,
+}
+
+impl MailConfig {
+    pub fn new(settings: &MailSettings) -> Self {
+        MailConfig {
+            base_url: settings.base_url.trim_end_matches('/').to_owned(),
+            verification_expiry_seconds: settings.verification_expiry_seconds,
+            reset_expiry_seconds: settings.reset_expiry_seconds,
+            require_verified_email: settings.require_verified_email,
+        }
+    }
+
+    /// Link to `path` below `mail.base_url` carrying `token`.
+    pub fn link(&self, path: &str, token: &str) -> String {
+        format!("{}/{}?token={}", self.base_url, path, token)
+    }
+}
+
+/// Builds the mailer selected with `mail.mailer`.
+pub fn init_mailer(settings: &MailSettings) -> anyhow::Result<Arc<dyn Mailer>> {
+    let from = settings.from.clone();
+    match settings.mailer {
+        MailerBackend::Smtp => {
+            let url = settings.smtp_url
+                .as_deref()
+                .context("mail.smtp_url (SMTP_URL) is required with the smtp mailer")?;
+            Ok(Arc::new(SmtpMailer::new(url, from).context("Error configuring SMTP")?))
+        }
+        MailerBackend::File => {
+            let dir = &settings.outbox_dir;
+            Ok(Arc::new(FileMailer::new(dir, from).context("Error creating mail outbox")?))
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::bail;
+use deadpool_postgres::Pool;
+use log::info;
+
+use crate::repositories::user_repository::RepositoryError;
+
+/// Versioned SQL migrations, applied in order. Never edit one that has been released,
+/// add a new version instead.
+const MIGRATIONS: &[(i32, &str, &str)] = &[
+    (1, "create_users", include_str!("../../migrations/0001_create_users.sql")),
+    (
+        2,
+        "add_user_id_and_timestamps",
+        include_str!("../../migrations/0002_add_user_id_and_timestamps.sql"),
+    ),
+    (3, "add_user_password_hash", include_str!("../../migrations/0003_add_user_password_hash.sql")),
+    (4, "create_refresh_tokens", include_str!("../../migrations/0004_create_refresh_tokens.sql")),
+    (5, "add_user_role", include_str!("../../migrations/0005_add_user_role.sql")),
+    (6, "create_api_keys", include_str!("../../migrations/0006_create_api_keys.sql")),
+    (
+        7,
+        "create_user_two_factor",
+        include_str!("../../migrations/0007_create_user_two_factor.sql"),
+    ),
+    (
+        8,
+        "add_user_email_verified",
+        include_str!("../../migrations/0008_add_user_email_verified.sql"),
+    ),
+    (9, "create_used_tokens", include_str!("../../migrations/0009_create_used_tokens.sql")),
+    (
+        10,
+        "add_two_factor_failed_attempts",
+        include_str!("../../migrations/0010_add_two_factor_failed_attempts.sql"),
+    ),
+    (
+        11,
+        "add_two_factor_locked_until",
+        include_str!("../../migrations/0011_add_two_factor_locked_until.sql"),
+    ),
+];
+
+// Arbitrary key for the advisory lock that keeps concurrent instances from migrating twice.
+const MIGRATION_LOCK_KEY: i64 = 0x6163_7478_6f6c;
+
+const CREATE_MIGRATIONS_TABLE: &str =
+    "CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    )";
+
+/// Applies every migration that is not recorded in `schema_migrations` yet.
+/// Returns the number of migrations applied.
+pub async fn run_migrations(pool: &Pool) -> Result<usize, RepositoryError> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    transaction.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY]).await?;
+    transaction.batch_execute(CREATE_MIGRATIONS_TABLE).await?;
+
+    let current: i32 = transaction
+        .query_one("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", &[]).await?
+        .get(0);
+
+    let mut applied = 0;
+    for (version, name, sql) in MIGRATIONS.iter().filter(|(version, ..)| *version > current) {
+        info!("Applying migration {version} {name}");
+        transaction.batch_execute(sql).await?;
+        transaction.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+            &[version, name]
+        ).await?;
+        applied += 1;
+    }
+    transaction.commit().await?;
+    Ok(applied)
+}
+
+/// Fails unless the latest of the `MIGRATIONS` is applied, e.g. because the server was
+/// started with `migrate_on_startup` off and `actxol migrate` was not run yet.
+pub async fn check_migrations(pool: &Pool) -> anyhow::Result<()> {
+    let client = pool.get().await?;
+    let exists: bool = client
+        .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[]).await?
+        .get(0);
+    let current: i32 = if exists {
+        client
+            .query_one("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", &[]).await?
+            .get(0)
+    } else {
+        0
+    };
+    let latest = MIGRATIONS.last().map_or(0, |(version, ..)| *version);
+    if current < latest {
+        bail!("Migrated to version {current} of {latest}, run `actxol migrate`");
+    }
+    Ok(())
+}
@@ -1 +1,5 @@
 pub mod db;
+pub mod migrations;
+pub mod mail;
+pub mod session;
+pub mod settings;
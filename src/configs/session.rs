@@ -0,0 +1,147 @@
+use std::str::FromStr;
+
+use actix_session::{
+    config::{ BrowserSession, PersistentSession, SessionLifecycle },
+    storage::SessionStore,
+    SessionMiddleware,
+};
+use actix_web::cookie::{ time::Duration, Key, SameSite };
+use log::warn;
+use serde::Deserialize;
+
+use super::settings::SessionSettings;
+
+// Session storage, selected with `session.backend`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionBackend {
+    // the whole session state lives in the encrypted cookie
+    Cookie,
+    // the cookie only holds a session key, the state is kept in MongoDB
+    #[serde(alias = "mongodb")]
+    Mongo,
+}
+
+impl FromStr for SessionBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "cookie" => Ok(SessionBackend::Cookie),
+            "mongo" | "mongodb" => Ok(SessionBackend::Mongo),
+            other => Err(format!("unknown session backend `{other}`, expected cookie or mongo")),
+        }
+    }
+}
+
+// `SameSite` attribute of the session cookie.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSitePolicy {
+    Lax,
+    Strict,
+    None,
+}
+
+impl FromStr for SameSitePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "lax" => Ok(SameSitePolicy::Lax),
+            "strict" => Ok(SameSitePolicy::Strict),
+            "none" => Ok(SameSitePolicy::None),
+            other => {
+                Err(format!("unknown SameSite policy `{other}`, expected lax, strict or none"))
+            }
+        }
+    }
+}
+
+impl From<SameSitePolicy> for SameSite {
+    fn from(policy: SameSitePolicy) -> Self {
+        match policy {
+            SameSitePolicy::Lax => SameSite::Lax,
+            SameSitePolicy::Strict => SameSite::Strict,
+            SameSitePolicy::None => SameSite::None,
+        }
+    }
+}
+
+/// Session cookie settings.
+///
+/// `SESSION_KEYS` holds comma separated `kid:secret` pairs of at least 32 bytes and
+/// `session.active_kid` picks the one that encrypts cookies. Cookies encrypted with the other
+/// keys are still accepted, so keys can be rotated like the `JWT_KEYS`.
+/// `session.max_age_seconds` makes the cookie persistent, otherwise it ends with the browser
+/// session and the server keeps its state for a day.
+pub struct SessionConfig {
+    pub backend: SessionBackend,
+    pub cookie_name: String,
+    pub secure: bool,
+    pub same_site: SameSite,
+    pub domain: Option<String>,
+    pub max_age: Option<Duration>,
+    pub active_key: Key,
+    // keys that are still accepted but no longer used for new cookies
+    pub previous_keys: Vec<Key>,
+}
+
+impl SessionConfig {
+    /// Builds the config from validated `settings`.
+    pub fn new(settings: &SessionSettings) -> Self {
+        let (active_key, previous_keys) = if settings.keys.is_empty() {
+            // NOTE: cookies encrypted with a random key are invalidated on restart,
+            // `Settings::validate` allows it outside the prod profile only.
+            warn!("SESSION_KEYS not set, encrypting session cookies with a random key");
+            (Key::generate(), Vec::new())
+        } else {
+            let active_kid = settings.active_kid
+                .as_deref()
+                .or_else(|| settings.keys.iter().next().map(|(kid, _)| kid))
+                .unwrap_or_default();
+            let active = settings.keys
+                .get(active_kid)
+                .expect("session.active_kid is checked by Settings::validate");
+            (
+                Key::derive_from(active.as_bytes()),
+                settings.keys
+                    .iter()
+                    .filter(|(kid, _)| *kid != active_kid)
+                    .map(|(_, secret)| Key::derive_from(secret.as_bytes()))
+                    .collect(),
+            )
+        };
+
+        let same_site = settings.cookie_same_site.into();
+        if same_site == SameSite::None && !settings.cookie_secure {
+            warn!("Browsers reject SameSite=None session cookies that are not secure");
+        }
+
+        SessionConfig {
+            backend: settings.backend,
+            cookie_name: settings.cookie_name.clone(),
+            secure: settings.cookie_secure,
+            same_site,
+            domain: settings.cookie_domain.clone().filter(|domain| !domain.is_empty()),
+            max_age: settings.max_age_seconds.map(Duration::seconds),
+            active_key,
+            previous_keys,
+        }
+    }
+
+    /// Builds the session middleware for `store` with these cookie settings.
+    pub fn middleware<Store: SessionStore>(&self, store: Store) -> SessionMiddleware<Store> {
+        let lifecycle: SessionLifecycle = match self.max_age {
+            Some(max_age) => PersistentSession::default().session_ttl(max_age).into(),
+            None => BrowserSession::default().into(),
+        };
+        SessionMiddleware::builder(store, self.active_key.clone())
+            .cookie_name(self.cookie_name.clone())
+            .cookie_secure(self.secure)
+            .cookie_same_site(self.same_site)
+            .cookie_domain(self.domain.clone())
+            .session_lifecycle(lifecycle)
+            .build()
+    }
+}
@@ -0,0 +1,978 @@
+use std::{ collections::HashMap, env, fmt, fs, io, path::{ Path, PathBuf }, str::FromStr };
+
+use lettre::message::Mailbox;
+use serde::{ de, Deserialize, Deserializer };
+use toml::{ Table, Value };
+
+use crate::models::error_model::ErrorFormat;
+use super::{ db::DbBackend, mail::MailerBackend, session::{ SameSitePolicy, SessionBackend } };
+
+/// Deployment profile, selected with `APP_PROFILE`. `config/<profile>.toml` is layered
+/// over `config/default.toml`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Profile {
+    #[default]
+    Dev,
+    Test,
+    Prod,
+}
+
+impl Profile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Profile::Dev => "dev",
+            Profile::Test => "test",
+            Profile::Prod => "prod",
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "dev" | "development" => Ok(Profile::Dev),
+            "test" => Ok(Profile::Test),
+            "prod" | "production" => Ok(Profile::Prod),
+            other => Err(format!("unknown profile `{other}`, expected dev, test or prod")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: u16,
+    pub workers: usize,
+    // returned by `/welcome`
+    pub static_path: String,
+    // `problem` renders every error as RFC 7807 problem details
+    pub error_format: ErrorFormat,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            host: "127.0.0.1".to_owned(),
+            port: 8080,
+            workers: 2,
+            static_path: "static/welcome.html".to_owned(),
+            error_format: ErrorFormat::Json,
+        }
+    }
+}
+
+/// Format of the log lines, `text` for people or `json` for log collectors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format `{other}`, expected text or json")),
+        }
+    }
+}
+
+// The levels are set with `RUST_LOG`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSettings {
+    pub format: LogFormat,
+}
+
+/// Where finished spans go. `none` turns tracing off.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceExporter {
+    #[default]
+    None,
+    Otlp,
+    Stdout,
+    File,
+}
+
+impl FromStr for TraceExporter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "none" => Ok(TraceExporter::None),
+            "otlp" => Ok(TraceExporter::Otlp),
+            "stdout" => Ok(TraceExporter::Stdout),
+            "file" => Ok(TraceExporter::File),
+            other => {
+                Err(format!("unknown exporter `{other}`, expected none, otlp, stdout or file"))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingSettings {
+    pub exporter: TraceExporter,
+    // the OTLP/HTTP traces endpoint, with its `/v1/traces` path
+    pub otlp_endpoint: String,
+    // spans are appended as JSON lines with the `file` exporter
+    pub file: PathBuf,
+    pub service_name: String,
+    // share of new traces that are recorded, requests with a sampled parent always are
+    pub sample_ratio: f64,
+}
+
+impl Default for TracingSettings {
+    fn default() -> Self {
+        TracingSettings {
+            exporter: TraceExporter::None,
+            otlp_endpoint: "http://localhost:4318/v1/traces".to_owned(),
+            file: PathBuf::from("traces.jsonl"),
+            service_name: "actxol".to_owned(),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MongoSettings {
+    pub app_name: String,
+    // the driver's defaults, or the URI's options, when unset
+    pub min_pool_size: Option<u32>,
+    pub max_pool_size: Option<u32>,
+    pub connect_timeout_seconds: u64,
+    pub server_selection_timeout_seconds: u64,
+    // retries at startup, waiting `retry_backoff_seconds` doubled after every failure
+    pub startup_retries: u32,
+    pub retry_backoff_seconds: u64,
+    // start without MongoDB and keep connecting in the background
+    pub degraded_start: bool,
+}
+
+impl Default for MongoSettings {
+    fn default() -> Self {
+        MongoSettings {
+            app_name: "actxol".to_owned(),
+            min_pool_size: None,
+            max_pool_size: None,
+            connect_timeout_seconds: 10,
+            server_selection_timeout_seconds: 10,
+            startup_retries: 5,
+            retry_backoff_seconds: 1,
+            degraded_start: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSettings {
+    pub backend: DbBackend,
+    pub mongodb_uri: Option<String>,
+    pub name: String,
+    pub users_collection: String,
+    pub mongo: MongoSettings,
+    pub postgres_uri: Option<String>,
+    pub postgres_pool_size: usize,
+    pub migrate_on_startup: bool,
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        DatabaseSettings {
+            backend: DbBackend::Mongo,
+            mongodb_uri: None,
+            name: "myApp".to_owned(),
+            users_collection: "users".to_owned(),
+            mongo: MongoSettings::default(),
+            postgres_uri: None,
+            postgres_pool_size: 16,
+            migrate_on_startup: true,
+        }
+    }
+}
+
+/// Comma separated `kid:secret` pairs of signing or encryption keys, so keys can be rotated.
+/// Only the kids show up in `Debug`.
+#[derive(Clone, Default)]
+pub struct KeySet(Vec<(String, String)>);
+
+impl KeySet {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(kid, secret)| (kid.as_str(), secret.as_str()))
+    }
+
+    pub fn get(&self, kid: &str) -> Option<&str> {
+        self.iter().find_map(|(key_kid, secret)| (key_kid == kid).then_some(secret))
+    }
+}
+
+impl FromStr for KeySet {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut keys: Vec<(String, String)> = Vec::new();
+        for pair in value.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (kid, secret) = match pair.split_once(':') {
+                Some((kid, secret)) if !kid.trim().is_empty() && !secret.trim().is_empty() => {
+                    (kid.trim(), secret.trim())
+                }
+                // the pair is not echoed, it holds a secret
+                _ => {
+                    return Err("expected comma separated kid:secret pairs".to_owned());
+                }
+            };
+            if keys.iter().any(|(existing, _)| existing == kid) {
+                return Err(format!("kid `{kid}` is used twice"));
+            }
+            keys.push((kid.to_owned(), secret.to_owned()));
+        }
+        Ok(KeySet(keys))
+    }
+}
+
+impl fmt::Debug for KeySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(kid, _)| kid))
+            .finish()
+    }
+}
+
+/// Access token settings, see `JwtConfig`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtSettings {
+    pub issuer: String,
+    pub audience: String,
+    pub expiry_seconds: i64,
+    pub refresh_expiry_seconds: i64,
+    // secret, only read from `JWT_KEYS`
+    #[serde(skip)]
+    pub keys: KeySet,
+    // signs new tokens, the first key when unset
+    pub active_kid: Option<String>,
+}
+
+impl Default for JwtSettings {
+    fn default() -> Self {
+        JwtSettings {
+            issuer: "actxol".to_owned(),
+            audience: "actxol".to_owned(),
+            expiry_seconds: 900,
+            refresh_expiry_seconds: 30 * 24 * 60 * 60,
+            keys: KeySet::default(),
+            active_kid: None,
+        }
+    }
+}
+
+/// Settings of the verification and password reset mails, see `MailConfig`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailSettings {
+    pub mailer: MailerBackend,
+    // required by the smtp mailer, may hold credentials
+    pub smtp_url: Option<String>,
+    // where the file mailer writes `.eml` files
+    pub outbox_dir: PathBuf,
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub from: Mailbox,
+    // links in the mails point below it
+    pub base_url: String,
+    pub verification_expiry_seconds: i64,
+    pub reset_expiry_seconds: i64,
+    pub require_verified_email: bool,
+}
+
+impl Default for MailSettings {
+    fn default() -> Self {
+        MailSettings {
+            mailer: MailerBackend::File,
+            smtp_url: None,
+            outbox_dir: PathBuf::from("mail_outbox"),
+            from: Mailbox::new(Some("actxol".to_owned()), "no-reply@localhost".parse().unwrap()),
+            base_url: "http://127.0.0.1:8080".to_owned(),
+            verification_expiry_seconds: 24 * 60 * 60,
+            reset_expiry_seconds: 30 * 60,
+            require_verified_email: false,
+        }
+    }
+}
+
+/// Session cookie settings, see `SessionConfig`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionSettings {
+    pub backend: SessionBackend,
+    // secret, only read from `SESSION_KEYS`
+    #[serde(skip)]
+    pub keys: KeySet,
+    // encrypts new cookies, the first key when unset
+    pub active_kid: Option<String>,
+    pub cookie_name: String,
+    // browsers drop secure cookies on plain http, set it to false for local development
+    pub cookie_secure: bool,
+    pub cookie_same_site: SameSitePolicy,
+    pub cookie_domain: Option<String>,
+    // makes the cookie persistent, otherwise it ends with the browser session
+    pub max_age_seconds: Option<i64>,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        SessionSettings {
+            backend: SessionBackend::Cookie,
+            keys: KeySet::default(),
+            active_kid: None,
+            cookie_name: "id".to_owned(),
+            cookie_secure: true,
+            cookie_same_site: SameSitePolicy::Lax,
+            cookie_domain: None,
+            max_age_seconds: None,
+        }
+    }
+}
+
+/// Application settings, shared with the handlers as `web::Data<Settings>`.
+///
+/// Loaded from `config/default.toml`, then `config/<profile>.toml`, then the environment
+/// variables listed in `apply_env`. Every layer is optional, missing values keep their defaults.
+/// `APP_CONFIG_DIR` moves the `config` directory.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    #[serde(skip)]
+    pub profile: Profile,
+    // the files the settings were read from, logged once the logger is set up
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+    pub server: ServerSettings,
+    pub database: DatabaseSettings,
+    pub logging: LoggingSettings,
+    pub tracing: TracingSettings,
+    pub jwt: JwtSettings,
+    pub mail: MailSettings,
+    pub session: SessionSettings,
+}
+
+/// Everything wrong with the configuration, so it can be fixed in one go.
+#[derive(Debug)]
+pub struct SettingsError {
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for error in &self.errors {
+            write!(f, "\n  - {error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl Settings {
+    /// Loads and validates the settings of the `APP_PROFILE` profile.
+    pub fn load() -> Result<Self, SettingsError> {
+        // variables that aren't unicode are left out, as if they were not set
+        let vars = env
+            ::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect();
+        Self::load_from(&vars)
+    }
+
+    // `load` with the environment variables in `vars`.
+    fn load_from(vars: &HashMap<String, String>) -> Result<Self, SettingsError> {
+        let mut errors = Vec::new();
+        let profile = vars
+            .get("APP_PROFILE")
+            .filter(|profile| !profile.is_empty())
+            .map_or(Ok(Profile::default()), |profile| profile.parse())
+            .unwrap_or_else(|err| {
+                errors.push(format!("APP_PROFILE: {err}"));
+                Profile::default()
+            });
+        let dir = PathBuf::from(vars.get("APP_CONFIG_DIR").map_or("config", String::as_str));
+
+        let mut table = Table::new();
+        let mut sources = Vec::new();
+        for name in ["default", profile.as_str()] {
+            let path = dir.join(format!("{name}.toml"));
+            match read_table(&path) {
+                Ok(Some(layer)) => {
+                    merge(&mut table, layer);
+                    sources.push(path);
+                }
+                Ok(None) => {}
+                Err(err) => errors.push(err),
+            }
+        }
+        let deserialized = serde_path_to_error::deserialize(Value::Table(table));
+        let mut settings = deserialized.unwrap_or_else(|err: serde_path_to_error::Error<_>| {
+            errors.push(format!("{}: {}", err.path(), err.inner().message()));
+            Settings::default()
+        });
+        settings.profile = profile;
+        settings.sources = sources;
+        settings.apply_env(&mut EnvOverrides { vars, errors: &mut errors });
+        settings.validate(&mut errors);
+
+        if errors.is_empty() {
+            Ok(settings)
+        } else {
+            Err(SettingsError { errors })
+        }
+    }
+
+    // Environment variables win over the files. The names predate the files and are kept.
+    fn apply_env(&mut self, env: &mut EnvOverrides) {
+        env.with(&mut self.server.host, "SERVER.HOST");
+        env.with(&mut self.server.port, "SERVER.PORT");
+        env.with(&mut self.server.workers, "SERVER.WORKERS");
+        env.with(&mut self.server.static_path, "PATH_STATIC");
+        env.with(&mut self.server.error_format, "ERROR_FORMAT");
+        env.with(&mut self.database.backend, "DB_BACKEND");
+        env.option(&mut self.database.mongodb_uri, "MONGODB_URI");
+        env.with(&mut self.database.name, "DB_NAME");
+        env.with(&mut self.database.users_collection, "COLL_NAME");
+        let mongo = &mut self.database.mongo;
+        env.with(&mut mongo.app_name, "MONGODB_APP_NAME");
+        env.option(&mut mongo.min_pool_size, "MONGODB_MIN_POOL_SIZE");
+        env.option(&mut mongo.max_pool_size, "MONGODB_MAX_POOL_SIZE");
+        env.with(&mut mongo.connect_timeout_seconds, "MONGODB_CONNECT_TIMEOUT_SECONDS");
+        env.with(
+            &mut mongo.server_selection_timeout_seconds,
+            "MONGODB_SERVER_SELECTION_TIMEOUT_SECONDS"
+        );
+        env.with(&mut mongo.startup_retries, "MONGODB_STARTUP_RETRIES");
+        env.with(&mut mongo.retry_backoff_seconds, "MONGODB_RETRY_BACKOFF_SECONDS");
+        env.with(&mut mongo.degraded_start, "MONGODB_DEGRADED_START");
+        env.option(&mut self.database.postgres_uri, "POSTGRES_URI");
+        env.with(&mut self.database.postgres_pool_size, "POSTGRES_POOL_SIZE");
+        env.with(&mut self.database.migrate_on_startup, "DB_MIGRATE_ON_STARTUP");
+        env.with(&mut self.logging.format, "LOG_FORMAT");
+        let tracing = &mut self.tracing;
+        env.with(&mut tracing.exporter, "TRACING_EXPORTER");
+        env.with(&mut tracing.otlp_endpoint, "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT");
+        env.with(&mut tracing.file, "TRACING_FILE");
+        env.with(&mut tracing.service_name, "OTEL_SERVICE_NAME");
+        env.with(&mut tracing.sample_ratio, "TRACING_SAMPLE_RATIO");
+        let jwt = &mut self.jwt;
+        env.with(&mut jwt.issuer, "JWT_ISSUER");
+        env.with(&mut jwt.audience, "JWT_AUDIENCE");
+        env.with(&mut jwt.expiry_seconds, "JWT_EXPIRY_SECONDS");
+        env.with(&mut jwt.refresh_expiry_seconds, "REFRESH_TOKEN_EXPIRY_SECONDS");
+        env.with(&mut jwt.keys, "JWT_KEYS");
+        env.option(&mut jwt.active_kid, "JWT_ACTIVE_KID");
+        let mail = &mut self.mail;
+        env.with(&mut mail.mailer, "MAILER");
+        env.option(&mut mail.smtp_url, "SMTP_URL");
+        env.with(&mut mail.outbox_dir, "MAIL_OUTBOX_DIR");
+        env.with(&mut mail.from, "MAIL_FROM");
+        env.with(&mut mail.base_url, "APP_BASE_URL");
+        env.with(&mut mail.verification_expiry_seconds, "EMAIL_VERIFICATION_EXPIRY_SECONDS");
+        env.with(&mut mail.reset_expiry_seconds, "PASSWORD_RESET_EXPIRY_SECONDS");
+        env.with(&mut mail.require_verified_email, "REQUIRE_VERIFIED_EMAIL");
+        let session = &mut self.session;
+        env.with(&mut session.backend, "SESSION_BACKEND");
+        env.with(&mut session.keys, "SESSION_KEYS");
+        env.option(&mut session.active_kid, "SESSION_ACTIVE_KID");
+        env.with(&mut session.cookie_name, "SESSION_COOKIE_NAME");
+        env.with(&mut session.cookie_secure, "SESSION_COOKIE_SECURE");
+        env.with(&mut session.cookie_same_site, "SESSION_COOKIE_SAME_SITE");
+        env.option(&mut session.cookie_domain, "SESSION_COOKIE_DOMAIN");
+        env.option(&mut session.max_age_seconds, "SESSION_MAX_AGE_SECONDS");
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        let Settings { profile, server, database, tracing, jwt, mail, session, .. } = self;
+        if server.host.trim().is_empty() {
+            errors.push("server.host must not be empty".to_owned());
+        }
+        if server.port == 0 {
+            errors.push("server.port must be between 1 and 65535".to_owned());
+        }
+        if server.workers == 0 {
+            errors.push("server.workers must be at least 1".to_owned());
+        }
+        if server.static_path.trim().is_empty() {
+            errors.push("server.static_path must not be empty".to_owned());
+        }
+        if let Err(err) = check_database_name(&database.name) {
+            errors.push(format!("database.name: {err}"));
+        }
+        if let Err(err) = check_collection_name(&database.name, &database.users_collection) {
+            errors.push(format!("database.users_collection: {err}"));
+        }
+        let mongo = &database.mongo;
+        if mongo.app_name.trim().is_empty() {
+            errors.push("database.mongo.app_name must not be empty".to_owned());
+        }
+        if mongo.max_pool_size == Some(0) {
+            errors.push("database.mongo.max_pool_size must be at least 1".to_owned());
+        }
+        if let (Some(min), Some(max)) = (mongo.min_pool_size, mongo.max_pool_size) {
+            if min > max {
+                errors.push(
+                    "database.mongo.min_pool_size must not exceed max_pool_size".to_owned()
+                );
+            }
+        }
+        if mongo.connect_timeout_seconds == 0 {
+            errors.push("database.mongo.connect_timeout_seconds must be at least 1".to_owned());
+        }
+        if mongo.server_selection_timeout_seconds == 0 {
+            errors.push(
+                "database.mongo.server_selection_timeout_seconds must be at least 1".to_owned()
+            );
+        }
+        if mongo.retry_backoff_seconds == 0 {
+            errors.push("database.mongo.retry_backoff_seconds must be at least 1".to_owned());
+        }
+        if database.postgres_pool_size == 0 {
+            errors.push("database.postgres_pool_size must be at least 1".to_owned());
+        }
+
+        match (database.backend, &database.mongodb_uri, &database.postgres_uri) {
+            (DbBackend::Mongo, None, _) => {
+                errors.push(
+                    "database.mongodb_uri (MONGODB_URI) is required for MongoDB".to_owned()
+                );
+            }
+            (DbBackend::Postgres, _, None) => {
+                errors.push(
+                    "database.postgres_uri (POSTGRES_URI) is required for PostgreSQL".to_owned()
+                );
+            }
+            _ => {}
+        }
+        if let Some(uri) = &database.mongodb_uri {
+            if !uri.starts_with("mongodb://") && !uri.starts_with("mongodb+srv://") {
+                errors.push(
+                    "database.mongodb_uri must start with mongodb:// or mongodb+srv://".to_owned()
+                );
+            }
+        }
+        if let Some(uri) = &database.postgres_uri {
+            if let Err(err) = tokio_postgres::Config::from_str(uri) {
+                errors.push(format!("database.postgres_uri: {err}"));
+            }
+        }
+
+        let endpoint = &tracing.otlp_endpoint;
+        if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+            errors.push("tracing.otlp_endpoint must start with http:// or https://".to_owned());
+        }
+        if tracing.file.as_os_str().is_empty() {
+            errors.push("tracing.file must not be empty".to_owned());
+        }
+        if tracing.service_name.trim().is_empty() {
+            errors.push("tracing.service_name must not be empty".to_owned());
+        }
+        if !(0.0..=1.0).contains(&tracing.sample_ratio) {
+            errors.push("tracing.sample_ratio must be between 0 and 1".to_owned());
+        }
+
+        if jwt.issuer.trim().is_empty() {
+            errors.push("jwt.issuer must not be empty".to_owned());
+        }
+        if jwt.audience.trim().is_empty() {
+            errors.push("jwt.audience must not be empty".to_owned());
+        }
+        if jwt.expiry_seconds < 1 {
+            errors.push("jwt.expiry_seconds must be at least 1".to_owned());
+        }
+        if jwt.refresh_expiry_seconds < 1 {
+            errors.push("jwt.refresh_expiry_seconds must be at least 1".to_owned());
+        }
+        if let Err(err) = check_active_kid(&jwt.keys, jwt.active_kid.as_deref()) {
+            errors.push(format!("jwt.active_kid (JWT_ACTIVE_KID): {err} of the JWT_KEYS"));
+        }
+        // a random key would log everyone out on restart and differ between replicas
+        if *profile == Profile::Prod && jwt.keys.is_empty() {
+            errors.push("JWT_KEYS is required in the prod profile".to_owned());
+        }
+
+        if mail.mailer == MailerBackend::Smtp && mail.smtp_url.is_none() {
+            errors.push("mail.smtp_url (SMTP_URL) is required with the smtp mailer".to_owned());
+        }
+        if mail.outbox_dir.as_os_str().is_empty() {
+            errors.push("mail.outbox_dir must not be empty".to_owned());
+        }
+        if !mail.base_url.starts_with("http://") && !mail.base_url.starts_with("https://") {
+            errors.push("mail.base_url must start with http:// or https://".to_owned());
+        }
+        if mail.verification_expiry_seconds < 1 {
+            errors.push("mail.verification_expiry_seconds must be at least 1".to_owned());
+        }
+        if mail.reset_expiry_seconds < 1 {
+            errors.push("mail.reset_expiry_seconds must be at least 1".to_owned());
+        }
+
+        for (kid, secret) in session.keys.iter() {
+            if secret.len() < 32 {
+                errors.push(format!("SESSION_KEYS: key `{kid}` is shorter than 32 bytes"));
+            }
+        }
+        if let Err(err) = check_active_kid(&session.keys, session.active_kid.as_deref()) {
+            errors.push(
+                format!("session.active_kid (SESSION_ACTIVE_KID): {err} of the SESSION_KEYS")
+            );
+        }
+        if *profile == Profile::Prod && session.keys.is_empty() {
+            errors.push("SESSION_KEYS is required in the prod profile".to_owned());
+        }
+        if session.cookie_name.trim().is_empty() {
+            errors.push("session.cookie_name must not be empty".to_owned());
+        }
+        if session.max_age_seconds.is_some_and(|seconds| seconds < 1) {
+            errors.push("session.max_age_seconds must be at least 1".to_owned());
+        }
+    }
+}
+
+// An active kid has to name one of the keys.
+fn check_active_kid(keys: &KeySet, active_kid: Option<&str>) -> Result<(), String> {
+    match active_kid {
+        Some(kid) if keys.get(kid).is_none() => Err(format!("`{kid}` is not one")),
+        _ => Ok(()),
+    }
+}
+
+// MongoDB database names are 1 to 63 bytes without `/\. "$*<>:|?` or null characters.
+fn check_database_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("must not be empty".to_owned());
+    }
+    if name.len() > 63 {
+        return Err(format!("`{name}` is longer than 63 bytes"));
+    }
+    match name.chars().find(|c| "/\\. \"$*<>:|?\0".contains(*c)) {
+        Some(c) => Err(format!("`{name}` must not contain {c:?}")),
+        None => Ok(()),
+    }
+}
+
+// MongoDB collection names start with a letter or `_`, don't contain `$` or null characters
+// and don't start with `system.`. `<database>.<collection>` is at most 255 bytes.
+fn check_collection_name(database: &str, name: &str) -> Result<(), String> {
+    if !name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        return Err(format!("`{name}` must start with a letter or an underscore"));
+    }
+    if let Some(c) = name.chars().find(|c| *c == '$' || *c == '\0') {
+        return Err(format!("`{name}` must not contain {c:?}"));
+    }
+    if name.starts_with("system.") {
+        return Err(format!("`{name}` uses the reserved `system.` prefix"));
+    }
+    if database.len() + 1 + name.len() > 255 {
+        return Err(format!("`{database}.{name}` is longer than 255 bytes"));
+    }
+    Ok(())
+}
+
+// Reads a TOML file, `None` if it doesn't exist.
+fn read_table(path: &Path) -> Result<Option<Table>, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(None);
+        }
+        Err(err) => {
+            return Err(format!("{}: {}", path.display(), err));
+        }
+    };
+    content
+        .parse::<Table>()
+        .map(Some)
+        .map_err(|err| {
+            let line = err.span().map_or(1, |span| content[..span.start].lines().count().max(1));
+            let message = err.message().trim().replace('\n', ", ");
+            format!("{}:{}: {}", path.display(), line, message)
+        })
+}
+
+// Merges `layer` into `base`, tables key by key.
+fn merge(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(layer)) => merge(base, layer),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+// For values the TOML files hold as strings, like mailboxes.
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where D: Deserializer<'de>, T: FromStr, T::Err: fmt::Display
+{
+    String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+}
+
+// Overrides settings with environment variables, collecting the malformed ones.
+struct EnvOverrides<'a> {
+    vars: &'a HashMap<String, String>,
+    errors: &'a mut Vec<String>,
+}
+
+impl EnvOverrides<'_> {
+    fn with<T>(&mut self, field: &mut T, var: &str) where T: FromStr, T::Err: fmt::Display {
+        if let Some(value) = self.vars.get(var) {
+            match value.trim().parse() {
+                Ok(value) => {
+                    *field = value;
+                }
+                Err(err) => self.errors.push(format!("{var}: {err}")),
+            }
+        }
+    }
+
+    // An empty variable unsets the value.
+    fn option<T>(&mut self, field: &mut Option<T>, var: &str)
+        where T: FromStr, T::Err: fmt::Display
+    {
+        if let Some(value) = self.vars.get(var) {
+            if value.trim().is_empty() {
+                *field = None;
+                return;
+            }
+            match value.trim().parse() {
+                Ok(value) => {
+                    *field = Some(value);
+                }
+                Err(err) => self.errors.push(format!("{var}: {err}")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    // A config directory holding `files`, removed again when dropped.
+    struct ConfigDir(PathBuf);
+
+    impl ConfigDir {
+        fn new(files: &[(&str, &str)]) -> Self {
+            let dir = env::temp_dir().join(format!("actxol-config-{}", Uuid::new_v4()));
+            fs::create_dir(&dir).unwrap();
+            for (name, content) in files {
+                fs::write(dir.join(name), content).unwrap();
+            }
+            ConfigDir(dir)
+        }
+
+        fn load(&self, vars: &[(&str, &str)]) -> Result<Settings, SettingsError> {
+            let mut vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            vars.insert("APP_CONFIG_DIR".to_owned(), self.0.display().to_string());
+            Settings::load_from(&vars)
+        }
+
+        fn errors(&self, vars: &[(&str, &str)]) -> Vec<String> {
+            self.load(vars).unwrap_err().errors
+        }
+    }
+
+    impl Drop for ConfigDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const MEMORY: (&str, &str) = ("DB_BACKEND", "memory");
+
+    #[test]
+    fn missing_files_keep_the_defaults() {
+        let dir = ConfigDir::new(&[]);
+        let settings = dir.load(&[MEMORY]).unwrap();
+        assert_eq!(settings.profile, Profile::Dev);
+        assert!(settings.sources.is_empty());
+        assert_eq!(settings.server.port, ServerSettings::default().port);
+    }
+
+    #[test]
+    fn profile_file_is_layered_over_default() {
+        let dir = ConfigDir::new(
+            &[
+                (
+                    "default.toml",
+                    "[server]\nport = 9000\nworkers = 3\n[database]\nbackend = \"memory\"",
+                ),
+                ("test.toml", "[server]\nport = 9001"),
+                ("dev.toml", "[server]\nport = 9002"),
+            ]
+        );
+        let settings = dir.load(&[("APP_PROFILE", "test")]).unwrap();
+        assert_eq!(settings.profile, Profile::Test);
+        assert_eq!(settings.sources.len(), 2);
+        assert_eq!((settings.server.port, settings.server.workers), (9001, 3));
+        assert_eq!(dir.load(&[]).unwrap().server.port, 9002);
+    }
+
+    #[test]
+    fn environment_wins_over_files() {
+        let dir = ConfigDir::new(
+            &[
+                (
+                    "default.toml",
+                    "[server]\nport = 9000\n[database]\nmongodb_uri = \"mongodb://db\"",
+                ),
+            ]
+        );
+        let vars = [
+            MEMORY,
+            ("SERVER.PORT", " 9100 "),
+            ("MONGODB_URI", ""),
+            ("ERROR_FORMAT", "problem"),
+        ];
+        let settings = dir.load(&vars).unwrap();
+        assert_eq!(settings.server.port, 9100);
+        assert_eq!(settings.server.error_format, ErrorFormat::Problem);
+        // an empty variable unsets an optional value
+        assert_eq!(settings.database.mongodb_uri, None);
+    }
+
+    #[test]
+    fn malformed_values_are_all_reported() {
+        let dir = ConfigDir::new(&[("default.toml", "[server]\nport_number = 1")]);
+        let errors = dir.errors(
+            &[
+                MEMORY,
+                ("APP_PROFILE", "staging"),
+                ("SERVER.PORT", "eighty"),
+                ("SESSION_COOKIE_SECURE", "sometimes"),
+            ]
+        );
+        assert_eq!(errors.len(), 4, "{errors:?}");
+        assert!(errors[0].starts_with("APP_PROFILE: unknown profile `staging`"));
+        assert!(errors[1].starts_with("server.port_number: unknown field"));
+        assert!(errors[2].starts_with("SERVER.PORT: "));
+        assert!(errors[3].starts_with("SESSION_COOKIE_SECURE: "));
+    }
+
+    #[test]
+    fn prod_requires_keys() {
+        let dir = ConfigDir::new(&[]);
+        let prod = [MEMORY, ("APP_PROFILE", "prod")];
+        assert_eq!(
+            dir.errors(&prod),
+            [
+                "JWT_KEYS is required in the prod profile",
+                "SESSION_KEYS is required in the prod profile",
+            ]
+        );
+        let session_keys = ("SESSION_KEYS", "kid:0123456789abcdef0123456789abcdef");
+        assert!(dir.load(&[prod[0], prod[1], ("JWT_KEYS", "kid:secret"), session_keys]).is_ok());
+        // the other profiles fall back to random keys
+        let settings = dir.load(&[MEMORY]).unwrap();
+        assert!(settings.jwt.keys.is_empty() && settings.session.keys.is_empty());
+    }
+
+    #[test]
+    fn validation_rejects_unusable_values() {
+        let dir = ConfigDir::new(&[]);
+        let errors = dir.errors(
+            &[
+                ("DB_BACKEND", "mongo"),
+                ("JWT_EXPIRY_SECONDS", "0"),
+                ("JWT_KEYS", "new:secret"),
+                ("JWT_ACTIVE_KID", "old"),
+                ("SESSION_KEYS", "new:short"),
+                ("MAILER", "smtp"),
+                ("COLL_NAME", "system.users"),
+            ]
+        );
+        let expected = [
+            "database.users_collection: ",
+            "database.mongodb_uri (MONGODB_URI) is required",
+            "jwt.expiry_seconds must be at least 1",
+            "jwt.active_kid (JWT_ACTIVE_KID): `old` is not one",
+            "mail.smtp_url (SMTP_URL) is required",
+            "SESSION_KEYS: key `new` is shorter than 32 bytes",
+        ];
+        assert_eq!(errors.len(), expected.len(), "{errors:?}");
+        for (error, expected) in errors.iter().zip(expected) {
+            assert!(error.starts_with(expected), "{error}");
+        }
+    }
+
+    #[test]
+    fn key_set_parses_pairs() {
+        let keys: KeySet = " old:first-secret , new:second:secret,".parse().unwrap();
+        assert_eq!(keys.get("old"), Some("first-secret"));
+        assert_eq!(keys.get("new"), Some("second:secret"));
+        assert!("".parse::<KeySet>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn key_set_rejects_malformed_pairs() {
+        for value in ["secret", "kid:", ":secret", "kid:a,kid:b"] {
+            assert!(value.parse::<KeySet>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn key_set_hides_secrets() {
+        let keys: KeySet = "kid:top-secret".parse().unwrap();
+        assert_eq!(format!("{keys:?}"), r#"["kid"]"#);
+    }
+
+    #[test]
+    fn active_kid_must_name_a_key() {
+        let keys: KeySet = "kid:secret".parse().unwrap();
+        assert!(check_active_kid(&keys, Some("kid")).is_ok());
+        assert!(check_active_kid(&keys, None).is_ok());
+        assert!(check_active_kid(&keys, Some("other")).is_err());
+        assert!(check_active_kid(&KeySet::default(), Some("kid")).is_err());
+    }
+
+    #[test]
+    fn database_names_follow_mongodb_rules() {
+        for name in ["app", "app_2024", &"d".repeat(63)] {
+            assert!(check_database_name(name).is_ok(), "{name}");
+        }
+        for name in ["", &"d".repeat(64), "my.app", "my app", "a/b", "a$b", "a\0b"] {
+            assert!(check_database_name(name).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn collection_names_follow_mongodb_rules() {
+        for name in ["users", "_users", "app.users"] {
+            assert!(check_collection_name("app", name).is_ok(), "{name}");
+        }
+        for name in ["", "1users", ".users", "us$ers", "a\0b", "system.users"] {
+            assert!(check_collection_name("app", name).is_err(), "{name}");
+        }
+        // the namespace `<database>.<collection>` is limited, not the name alone
+        assert!(check_collection_name("app", &"c".repeat(251)).is_ok());
+        assert!(check_collection_name("app", &"c".repeat(252)).is_err());
+    }
+}
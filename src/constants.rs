@@ -1,3 +1,19 @@
 // Pagination configuration.
 pub const DEFAULT_OFFSET_SIZE: u64 = 0;
 pub const DEFAULT_LIMIT_SIZE: i64 = 20;
+
+// Request headers.
+pub const API_KEY_HEADER: &str = "x-api-key";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+// also sent back in every response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Session keys.
+pub const SESSION_USER_ID: &str = "user_id";
+pub const SESSION_CSRF_TOKEN: &str = "csrf_token";
+// recorded by the `session_activity` middleware
+pub const SESSION_ID: &str = "session_id";
+pub const SESSION_CREATED_AT: &str = "created_at";
+pub const SESSION_LAST_SEEN_AT: &str = "last_seen_at";
+pub const SESSION_IP: &str = "ip";
+pub const SESSION_USER_AGENT: &str = "user_agent";
@@ -0,0 +1,290 @@
+use actix_web::{
+    error::{ Error, InternalError, PathError, QueryPayloadError, ResponseError, UrlencodedError },
+    http::StatusCode,
+    HttpRequest,
+};
+use chrono::{ SecondsFormat, Utc };
+use serde::de::DeserializeOwned;
+
+use crate::{
+    models::error_model::{ ApiError, ValidationError },
+    telemetry::logging::current_request_id,
+};
+
+// Handle query string parser errors.
+// With `T` set to the route's query type, the failing parameter is found by deserializing
+// the query string again while tracking the field path. Use `IgnoredAny` for routes
+// without a dedicated config, those only get the field when serde names it in its message.
+pub fn query_error_handler<T: DeserializeOwned>(err: QueryPayloadError, req: &HttpRequest) -> Error {
+    let de_message = match &err {
+        QueryPayloadError::Deserialize(de_err) => de_err.to_string(),
+        _ => err.to_string(),
+    };
+    let pairs = parse_pairs(req.query_string());
+    let (field, message) = failing_field::<T>(req.query_string())
+        .or_else(|| field_from_message(&de_message, &pairs).map(|field| (field, de_message.clone())))
+        .unwrap_or_else(|| ("query".to_owned(), de_message.clone()));
+    let rejected_value = rejected_value(&field, &pairs).unwrap_or_else(||
+        req.query_string().to_owned()
+    );
+    let resp = parameter_error(
+        StatusCode::BAD_REQUEST,
+        "Invalid query parameter.",
+        err.to_string(),
+        ValidationError {
+            object: "query".to_owned(),
+            field,
+            rejected_value,
+            message,
+        }
+    );
+    InternalError::from_response(err, resp).into()
+}
+
+// Handle path parameter parser errors.
+pub fn path_error_handler(err: PathError, req: &HttpRequest) -> Error {
+    let de_message = match &err {
+        PathError::Deserialize(de_err) => de_err.to_string(),
+        _ => err.to_string(),
+    };
+    let segments: Vec<(String, String)> = req
+        .match_info()
+        .iter()
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        .collect();
+    let field = field_from_message(&de_message, &segments)
+        .or_else(|| {
+            match segments.as_slice() {
+                [(name, _)] => Some(name.to_owned()),
+                _ => None,
+            }
+        })
+        .unwrap_or_else(|| "path".to_owned());
+    let rejected_value = rejected_value(&field, &segments).unwrap_or_else(||
+        req.path().to_owned()
+    );
+    let resp = parameter_error(
+        StatusCode::BAD_REQUEST,
+        "Invalid path parameter.",
+        err.to_string(),
+        ValidationError {
+            object: "path".to_owned(),
+            field,
+            rejected_value,
+            message: de_message,
+        }
+    );
+    InternalError::from_response(err, resp).into()
+}
+
+// Handle urlencoded form parser errors. The body is already consumed here,
+// so the field is only known when serde names it in its message.
+pub fn form_error_handler(err: UrlencodedError, _req: &HttpRequest) -> Error {
+    let sub_error = match &err {
+        UrlencodedError::Parse(de_err) => {
+            let field = field_from_message(&de_err.to_string(), &[]).unwrap_or_else(||
+                "form".to_owned()
+            );
+            Some(ValidationError {
+                object: "form".to_owned(),
+                field,
+                rejected_value: String::new(),
+                message: de_err.to_string(),
+            })
+        }
+        _ => None,
+    };
+    let status = err.status_code();
+    let message = match status {
+        StatusCode::BAD_REQUEST => "Invalid form field.",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "Unsupported media type",
+        StatusCode::PAYLOAD_TOO_LARGE => "Payload too large",
+        _ => "Bad request. Missing parameter and / or wrong payload.",
+    };
+    let resp = (ApiError {
+        status: status.as_u16(),
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+        message: message.to_owned(),
+        debug_message: Some(err.to_string()),
+        sub_errors: sub_error.into_iter().collect(),
+        request_id: current_request_id(),
+    }).into_response();
+    InternalError::from_response(err, resp).into()
+}
+
+fn parameter_error(
+    status: StatusCode,
+    message: &str,
+    detail: String,
+    sub_error: ValidationError
+) -> actix_web::HttpResponse {
+    (ApiError {
+        status: status.as_u16(),
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+        message: message.to_owned(),
+        debug_message: Some(detail),
+        sub_errors: vec![sub_error],
+        request_id: current_request_id(),
+    }).into_response()
+}
+
+fn parse_pairs(query: &str) -> Vec<(String, String)> {
+    form_urlencoded
+        ::parse(query.as_bytes())
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect()
+}
+
+// Deserializes `query` into `T` again, returning the failing field and the error message.
+fn failing_field<T: DeserializeOwned>(query: &str) -> Option<(String, String)> {
+    let deserializer = serde_urlencoded::Deserializer::new(
+        form_urlencoded::parse(query.as_bytes())
+    );
+    let err = serde_path_to_error::deserialize::<_, T>(deserializer).err()?;
+    let field = err.path().to_string();
+    // errors on the struct itself, like a missing field, have no field path
+    if field == "." {
+        return None;
+    }
+    Some((field, err.inner().to_string()))
+}
+
+// Picks the field out of serde messages such as "missing field `data`",
+// or finds the parameter holding the value of "unknown variant `SIDEWAYS`".
+fn field_from_message(message: &str, pairs: &[(String, String)]) -> Option<String> {
+    let quoted = message.split('`').nth(1)?;
+    if message.contains(" field `") {
+        return Some(quoted.to_owned());
+    }
+    if message.starts_with("unknown variant `") {
+        return pairs
+            .iter()
+            .find(|(_, value)| value == quoted)
+            .map(|(key, _)| key.to_owned());
+    }
+    None
+}
+
+fn rejected_value(field: &str, pairs: &[(String, String)]) -> Option<String> {
+    pairs
+        .iter()
+        .find(|(key, _)| key == field)
+        .map(|(_, value)| value.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        body::MessageBody,
+        dev::ServiceResponse,
+        http::header,
+        test,
+        web::{ self, FormConfig, PathConfig, QueryConfig },
+        App,
+        HttpResponse,
+    };
+    use serde::Deserialize;
+    use serde_json::Value;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Order {
+        Asc,
+        Desc,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Paging {
+        per_page: u32,
+        order: Option<Order>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Counter {
+        count: u32,
+    }
+
+    async fn call(request: test::TestRequest) -> ServiceResponse<impl MessageBody> {
+        let app = test::init_service(
+            App::new()
+                .app_data(QueryConfig::default().error_handler(query_error_handler::<Paging>))
+                .app_data(PathConfig::default().error_handler(path_error_handler))
+                .app_data(FormConfig::default().error_handler(form_error_handler))
+                .route(
+                    "/items",
+                    web::get().to(|_: web::Query<Paging>| async { HttpResponse::Ok().finish() })
+                )
+                .route(
+                    "/items/{id}",
+                    web::get().to(|_: web::Path<u32>| async { HttpResponse::Ok().finish() })
+                )
+                .route(
+                    "/counter",
+                    web::post().to(|_: web::Form<Counter>| async { HttpResponse::Ok().finish() })
+                )
+        ).await;
+        test::call_service(&app, request.to_request()).await
+    }
+
+    // Checks the status and returns the only sub error.
+    async fn sub_error(response: ServiceResponse<impl MessageBody>, status: StatusCode) -> Value {
+        assert_eq!(response.status(), status);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["status"], status.as_u16());
+        assert_eq!(body["sub_errors"].as_array().unwrap().len(), 1);
+        body["sub_errors"][0].clone()
+    }
+
+    #[actix_web::test]
+    async fn query_errors_name_the_parameter() {
+        let response = call(test::TestRequest::get().uri("/items?per_page=ten")).await;
+        let error = sub_error(response, StatusCode::BAD_REQUEST).await;
+        assert_eq!(error["object"], "query");
+        assert_eq!(error["field"], "per_page");
+        assert_eq!(error["rejected_value"], "ten");
+
+        let response = call(test::TestRequest::get().uri("/items?per_page=1&order=sideways")).await;
+        let error = sub_error(response, StatusCode::BAD_REQUEST).await;
+        assert_eq!(error["field"], "order");
+        assert_eq!(error["rejected_value"], "sideways");
+
+        let response = call(test::TestRequest::get().uri("/items?order=asc")).await;
+        let error = sub_error(response, StatusCode::BAD_REQUEST).await;
+        assert_eq!(error["field"], "per_page");
+    }
+
+    #[actix_web::test]
+    async fn path_errors_name_the_segment() {
+        let response = call(test::TestRequest::get().uri("/items/abc")).await;
+        let error = sub_error(response, StatusCode::BAD_REQUEST).await;
+        assert_eq!(error["object"], "path");
+        assert_eq!(error["field"], "id");
+        assert_eq!(error["rejected_value"], "abc");
+    }
+
+    #[actix_web::test]
+    async fn form_errors_are_api_errors() {
+        let request = test::TestRequest
+            ::post()
+            .uri("/counter")
+            .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+            .set_payload("other=1");
+        let error = sub_error(call(request).await, StatusCode::BAD_REQUEST).await;
+        assert_eq!(error["object"], "form");
+        assert_eq!(error["field"], "count");
+
+        let request = test::TestRequest
+            ::post()
+            .uri("/counter")
+            .insert_header((header::CONTENT_TYPE, "text/plain"))
+            .set_payload("count=1");
+        let response = call(request).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["message"], "Unsupported media type");
+    }
+}
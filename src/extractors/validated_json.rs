@@ -0,0 +1,48 @@
+use std::{ future::Future, ops::Deref, pin::Pin };
+
+use actix_web::{ dev::Payload, web::Json, Error, FromRequest, HttpRequest };
+use serde::de::DeserializeOwned;
+use validator::Validate;
+
+use crate::models::error_model::ApiErrorType;
+
+/// JSON extractor that runs `Validate` on the payload after deserializing it.
+/// Parse errors still go through `JsonConfig`, validation errors become `ApiErrorType::ValidationError`.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> ValidatedJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidatedJson<T> {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let value = json.await?.into_inner();
+            value.validate().map_err(|validation_error| ApiErrorType::ValidationError {
+                validation_error,
+                object: object_name::<T>(),
+            })?;
+            Ok(ValidatedJson(value))
+        })
+    }
+}
+
+// Type name without its module path, e.g. `UserPayload`.
+fn object_name<T>() -> String {
+    let name = std::any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name).to_owned()
+}
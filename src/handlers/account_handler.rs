@@ -0,0 +1,347 @@
+use actix_web::{ post, rt, web, HttpResponse };
+use chrono::DateTime;
+use log::{ error, info };
+
+use crate::{
+    auth::{ jwt::{ EmailTokenPurpose, JwtConfig }, password::hash_password, rbac::Principal },
+    configs::{ db::AppStates, mail::MailConfig },
+    extractors::validated_json::ValidatedJson,
+    mailers::mailer::{ Email, Mailer },
+    models::{
+        auth_model::{ EmailTokenPayload, ForgotPasswordPayload, ResetPasswordPayload },
+        error_model::ApiErrorType,
+        user_model::User,
+    },
+    repositories::user_repository::UserKey,
+};
+
+/// Mails `user` a link to verify their current email address.
+pub async fn send_verification_email(
+    mailer: &dyn Mailer,
+    mail: &MailConfig,
+    jwt: &JwtConfig,
+    user: &User
+) -> Result<(), ApiErrorType> {
+    let token = jwt.issue_email_token(
+        EmailTokenPurpose::VerifyEmail,
+        &user.id,
+        &user.email,
+        mail.verification_expiry_seconds
+    )?;
+    let link = mail.link("verify_email", &token);
+    mailer.send(Email {
+        to: user.email.clone(),
+        subject: "Verify your email address".to_owned(),
+        body: format!(
+            "Hello {},\n\nOpen this link to verify your email address:\n\n{}\n\n\
+             It expires in {} hours. If you did not sign up, ignore this email.\n",
+            user.first_name,
+            link,
+            mail.verification_expiry_seconds / 3600
+        ),
+    }).await?;
+    Ok(())
+}
+
+async fn send_reset_email(
+    mailer: &dyn Mailer,
+    mail: &MailConfig,
+    jwt: &JwtConfig,
+    user: &User
+) -> Result<(), ApiErrorType> {
+    let token = jwt.issue_email_token(
+        EmailTokenPurpose::ResetPassword,
+        &user.id,
+        &user.email,
+        mail.reset_expiry_seconds
+    )?;
+    let link = mail.link("reset_password", &token);
+    mailer.send(Email {
+        to: user.email.clone(),
+        subject: "Reset your password".to_owned(),
+        body: format!(
+            "Hello {},\n\nOpen this link to choose a new password:\n\n{}\n\n\
+             It expires in {} minutes. If you did not ask for it, ignore this email.\n",
+            user.first_name,
+            link,
+            mail.reset_expiry_seconds / 60
+        ),
+    }).await?;
+    Ok(())
+}
+
+/// Verifies a mailed token and marks it used. Tokens mailed to an address the user
+/// no longer has are rejected.
+async fn use_email_token(
+    cfg: &AppStates,
+    jwt: &JwtConfig,
+    purpose: EmailTokenPurpose,
+    token: &str
+) -> Result<User, ApiErrorType> {
+    let claims = jwt.verify_email_token(purpose, token)?;
+    let user = match cfg.users.find(&UserKey::parse(claims.sub.clone())).await? {
+        Some(user) if claims.email.as_deref() == Some(user.email.as_str()) => user,
+        _ => {
+            return Err(ApiErrorType::AuthenticationError);
+        }
+    };
+    let expires_at = DateTime::from_timestamp(claims.exp, 0).unwrap_or_default();
+    if !cfg.used_tokens.mark_used(&claims.jti, expires_at).await? {
+        return Err(ApiErrorType::AuthenticationError);
+    }
+    Ok(user)
+}
+
+/// Marks the email address of the user as verified, with the token from the verification mail.
+#[post("/auth/verify_email")]
+async fn verify_email(
+    cfg: web::Data<AppStates>,
+    jwt: web::Data<JwtConfig>,
+    json: ValidatedJson<EmailTokenPayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    let token = json.into_inner().token;
+    let mut user = use_email_token(&cfg, &jwt, EmailTokenPurpose::VerifyEmail, &token).await?;
+    if !cfg.users.set_email_verified(&UserKey::parse(user.id.clone()), &user.email, true).await? {
+        return Err(ApiErrorType::AuthenticationError);
+    }
+    info!("Email address of user {} verified", user.id);
+    user.email_verified = true;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// Mails the caller a new verification link.
+#[post("/me/verify_email")]
+async fn resend_verification_email(
+    cfg: web::Data<AppStates>,
+    mailer: web::Data<dyn Mailer>,
+    mail: web::Data<MailConfig>,
+    jwt: web::Data<JwtConfig>,
+    principal: Principal
+) -> Result<HttpResponse, ApiErrorType> {
+    let user_id = principal.user_id().ok_or(ApiErrorType::AuthorizationError)?;
+    let user = match cfg.users.find(&UserKey::parse(user_id.to_owned())).await? {
+        Some(user) => user,
+        None => {
+            return Err(ApiErrorType::AuthenticationError);
+        }
+    };
+    if user.email_verified {
+        return Err(ApiErrorType::Conflict);
+    }
+    send_verification_email(mailer.get_ref(), &mail, &jwt, &user).await?;
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Mails a password reset link to the user. Always answers `202 Accepted`,
+/// so it can't be used to find out which usernames exist.
+#[post("/auth/forgot_password")]
+async fn forgot_password(
+    cfg: web::Data<AppStates>,
+    mailer: web::Data<dyn Mailer>,
+    mail: web::Data<MailConfig>,
+    jwt: web::Data<JwtConfig>,
+    json: ValidatedJson<ForgotPasswordPayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    let key = UserKey::Username(json.into_inner().username);
+    let user = cfg.users.find(&key).await?;
+    // only users with a password can reset it
+    if let (Some(user), Some(_)) = (user, cfg.users.password_hash(&key).await?) {
+        // sent in the background, so the response time doesn't tell either
+        rt::spawn(async move {
+            if send_reset_email(mailer.get_ref(), &mail, &jwt, &user).await.is_ok() {
+                info!("Password reset mailed to user {}", user.id);
+            }
+        });
+    }
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Sets a new password with the token from the password reset mail, ends the user's MongoDB
+/// sessions and revokes their refresh tokens. Cookie sessions can't be ended from the server
+/// and stay valid until they expire.
+#[post("/auth/reset_password")]
+async fn reset_password(
+    cfg: web::Data<AppStates>,
+    jwt: web::Data<JwtConfig>,
+    json: ValidatedJson<ResetPasswordPayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    let ResetPasswordPayload { token, password } = json.into_inner();
+    let user = use_email_token(&cfg, &jwt, EmailTokenPurpose::ResetPassword, &token).await?;
+    let password_hash = web
+        ::block(move || hash_password(&password)).await
+        .map_err(|_| ApiErrorType::InternalServerError)?
+        .map_err(|err| {
+            error!("Error: {}", err);
+            ApiErrorType::InternalServerError
+        })?;
+
+    let key = UserKey::parse(user.id.clone());
+    if !cfg.users.set_password_hash(&key, password_hash).await? {
+        return Err(ApiErrorType::AuthenticationError);
+    }
+    // the link was mailed to the address, so it is verified too
+    cfg.users.set_email_verified(&key, &user.email, true).await?;
+    if let Some(sessions) = &cfg.sessions {
+        sessions.delete_all(&user.id).await?;
+    }
+    cfg.refresh_tokens.revoke_user(&user.id).await?;
+    info!("Password of user {} reset", user.id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ sync::Arc, time::Duration };
+
+    use actix_http::Request;
+    use actix_web::{
+        body::MessageBody,
+        dev::{ Service, ServiceResponse },
+        http::StatusCode,
+        test,
+        App,
+        Error,
+    };
+    use chrono::Utc;
+    use serde_json::{ json, Value };
+
+    use crate::{
+        auth::password::verify_password,
+        configs::settings::{ JwtSettings, MailSettings },
+        models::{ refresh_token_model::RefreshToken, role_model::Role, user_model::UserPayload },
+        test_support::{ create_user, memory_states, RecordingMailer },
+    };
+    use super::*;
+
+    const NEW_PASSWORD: &str = "a brand new password";
+
+    async fn app(
+        states: web::Data<AppStates>,
+        mailer: Arc<RecordingMailer>,
+        jwt: web::Data<JwtConfig>
+    ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+        let mailer: Arc<dyn Mailer> = mailer;
+        test::init_service(
+            App::new()
+                .app_data(states)
+                .app_data(web::Data::from(mailer))
+                .app_data(jwt)
+                .app_data(web::Data::new(MailConfig::new(&MailSettings::default())))
+                .service(verify_email)
+                .service(forgot_password)
+                .service(reset_password)
+        ).await
+    }
+
+    fn post(uri: &str, body: Value) -> Request {
+        test::TestRequest::post().uri(uri).set_json(body).to_request()
+    }
+
+    // The token of the link in the last mail, waiting for mails sent in the background.
+    async fn mailed_token(mailer: &RecordingMailer) -> String {
+        for _ in 0..100 {
+            if let Some(email) = mailer.sent.lock().unwrap().last() {
+                let link = email.body.split_whitespace().find(|word| word.contains("?token="));
+                return link.unwrap().split("?token=").nth(1).unwrap().to_owned();
+            }
+            rt::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no mail sent");
+    }
+
+    #[actix_web::test]
+    async fn verification_token_works_once() {
+        let states = web::Data::new(memory_states());
+        let mailer = Arc::new(RecordingMailer::default());
+        let user = create_user(&states, "hello", Role::Viewer).await;
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        let app = app(states.clone(), mailer.clone(), jwt.clone()).await;
+        let mail = MailConfig::new(&MailSettings::default());
+        send_verification_email(mailer.as_ref(), &mail, &jwt, &user).await.unwrap();
+        let token = mailed_token(&mailer).await;
+
+        let request = post("/auth/verify_email", json!({ "token": token }));
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let verified: Value = test::read_body_json(response).await;
+        assert_eq!(verified["email_verified"], true);
+
+        let request = post("/auth/verify_email", json!({ "token": token }));
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn verification_token_for_an_old_address_is_rejected() {
+        let states = web::Data::new(memory_states());
+        let mailer = Arc::new(RecordingMailer::default());
+        let user = create_user(&states, "hello", Role::Viewer).await;
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        let app = app(states.clone(), mailer.clone(), jwt.clone()).await;
+        let mail = MailConfig::new(&MailSettings::default());
+        send_verification_email(mailer.as_ref(), &mail, &jwt, &user).await.unwrap();
+        let token = mailed_token(&mailer).await;
+
+        let changes = UserPayload {
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            username: user.username.clone(),
+            email: "changed@example.com".to_owned(),
+        };
+        states.users.update(&UserKey::parse(user.id.clone()), changes).await.unwrap();
+
+        let request = post("/auth/verify_email", json!({ "token": token }));
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let stored = states.users.find(&UserKey::parse(user.id)).await.unwrap().unwrap();
+        assert!(!stored.email_verified);
+    }
+
+    #[actix_web::test]
+    async fn password_reset_token_works_once() {
+        let states = web::Data::new(memory_states());
+        let mailer = Arc::new(RecordingMailer::default());
+        let user = create_user(&states, "hello", Role::Viewer).await;
+        let now = Utc::now();
+        states.refresh_tokens
+            .insert(RefreshToken {
+                token_hash: "hash".to_owned(),
+                family_id: "family".to_owned(),
+                user_id: user.id.clone(),
+                created_at: now,
+                expires_at: now + chrono::Duration::days(1),
+                used_at: None,
+                revoked: false,
+            }).await
+            .unwrap();
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        let app = app(states.clone(), mailer.clone(), jwt).await;
+
+        // unknown usernames get the same answer, but no mail
+        let request = post("/auth/forgot_password", json!({ "username": "nobody" }));
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let request = post("/auth/forgot_password", json!({ "username": "hello" }));
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let token = mailed_token(&mailer).await;
+        assert_eq!(mailer.sent.lock().unwrap().len(), 1);
+
+        // it is no verification token
+        let request = post("/auth/verify_email", json!({ "token": token }));
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let reset = json!({ "token": token, "password": NEW_PASSWORD });
+        let response = test::call_service(&app, post("/auth/reset_password", reset.clone())).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = test::call_service(&app, post("/auth/reset_password", reset)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let key = UserKey::parse(user.id);
+        let password_hash = states.users.password_hash(&key).await.unwrap();
+        assert!(verify_password(NEW_PASSWORD, password_hash.as_deref()));
+        assert!(states.users.find(&key).await.unwrap().unwrap().email_verified);
+        assert!(states.refresh_tokens.find("hash").await.unwrap().unwrap().revoked);
+    }
+}
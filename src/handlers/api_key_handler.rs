@@ -0,0 +1,49 @@
+use actix_web::{ web, HttpResponse };
+
+use crate::{
+    auth::{ rbac::Principal, token::{ generate_token, hash_token } },
+    configs::db::AppStates,
+    extractors::validated_json::ValidatedJson,
+    models::{
+        api_key_model::{ ApiKey, ApiKeyPayload, CreatedApiKey, API_KEY_MARKER },
+        error_model::ApiErrorType,
+    },
+};
+
+// The handlers below are registered in `main`, guarded by `Permission::ApiKeysAdmin`.
+
+/// Creates an API key and returns it. The key is not stored and can't be shown again.
+/// Keys are created by users only, and never with scopes the creator doesn't have.
+pub async fn create_api_key(
+    cfg: web::Data<AppStates>,
+    principal: Principal,
+    json: ValidatedJson<ApiKeyPayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    let payload = json.into_inner();
+    let created_by = principal.user_id().ok_or(ApiErrorType::AuthorizationError)?.to_owned();
+    for scope in &payload.scopes {
+        principal.require(*scope)?;
+    }
+
+    let key = format!("{API_KEY_MARKER}{}", generate_token());
+    let api_key = ApiKey::new(&key, payload, created_by);
+    cfg.api_keys.insert(api_key.clone(), hash_token(&key)).await?;
+    Ok(HttpResponse::Created().json(CreatedApiKey { api_key, key }))
+}
+
+/// Lists all API keys, revoked ones included, without their secrets.
+pub async fn list_api_keys(cfg: web::Data<AppStates>) -> Result<HttpResponse, ApiErrorType> {
+    Ok(HttpResponse::Ok().json(cfg.api_keys.list().await?))
+}
+
+/// Revokes an API key. It is kept, so it still shows up in the list.
+pub async fn revoke_api_key(
+    cfg: web::Data<AppStates>,
+    id: web::Path<String>
+) -> Result<HttpResponse, ApiErrorType> {
+    if cfg.api_keys.revoke(&id.into_inner()).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ApiErrorType::NotFound)
+    }
+}
@@ -0,0 +1,498 @@
+use actix_session::Session;
+use actix_web::{ get, post, web, HttpResponse };
+use chrono::{ DateTime, Duration, Utc };
+use log::{ error, warn };
+use uuid::Uuid;
+
+use crate::{
+    auth::{
+        jwt::{
+            JwtConfig,
+            CHALLENGE_EXPIRY_SECONDS,
+            MAX_TWO_FACTOR_ATTEMPTS,
+            TWO_FACTOR_LOCKOUT_SECONDS,
+        },
+        password::{ hash_password, verify_password },
+        rbac::Principal,
+        token::{ generate_token, hash_token },
+    },
+    configs::{ db::AppStates, mail::MailConfig },
+    constants::{ SESSION_CREATED_AT, SESSION_CSRF_TOKEN, SESSION_ID, SESSION_USER_ID },
+    extractors::validated_json::ValidatedJson,
+    mailers::mailer::Mailer,
+    middlewares::csrf::session_csrf_token,
+    models::{
+        auth_model::{
+            CsrfTokenResponse,
+            LoginPayload,
+            LoginResponse,
+            LogoutPayload,
+            RefreshPayload,
+            RegisterPayload,
+            TokenResponse,
+            TwoFactorChallenge,
+            TwoFactorLoginPayload,
+        },
+        error_model::ApiErrorType,
+        refresh_token_model::RefreshToken,
+        user_model::User,
+    },
+    repositories::user_repository::UserKey,
+};
+use super::{ account_handler::send_verification_email, two_factor_handler::verify_second_factor };
+
+/// Issues an access token with the scopes of the user's role
+/// and stores a new refresh token of `family_id` for the user.
+async fn issue_tokens(
+    cfg: &AppStates,
+    jwt: &JwtConfig,
+    user: &User,
+    family_id: String
+) -> Result<TokenResponse, ApiErrorType> {
+    let access_token = jwt.issue(&user.id, &user.role.scopes())?;
+    let refresh_token = generate_token();
+    let now = Utc::now();
+    cfg.refresh_tokens.insert(RefreshToken {
+        token_hash: hash_token(&refresh_token),
+        family_id,
+        user_id: user.id.clone(),
+        created_at: now,
+        expires_at: now + Duration::seconds(jwt.refresh_expiry_seconds),
+        used_at: None,
+        revoked: false,
+    }).await?;
+    Ok(TokenResponse {
+        access_token,
+        token_type: "Bearer",
+        expires_in: jwt.expiry_seconds,
+        refresh_token,
+    })
+}
+
+/// Stores the user id in the cookie session and issues access and refresh tokens.
+async fn complete_login(
+    cfg: &AppStates,
+    jwt: &JwtConfig,
+    session: &Session,
+    user: User
+) -> Result<HttpResponse, ApiErrorType> {
+    // new session id on login, against session fixation
+    session.renew();
+    // and a new entry in the session list
+    session.remove(SESSION_ID);
+    session.remove(SESSION_CREATED_AT);
+    session.remove(SESSION_CSRF_TOKEN);
+    session.insert(SESSION_USER_ID, &user.id).map_err(|err| {
+        error!("Error: {}", err);
+        ApiErrorType::InternalServerError
+    })?;
+    let csrf_token = session_csrf_token(session)?;
+    // every login starts a new refresh token family
+    let tokens = issue_tokens(cfg, jwt, &user, Uuid::new_v4().to_string()).await?;
+    Ok(HttpResponse::Ok().json(LoginResponse { user, tokens, csrf_token }))
+}
+
+/// Creates a user with a password, so it can log in, and mails it a link to verify its email.
+#[post("/auth/register")]
+async fn register(
+    cfg: web::Data<AppStates>,
+    mailer: web::Data<dyn Mailer>,
+    mail: web::Data<MailConfig>,
+    jwt: web::Data<JwtConfig>,
+    json: ValidatedJson<RegisterPayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    let RegisterPayload { user, password } = json.into_inner();
+    let password_hash = web
+        ::block(move || hash_password(&password)).await
+        .map_err(|_| ApiErrorType::InternalServerError)?
+        .map_err(|err| {
+            error!("Error: {}", err);
+            ApiErrorType::InternalServerError
+        })?;
+
+    let user = User::new(user);
+    cfg.users.insert(user.clone(), Some(password_hash)).await?;
+    // the user can ask for another mail, so a failed one doesn't fail the registration
+    if send_verification_email(mailer.get_ref(), &mail, &jwt, &user).await.is_err() {
+        warn!("Verification email for user {} not sent", user.id);
+    }
+    Ok(HttpResponse::Created().json(user))
+}
+
+/// Checks the credentials, stores the user id in the cookie session
+/// and returns bearer access and refresh tokens for clients that don't keep cookies.
+/// Accounts with two-factor authentication get a `TwoFactorChallenge` instead.
+#[post("/auth/login")]
+async fn login(
+    cfg: web::Data<AppStates>,
+    jwt: web::Data<JwtConfig>,
+    mail: web::Data<MailConfig>,
+    session: Session,
+    json: ValidatedJson<LoginPayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    let LoginPayload { username, password } = json.into_inner();
+    let key = UserKey::Username(username);
+    let user = cfg.users.find(&key).await?;
+    let password_hash = match user {
+        Some(_) => cfg.users.password_hash(&key).await?,
+        None => None,
+    };
+    // always verify, so unknown usernames are not faster to reject
+    let valid = web
+        ::block(move || verify_password(&password, password_hash.as_deref())).await
+        .map_err(|_| ApiErrorType::InternalServerError)?;
+
+    match user {
+        Some(user) if valid => {
+            if mail.require_verified_email && !user.email_verified {
+                return Err(ApiErrorType::EmailNotVerified);
+            }
+            let two_factor = cfg.two_factor.find(&user.id).await?;
+            if two_factor.is_some_and(|two_factor| two_factor.enabled) {
+                return Ok(
+                    HttpResponse::Ok().json(TwoFactorChallenge {
+                        two_factor_required: true,
+                        challenge_token: jwt.issue_challenge(&user.id)?,
+                        expires_in: CHALLENGE_EXPIRY_SECONDS,
+                    })
+                );
+            }
+            complete_login(&cfg, &jwt, &session, user).await
+        }
+        _ => Err(ApiErrorType::InvalidCredential),
+    }
+}
+
+/// Completes a login that was answered with a `TwoFactorChallenge`,
+/// with a TOTP code or a recovery code. A challenge completes one login. After
+/// `MAX_TWO_FACTOR_ATTEMPTS` wrong codes, over any number of challenges, the user is locked
+/// out for `TWO_FACTOR_LOCKOUT_SECONDS`, and again after each further wrong code.
+#[post("/auth/two_factor")]
+async fn login_two_factor(
+    cfg: web::Data<AppStates>,
+    jwt: web::Data<JwtConfig>,
+    session: Session,
+    json: ValidatedJson<TwoFactorLoginPayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    let TwoFactorLoginPayload { challenge_token, code } = json.into_inner();
+    let claims = jwt.verify_challenge(&challenge_token)?;
+    let expires_at = DateTime::from_timestamp(claims.exp, 0).unwrap_or_default();
+    let two_factor = match cfg.two_factor.find(&claims.sub).await? {
+        Some(two_factor) if two_factor.enabled => two_factor,
+        _ => {
+            return Err(ApiErrorType::InvalidCredential);
+        }
+    };
+    let now = Utc::now().timestamp();
+    let counted = cfg.two_factor.record_attempt(
+        &claims.sub,
+        MAX_TWO_FACTOR_ATTEMPTS,
+        now,
+        now + TWO_FACTOR_LOCKOUT_SECONDS
+    ).await?;
+    if !counted {
+        warn!("Two-factor code refused, user {} is locked out", claims.sub);
+        return Err(ApiErrorType::TooManyAttempts);
+    }
+    if !verify_second_factor(&cfg, &two_factor, &code).await? {
+        return Err(ApiErrorType::InvalidCredential);
+    }
+    if !cfg.used_tokens.mark_used(&claims.jti, expires_at).await? {
+        return Err(ApiErrorType::AuthenticationError);
+    }
+    cfg.two_factor.reset_attempts(&claims.sub).await?;
+    match cfg.users.find(&UserKey::parse(claims.sub)).await? {
+        Some(user) => complete_login(&cfg, &jwt, &session, user).await,
+        None => Err(ApiErrorType::InvalidCredential),
+    }
+}
+
+/// Exchanges a refresh token for a new access token and a new refresh token.
+/// Each refresh token works once. Presenting one that was already exchanged revokes
+/// its whole family, so a stolen token stops working for the thief and the owner alike.
+#[post("/auth/refresh")]
+async fn refresh(
+    cfg: web::Data<AppStates>,
+    jwt: web::Data<JwtConfig>,
+    json: ValidatedJson<RefreshPayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    let token_hash = hash_token(&json.into_inner().refresh_token);
+    let token = match cfg.refresh_tokens.find(&token_hash).await? {
+        Some(token) if !token.revoked && !token.is_expired() => token,
+        _ => {
+            return Err(ApiErrorType::AuthenticationError);
+        }
+    };
+    if !cfg.refresh_tokens.mark_used(&token_hash).await? {
+        warn!("Refresh token reused, revoking token family {}", token.family_id);
+        cfg.refresh_tokens.revoke_family(&token.family_id).await?;
+        return Err(ApiErrorType::AuthenticationError);
+    }
+    // the user is loaded again, so role changes apply to the new access token
+    let user = match cfg.users.find(&UserKey::parse(token.user_id)).await? {
+        Some(user) => user,
+        None => {
+            cfg.refresh_tokens.revoke_family(&token.family_id).await?;
+            return Err(ApiErrorType::AuthenticationError);
+        }
+    };
+
+    let tokens = issue_tokens(&cfg, &jwt, &user, token.family_id).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Ends the cookie session and revokes the family of the supplied refresh token, if any.
+/// Access tokens that were already issued stay valid until they expire.
+#[post("/auth/logout")]
+async fn logout(
+    cfg: web::Data<AppStates>,
+    session: Session,
+    json: Option<web::Json<LogoutPayload>>
+) -> Result<HttpResponse, ApiErrorType> {
+    if let Some(refresh_token) = json.and_then(|json| json.into_inner().refresh_token) {
+        if let Some(token) = cfg.refresh_tokens.find(&hash_token(&refresh_token)).await? {
+            cfg.refresh_tokens.revoke_family(&token.family_id).await?;
+        }
+    }
+    session.purge();
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Gets the authenticated user, from the bearer token or the cookie session.
+#[get("/me")]
+async fn me(cfg: web::Data<AppStates>, principal: Principal) -> Result<HttpResponse, ApiErrorType> {
+    // API keys don't belong to a user
+    let user_id = principal.user_id().ok_or(ApiErrorType::AuthorizationError)?;
+    match cfg.users.find(&UserKey::parse(user_id.to_owned())).await? {
+        Some(user) => Ok(HttpResponse::Ok().json(user)),
+        None => Err(ApiErrorType::AuthenticationError),
+    }
+}
+
+/// Returns the CSRF token of the cookie session, for clients that no longer have the one
+/// returned by the login. Unsafe requests with the session cookie send it as `X-CSRF-Token`.
+#[get("/auth/csrf")]
+async fn get_csrf_token(session: Session) -> Result<HttpResponse, ApiErrorType> {
+    if session.get::<String>(SESSION_USER_ID).ok().flatten().is_none() {
+        return Err(ApiErrorType::AuthenticationError);
+    }
+    Ok(HttpResponse::Ok().json(CsrfTokenResponse { csrf_token: session_csrf_token(&session)? }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_http::Request;
+    use actix_web::{
+        body::MessageBody,
+        dev::{ Service, ServiceResponse },
+        http::StatusCode,
+        test,
+        App,
+        Error,
+    };
+    use serde_json::{ json, Value };
+
+    use crate::{
+        auth::totp::current_code,
+        configs::settings::{ JwtSettings, MailSettings },
+        models::role_model::Role,
+        test_support::{ create_user, enable_two_factor, memory_states, RecordingMailer, PASSWORD },
+    };
+    use super::*;
+
+    async fn two_factor_app(
+        states: web::Data<AppStates>
+    ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+        test::init_service(
+            App::new()
+                .app_data(states)
+                .app_data(web::Data::new(JwtConfig::new(&JwtSettings::default())))
+                .app_data(web::Data::new(MailConfig::new(&MailSettings::default())))
+                .service(login)
+                .service(login_two_factor)
+        ).await
+    }
+
+    // Logs in with the password and returns the challenge token.
+    async fn challenge(
+        app: &impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error>
+    ) -> String {
+        let request = test::TestRequest
+            ::post()
+            .uri("/auth/login")
+            .set_json(json!({ "username": "hello", "password": PASSWORD }))
+            .to_request();
+        let response: Value = test::call_and_read_body_json(app, request).await;
+        assert_eq!(response["two_factor_required"], true);
+        response["challenge_token"].as_str().unwrap().to_owned()
+    }
+
+    fn second_factor(challenge_token: &str, code: &str) -> Request {
+        test::TestRequest
+            ::post()
+            .uri("/auth/two_factor")
+            .set_json(json!({ "challenge_token": challenge_token, "code": code }))
+            .to_request()
+    }
+
+    #[actix_web::test]
+    async fn totp_code_completes_one_login() {
+        let states = memory_states();
+        let user = create_user(&states, "hello", Role::Viewer).await;
+        let (secret, _) = enable_two_factor(&states, &user).await;
+        let app = two_factor_app(web::Data::new(states)).await;
+
+        let token = challenge(&app).await;
+        let code = current_code(&secret);
+        let response = test::call_service(&app, second_factor(&token, &code)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // neither the challenge nor the code work twice
+        let response = test::call_service(&app, second_factor(&token, &code)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&app, second_factor(&challenge(&app).await, &code)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn recovery_code_works_once() {
+        let states = memory_states();
+        let user = create_user(&states, "hello", Role::Viewer).await;
+        let (_, recovery_codes) = enable_two_factor(&states, &user).await;
+        let app = two_factor_app(web::Data::new(states)).await;
+
+        let code = recovery_codes[0].to_uppercase();
+        let response = test::call_service(&app, second_factor(&challenge(&app).await, &code)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, second_factor(&challenge(&app).await, &code)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn wrong_codes_lock_the_user_out_across_logins() {
+        let states = web::Data::new(memory_states());
+        let user = create_user(&states, "hello", Role::Viewer).await;
+        let (secret, _) = enable_two_factor(&states, &user).await;
+        let app = two_factor_app(states.clone()).await;
+
+        let first = challenge(&app).await;
+        for attempt in 1..=MAX_TWO_FACTOR_ATTEMPTS + 1 {
+            // a new password login doesn't give new attempts
+            let response = test::call_service(&app, second_factor(&challenge(&app).await, "wrong"))
+                .await;
+            let expected = if attempt > MAX_TWO_FACTOR_ATTEMPTS {
+                StatusCode::TOO_MANY_REQUESTS
+            } else {
+                StatusCode::UNAUTHORIZED
+            };
+            assert_eq!(response.status(), expected);
+        }
+        // neither a right code nor an older challenge get through
+        let code = current_code(&secret);
+        let response = test::call_service(&app, second_factor(&challenge(&app).await, &code)).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let response = test::call_service(&app, second_factor(&first, &code)).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // once the lockout is over a right code is accepted and the attempts start over
+        let mut two_factor = states.two_factor.find(&user.id).await.unwrap().unwrap();
+        two_factor.locked_until = Utc::now().timestamp() - 1;
+        states.two_factor.save(two_factor).await.unwrap();
+        let response = test::call_service(&app, second_factor(&first, &code)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let two_factor = states.two_factor.find(&user.id).await.unwrap().unwrap();
+        assert_eq!((two_factor.failed_attempts, two_factor.locked_until), (0, 0));
+    }
+
+    #[actix_web::test]
+    async fn reused_refresh_token_revokes_its_family() {
+        let states = memory_states();
+        create_user(&states, "hello", Role::Viewer).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(states))
+                .app_data(web::Data::new(JwtConfig::new(&JwtSettings::default())))
+                .app_data(web::Data::new(MailConfig::new(&MailSettings::default())))
+                .service(login)
+                .service(refresh)
+        ).await;
+        let refresh_with = |refresh_token: &str| {
+            test::TestRequest
+                ::post()
+                .uri("/auth/refresh")
+                .set_json(json!({ "refresh_token": refresh_token }))
+                .to_request()
+        };
+
+        let request = test::TestRequest
+            ::post()
+            .uri("/auth/login")
+            .set_json(json!({ "username": "hello", "password": PASSWORD }))
+            .to_request();
+        let response: Value = test::call_and_read_body_json(&app, request).await;
+        let first = response["refresh_token"].as_str().unwrap().to_owned();
+
+        let response: Value = test::call_and_read_body_json(&app, refresh_with(&first)).await;
+        let second = response["refresh_token"].as_str().unwrap().to_owned();
+        assert_ne!(first, second);
+
+        let response = test::call_service(&app, refresh_with(&first)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        // the replay revoked the newest token of the family as well
+        let response = test::call_service(&app, refresh_with(&second)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn login_as(username: &str, password: &str) -> Request {
+        test::TestRequest
+            ::post()
+            .uri("/auth/login")
+            .set_json(json!({ "username": username, "password": password }))
+            .to_request()
+    }
+
+    #[actix_web::test]
+    async fn registered_user_logs_in_with_its_password() {
+        let states = web::Data::new(memory_states());
+        let mailer: Arc<dyn Mailer> = Arc::new(RecordingMailer::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(states.clone())
+                .app_data(web::Data::from(mailer))
+                .app_data(web::Data::new(JwtConfig::new(&JwtSettings::default())))
+                .app_data(web::Data::new(MailConfig::new(&MailSettings::default())))
+                .service(register)
+                .service(login)
+        ).await;
+        let register_with = |password: &str| {
+            let body = json!({
+                "first_name": "hello",
+                "last_name": "world",
+                "username": "hello",
+                "email": "hello@example.com",
+                "password": password,
+            });
+            test::TestRequest::post().uri("/auth/register").set_json(body).to_request()
+        };
+
+        let response = test::call_service(&app, register_with("short")).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = test::call_service(&app, register_with(PASSWORD)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let user: Value = test::read_body_json(response).await;
+        assert!(user.get("password").is_none());
+        let key = UserKey::Username("hello".to_owned());
+        let password_hash = states.users.password_hash(&key).await.unwrap().unwrap();
+        assert!(password_hash.starts_with("$argon2id$"));
+
+        let request = login_as("hello", PASSWORD);
+        let response: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(response["user"]["id"], user["id"]);
+        assert!(response["access_token"].is_string());
+        for (username, password) in [("hello", "wrong password"), ("nobody", PASSWORD)] {
+            let response = test::call_service(&app, login_as(username, password)).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::{ fmt::Display, future::Future, time::{ Duration, Instant } };
+
+use actix_web::{ get, rt::time::timeout, web, HttpResponse };
+
+use crate::{
+    configs::{ db::{ ping_mongo, ping_postgres, AppStates }, migrations::check_migrations },
+    models::health_model::{ DependencyHealth, HealthReport, HealthStatus },
+};
+
+// A dependency that takes longer to answer counts as down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+async fn check<E: Display>(
+    name: &'static str,
+    ping: impl Future<Output = Result<(), E>>
+) -> DependencyHealth {
+    let started = Instant::now();
+    let result = match timeout(CHECK_TIMEOUT, ping).await {
+        Ok(result) => result.map_err(|err| err.to_string()),
+        Err(_) => Err(format!("No answer within {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    DependencyHealth {
+        name,
+        status: if result.is_ok() { HealthStatus::Up } else { HealthStatus::Down },
+        latency_ms: Some(started.elapsed().as_secs_f64() * 1000.0),
+        error: result.err(),
+    }
+}
+
+/// Answers as long as the process serves requests, for liveness probes.
+#[get("/health/live")]
+async fn live() -> HttpResponse {
+    HttpResponse::Ok().json(HealthReport { status: HealthStatus::Up, dependencies: Vec::new() })
+}
+
+/// Pings the configured databases and checks that the MongoDB indexes are set up and the
+/// PostgreSQL migrations are applied. Answers `503 Service Unavailable` with the same report
+/// when anything is down.
+#[get("/health/ready")]
+async fn ready(cfg: web::Data<AppStates>) -> HttpResponse {
+    let mut dependencies = Vec::new();
+    if let Some(db) = &cfg.mongo {
+        dependencies.push(check("mongodb", ping_mongo(db.client())).await);
+        let indexes_ready = cfg.db_status.is_ready();
+        dependencies.push(DependencyHealth {
+            name: "schema",
+            status: if indexes_ready { HealthStatus::Up } else { HealthStatus::Down },
+            latency_ms: None,
+            error: (!indexes_ready).then(|| "Indexes are not set up yet".to_owned()),
+        });
+    }
+    if let Some(pool) = &cfg.postgres {
+        dependencies.push(check("postgres", ping_postgres(pool)).await);
+        dependencies.push(check("migrations", check_migrations(pool)).await);
+    }
+
+    if dependencies.iter().all(|dependency| dependency.status == HealthStatus::Up) {
+        HttpResponse::Ok().json(HealthReport { status: HealthStatus::Up, dependencies })
+    } else {
+        HttpResponse::ServiceUnavailable().json(HealthReport {
+            status: HealthStatus::Down,
+            dependencies,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{ http::StatusCode, rt::time::sleep, test, App };
+    use mongodb::{ options::ClientOptions, Client };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        configs::{ db::connect, settings::DatabaseSettings },
+        test_support::memory_states,
+    };
+
+    // Nothing listens on the discard port.
+    const UNREACHABLE_MONGO: &str = "mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=200";
+    const UNREACHABLE_POSTGRES: &str = "postgres://postgres@127.0.0.1:9/postgres";
+
+    async fn get(states: AppStates, uri: &str) -> (StatusCode, Value) {
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(states)).service(live).service(ready)
+        ).await;
+        let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request())
+            .await;
+        let status = response.status();
+        (status, test::read_body_json(response).await)
+    }
+
+    fn dependency<'a>(report: &'a Value, name: &str) -> &'a Value {
+        let dependencies = report["dependencies"].as_array().unwrap();
+        dependencies.iter().find(|dependency| dependency["name"] == name).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn live_always_answers() {
+        let states = memory_states();
+        states.db_status.set_ready(false);
+        let (status, report) = get(states, "/health/live").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["status"], "up");
+    }
+
+    #[actix_web::test]
+    async fn memory_backend_is_ready() {
+        let (status, report) = get(memory_states(), "/health/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["status"], "up");
+        assert!(report.get("dependencies").is_none());
+    }
+
+    #[actix_web::test]
+    async fn unreachable_mongo_is_not_ready() {
+        let options = ClientOptions::parse(UNREACHABLE_MONGO).await.unwrap();
+        let mut states = memory_states();
+        states.mongo = Some(Client::with_options(options).unwrap().database("app"));
+        states.db_status.set_ready(false);
+
+        let (status, report) = get(states, "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report["status"], "down");
+        let mongodb = dependency(&report, "mongodb");
+        assert_eq!(mongodb["status"], "down");
+        assert!(mongodb["error"].is_string());
+        assert_eq!(dependency(&report, "schema")["error"], "Indexes are not set up yet");
+    }
+
+    #[actix_web::test]
+    async fn unreachable_postgres_is_not_ready() {
+        let settings = DatabaseSettings {
+            postgres_uri: Some(UNREACHABLE_POSTGRES.to_owned()),
+            ..DatabaseSettings::default()
+        };
+        let mut states = memory_states();
+        states.postgres = Some(connect(&settings).unwrap());
+
+        let (status, report) = get(states, "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(dependency(&report, "postgres")["status"], "down");
+        assert_eq!(dependency(&report, "migrations")["status"], "down");
+    }
+
+    #[actix_web::test]
+    async fn slow_dependencies_count_as_down() {
+        let health = check("slow", async {
+            sleep(CHECK_TIMEOUT * 2).await;
+            Ok::<(), String>(())
+        }).await;
+        assert_eq!(health.status, HealthStatus::Down);
+        assert_eq!(health.error.as_deref(), Some("No answer within 2s"));
+
+        let health = check("fast", async { Ok::<(), String>(()) }).await;
+        assert_eq!((health.status, health.error), (HealthStatus::Up, None));
+    }
+}
@@ -0,0 +1,79 @@
+use actix_web::{ get, web, HttpResponse };
+use log::error;
+
+use crate::{
+    configs::db::AppStates,
+    models::error_model::ApiErrorType,
+    telemetry::metrics::METRICS,
+};
+
+/// Serves the metrics in the Prometheus text format.
+#[get("/metrics")]
+async fn metrics(cfg: web::Data<AppStates>) -> Result<HttpResponse, ApiErrorType> {
+    // only server-side sessions can be counted, MongoDB is the one such store
+    if let Some(sessions) = &cfg.sessions {
+        match sessions.count().await {
+            Ok(count) => METRICS.sessions_active.with_label_values(&["mongo"]).set(count as i64),
+            // a stale gauge is better than failing the whole scrape
+            Err(err) => error!("Error: {}", err),
+        }
+    }
+    let body = METRICS.render().map_err(|err| {
+        error!("Error: {}", err);
+        ApiErrorType::InternalServerError
+    })?;
+    Ok(HttpResponse::Ok().content_type("text/plain; version=0.0.4; charset=utf-8").body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{ test, App };
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        models::session_model::SessionInfo,
+        repositories::user_repository::RepositoryError,
+        sessions::session_repository::SessionRepository,
+        test_support::memory_states,
+    };
+
+    // a session store that always holds three sessions
+    struct ThreeSessions;
+
+    #[async_trait]
+    impl SessionRepository for ThreeSessions {
+        async fn list(&self, _user_id: &str) -> Result<Vec<SessionInfo>, RepositoryError> {
+            Ok(Vec::new())
+        }
+
+        async fn delete(&self, _user_id: &str, _session_id: &str) -> Result<bool, RepositoryError> {
+            Ok(false)
+        }
+
+        async fn delete_all(&self, _user_id: &str) -> Result<(), RepositoryError> {
+            Ok(())
+        }
+
+        async fn count(&self) -> Result<u64, RepositoryError> {
+            Ok(3)
+        }
+    }
+
+    #[actix_web::test]
+    async fn active_sessions_are_counted_in_the_store() {
+        let mut states = memory_states();
+        states.sessions = Some(Arc::new(ThreeSessions));
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(states)).service(metrics)
+        ).await;
+
+        let request = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::call_and_read_body(&app, request).await;
+
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("sessions_active{backend=\"mongo\"} 3"), "{body}");
+    }
+}
@@ -1,3 +1,10 @@
 pub mod welcome_handler;
 pub mod qr_handler;
 pub mod user_handler;
+pub mod auth_handler;
+pub mod api_key_handler;
+pub mod two_factor_handler;
+pub mod account_handler;
+pub mod session_handler;
+pub mod health_handler;
+pub mod metrics_handler;
@@ -1,23 +1,29 @@
+use std::time::Instant;
+
 use actix_http::StatusCode;
-use actix_web::{ get, http::header::ContentType, post, web, HttpResponse, Result };
+use actix_web::{ http::header::ContentType, post, web, HttpResponse, Result };
 use qirust::helper::generate_svg_string;
 use serde::{ Deserialize, Serialize };
 
+use crate::telemetry::{ metrics::METRICS, trace::in_span };
+
 #[derive(Deserialize)]
-struct Info {
+pub struct Info {
     data: String,
 }
 
 #[derive(Serialize)]
-struct ResponseData {
+pub struct ResponseData {
     svg: String,
 }
 
 // this handler gets called if the query deserializes into `Info` successfully
-// otherwise a 400 Bad Request error response is returned
-#[get("/qr")]
-async fn generate_qr(info: web::Query<Info>) -> Result<HttpResponse> {
-    let svg_string = generate_svg_string(&info.data);
+// otherwise a 400 Bad Request error response is returned.
+// Registered with `web::resource` in `main`, so the route gets its own `QueryConfig`.
+pub async fn generate_qr(info: web::Query<Info>) -> Result<HttpResponse> {
+    let started = Instant::now();
+    let svg_string = in_span("qr.render", || generate_svg_string(&info.data));
+    METRICS.observe_qr("html", started.elapsed());
     let response =
         format!("<body><div style='width: 500; height: 500; margin-left: auto; margin-right: auto;'>{}</div></body>", svg_string);
     Ok(HttpResponse::build(StatusCode::OK).content_type(ContentType::html()).body(response))
@@ -25,7 +31,9 @@ async fn generate_qr(info: web::Query<Info>) -> Result<HttpResponse> {
 
 #[post("/svg")]
 async fn get_svg(info: web::Json<Info>) -> web::Json<ResponseData> {
-    let svg_string = generate_svg_string(&info.data);
+    let started = Instant::now();
+    let svg_string = in_span("qr.render", || generate_svg_string(&info.data));
+    METRICS.observe_qr("svg", started.elapsed());
 
     web::Json(ResponseData { svg: svg_string })
 }
@@ -0,0 +1,89 @@
+use actix_session::Session;
+use actix_web::{ delete, get, web, HttpResponse };
+use log::info;
+
+use crate::{
+    auth::rbac::Principal,
+    configs::db::AppStates,
+    constants::{ SESSION_ID, SESSION_USER_ID },
+    models::{ error_model::ApiErrorType, session_model::SessionInfo },
+};
+
+// Users only, API keys have no sessions.
+fn user_id(principal: &Principal) -> Result<&str, ApiErrorType> {
+    principal.user_id().ok_or(ApiErrorType::AuthorizationError)
+}
+
+// The id of the request's cookie session, if it belongs to `user_id`.
+fn current_session_id(session: &Session, user_id: &str) -> Option<String> {
+    let owner = session.get::<String>(SESSION_USER_ID).ok().flatten()?;
+    if owner != user_id {
+        return None;
+    }
+    session.get::<String>(SESSION_ID).ok().flatten()
+}
+
+/// Lists the caller's sessions with their creation and last-seen time, IP and user agent.
+/// With cookie sessions only the current session is known.
+#[get("/me/sessions")]
+async fn list_sessions(
+    cfg: web::Data<AppStates>,
+    principal: Principal,
+    session: Session
+) -> Result<HttpResponse, ApiErrorType> {
+    let user_id = user_id(&principal)?;
+    let current_id = current_session_id(&session, user_id);
+    let mut sessions = match &cfg.sessions {
+        Some(sessions) => sessions.list(user_id).await?,
+        None if current_id.is_some() => {
+            SessionInfo::from_state(&session.entries()).into_iter().collect()
+        }
+        None => Vec::new(),
+    };
+    for info in &mut sessions {
+        info.current = current_id.as_ref() == Some(&info.id);
+    }
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// Ends one of the caller's sessions. Ending another session than the current one
+/// needs the MongoDB session store.
+#[delete("/me/sessions/{id}")]
+async fn delete_session(
+    cfg: web::Data<AppStates>,
+    principal: Principal,
+    session: Session,
+    id: web::Path<String>
+) -> Result<HttpResponse, ApiErrorType> {
+    let user_id = user_id(&principal)?;
+    let id = id.into_inner();
+    if current_session_id(&session, user_id).as_ref() == Some(&id) {
+        session.purge();
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    match &cfg.sessions {
+        Some(sessions) if sessions.delete(user_id, &id).await? => {
+            Ok(HttpResponse::NoContent().finish())
+        }
+        _ => Err(ApiErrorType::NotFound),
+    }
+}
+
+/// Logs the caller out everywhere: ends all their sessions, including the current one,
+/// and revokes their refresh tokens. Access tokens that were already issued stay valid
+/// until they expire.
+#[delete("/me/sessions")]
+async fn delete_all_sessions(
+    cfg: web::Data<AppStates>,
+    principal: Principal,
+    session: Session
+) -> Result<HttpResponse, ApiErrorType> {
+    let user_id = user_id(&principal)?;
+    if let Some(sessions) = &cfg.sessions {
+        sessions.delete_all(user_id).await?;
+    }
+    cfg.refresh_tokens.revoke_user(user_id).await?;
+    session.purge();
+    info!("User {} logged out everywhere", user_id);
+    Ok(HttpResponse::NoContent().finish())
+}
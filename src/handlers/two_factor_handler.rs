@@ -0,0 +1,153 @@
+use actix_web::{ post, web, HttpResponse };
+use chrono::Utc;
+use log::{ error, info };
+use qirust::helper::generate_svg_string;
+
+use crate::{
+    auth::{
+        jwt::JwtConfig,
+        rbac::Principal,
+        token::hash_token,
+        totp::{
+            generate_recovery_codes,
+            generate_secret,
+            is_totp_code,
+            normalize_recovery_code,
+            provisioning_url,
+            verify_code,
+        },
+    },
+    configs::db::AppStates,
+    extractors::validated_json::ValidatedJson,
+    models::{
+        error_model::ApiErrorType,
+        two_factor_model::{ CodePayload, EnrollmentResponse, RecoveryCodesResponse, TwoFactor },
+        user_model::User,
+    },
+    repositories::user_repository::UserKey,
+};
+
+/// Checks a TOTP code or a recovery code for the login of `two_factor.user_id`.
+/// Both work only once.
+pub async fn verify_second_factor(
+    cfg: &AppStates,
+    two_factor: &TwoFactor,
+    code: &str
+) -> Result<bool, ApiErrorType> {
+    let code = code.trim();
+    if is_totp_code(code) {
+        return match verify_code(&two_factor.secret, code) {
+            Some(step) => Ok(cfg.two_factor.record_step(&two_factor.user_id, step).await?),
+            None => Ok(false),
+        };
+    }
+
+    let code_hash = hash_token(&normalize_recovery_code(code));
+    let used = cfg.two_factor.use_recovery_code(&two_factor.user_id, &code_hash).await?;
+    if used {
+        info!("Recovery code used by user {}", two_factor.user_id);
+    }
+    Ok(used)
+}
+
+// Users only, API keys have no second factor.
+async fn current_user(cfg: &AppStates, principal: &Principal) -> Result<User, ApiErrorType> {
+    let user_id = principal.user_id().ok_or(ApiErrorType::AuthorizationError)?;
+    match cfg.users.find(&UserKey::parse(user_id.to_owned())).await? {
+        Some(user) => Ok(user),
+        None => Err(ApiErrorType::AuthenticationError),
+    }
+}
+
+/// Starts enrolling the caller in two-factor authentication with a new TOTP secret.
+/// Returns the `otpauth://` URL as a QR code to scan with an authenticator app.
+/// Enrolling again before confirming replaces the secret.
+#[post("/me/two_factor/enroll")]
+async fn enroll_two_factor(
+    cfg: web::Data<AppStates>,
+    jwt: web::Data<JwtConfig>,
+    principal: Principal
+) -> Result<HttpResponse, ApiErrorType> {
+    let user = current_user(&cfg, &principal).await?;
+    if cfg.two_factor.find(&user.id).await?.is_some_and(|two_factor| two_factor.enabled) {
+        return Err(ApiErrorType::Conflict);
+    }
+
+    let secret = generate_secret();
+    let otpauth_url = provisioning_url(&secret, &jwt.issuer, &user.username).map_err(|err| {
+        error!("Error: {:?}", err);
+        ApiErrorType::InternalServerError
+    })?;
+    cfg.two_factor.save(TwoFactor {
+        user_id: user.id,
+        secret: secret.clone(),
+        enabled: false,
+        recovery_code_hashes: Vec::new(),
+        last_used_step: 0,
+        failed_attempts: 0,
+        locked_until: 0,
+        created_at: Utc::now(),
+    }).await?;
+
+    let svg = generate_svg_string(&otpauth_url);
+    Ok(HttpResponse::Ok().json(EnrollmentResponse { secret, otpauth_url, svg }))
+}
+
+/// Confirms the enrollment with a code from the authenticator app and turns two-factor
+/// authentication on. Returns the recovery codes, which are not shown again.
+#[post("/me/two_factor/confirm")]
+async fn confirm_two_factor(
+    cfg: web::Data<AppStates>,
+    principal: Principal,
+    json: ValidatedJson<CodePayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    let user = current_user(&cfg, &principal).await?;
+    let mut two_factor = match cfg.two_factor.find(&user.id).await? {
+        Some(two_factor) if two_factor.enabled => {
+            return Err(ApiErrorType::Conflict);
+        }
+        Some(two_factor) => two_factor,
+        None => {
+            return Err(ApiErrorType::NotFound);
+        }
+    };
+    let step = match verify_code(&two_factor.secret, json.into_inner().code.trim()) {
+        Some(step) => step,
+        None => {
+            return Err(ApiErrorType::InvalidCredential);
+        }
+    };
+
+    let recovery_codes = generate_recovery_codes();
+    two_factor.enabled = true;
+    two_factor.last_used_step = step;
+    two_factor.recovery_code_hashes = recovery_codes
+        .iter()
+        .map(|code| hash_token(&normalize_recovery_code(code)))
+        .collect();
+    cfg.two_factor.save(two_factor).await?;
+    info!("Two-factor authentication enabled for user {}", user.id);
+
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Turns two-factor authentication off for a user that lost their authenticator
+/// and recovery codes. They can enroll again after logging in with their password.
+/// Registered in `main`, guarded by `Permission::UsersAdmin`.
+pub async fn reset_two_factor(
+    cfg: web::Data<AppStates>,
+    user: web::Path<String>
+) -> Result<HttpResponse, ApiErrorType> {
+    let user = match cfg.users.find(&UserKey::parse(user.into_inner())).await? {
+        Some(user) => user,
+        None => {
+            return Err(ApiErrorType::UserNotFound);
+        }
+    };
+    if cfg.two_factor.delete(&user.id).await? {
+        info!("Two-factor authentication reset for user {}", user.id);
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ApiErrorType::NotFound)
+    }
+}
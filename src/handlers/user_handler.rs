@@ -1,10 +1,21 @@
-use actix_web::{ delete, get, post, put, web, HttpResponse, Responder };
-use mongodb::{ bson::doc, Collection };
-use log::error;
+use actix_web::{ web, HttpResponse };
+use log::warn;
 use serde::{ Deserialize, Serialize };
-use futures::stream::TryStreamExt;
 
-use crate::{ configs::db::AppStates, constants, models::user_model::User };
+use crate::{
+    auth::{ jwt::JwtConfig, rbac::Principal },
+    configs::{ db::AppStates, mail::MailConfig },
+    constants,
+    extractors::validated_json::ValidatedJson,
+    mailers::mailer::Mailer,
+    models::{
+        error_model::ApiErrorType,
+        role_model::RolePayload,
+        user_model::{ User, UserPayload },
+    },
+    repositories::user_repository::UserKey,
+};
+use super::account_handler::send_verification_email;
 
 #[derive(Deserialize)]
 pub enum OrderQuery {
@@ -38,123 +49,303 @@ pub struct ResultData {
     per_page: i64,
 }
 
-/// Adds a new user to the "users" collection in the database.
-#[post("/add_user")]
-async fn add_user(cfg: web::Data<AppStates>, json: web::Json<User>) -> HttpResponse {
-    let collection: Collection<User> = cfg.mongo_db.collection("users");
-    let result = collection.insert_one(json.into_inner()).await;
-    match result {
-        Ok(_) => HttpResponse::Ok().body("user added"),
-        Err(err) => {
-            error!("Error: {}", err);
-            HttpResponse::InternalServerError().body(err.to_string())
-        }
+// The handlers below are registered in `main`, next to the permission each route requires.
+
+/// Adds a new user to the configured user repository and returns it with its generated id.
+/// The user gets a mail to verify its email address.
+pub async fn add_user(
+    cfg: web::Data<AppStates>,
+    mailer: web::Data<dyn Mailer>,
+    mail: web::Data<MailConfig>,
+    jwt: web::Data<JwtConfig>,
+    json: ValidatedJson<UserPayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    let user = User::new(json.into_inner());
+    cfg.users.insert(user.clone(), None).await?;
+    if send_verification_email(mailer.get_ref(), &mail, &jwt, &user).await.is_err() {
+        warn!("Verification email for user {} not sent", user.id);
     }
+    Ok(HttpResponse::Ok().json(user))
 }
 
-/// Gets the user with the supplied username.
-#[get("/user/{username}")]
-async fn get_user(cfg: web::Data<AppStates>, username: web::Path<String>) -> HttpResponse {
-    let username = username.into_inner();
-    let collection: Collection<User> = cfg.mongo_db.collection("users");
-    match collection.find_one(doc! { "username": &username }).await {
-        Ok(Some(user)) => HttpResponse::Ok().json(user),
-        Ok(None) => {
-            HttpResponse::NotFound().body(format!("No user found with username {username}"))
-        }
-        Err(err) => {
-            error!("Error: {}", err);
-            HttpResponse::InternalServerError().body(err.to_string())
-        }
+/// Gets the user with the supplied id or username.
+pub async fn get_user(
+    cfg: web::Data<AppStates>,
+    user: web::Path<String>
+) -> Result<HttpResponse, ApiErrorType> {
+    match cfg.users.find(&UserKey::parse(user.into_inner())).await? {
+        Some(user) => Ok(HttpResponse::Ok().json(user)),
+        None => Err(ApiErrorType::UserNotFound),
     }
 }
 
-#[get("/users")]
-async fn get_users(
+// Also gets its own `QueryConfig` in `main`.
+pub async fn get_users(
     cfg: web::Data<AppStates>,
-    query: Option<web::Query<ListQuery>>
-) -> impl Responder {
-    let query = query.unwrap();
-    let search = query.search.clone().unwrap_or_else(|| "".to_string());
+    query: web::Query<ListQuery>
+) -> Result<HttpResponse, ApiErrorType> {
+    let search = query.search.clone().unwrap_or_default();
     let per_page = query.per_page.unwrap_or(10);
     let page = query.page.unwrap_or(1);
 
     let limit = per_page.try_into().unwrap_or(constants::DEFAULT_LIMIT_SIZE);
     let offset = ((page - 1) * per_page).try_into().unwrap_or(constants::DEFAULT_OFFSET_SIZE);
-    let collection: Collection<User> = cfg.mongo_db.collection("users");
 
-    let mut cursor = collection
-        .find(doc! { "username": {"$regex": search, "$options": "i"} })
-        .limit(limit.try_into().unwrap())
-        .skip(offset.try_into().unwrap()).await
-        .expect("Failed to execute find.");
-
-    let count = collection.count_documents(doc! {}).await.unwrap();
-
-    let mut users: Vec<User> = Vec::new();
-    while let Some(result) = cursor.try_next().await.expect("Failed to fetch next document.") {
-        users.push(result);
-    }
+    let users = cfg.users.list(&search, limit, offset).await?;
+    let count = cfg.users.count().await?;
 
     let data = ResultData {
         data: users,
         total: count,
-        page: page,
-        per_page: per_page,
+        page,
+        per_page,
     };
 
-    HttpResponse::Ok().json(data)
+    Ok(HttpResponse::Ok().json(data))
 }
 
-#[put("/user/{username}")]
-async fn update_user(
-    cfg: web::Data<AppStates>,
-    username: web::Path<String>,
-    json: web::Json<User>
-) -> HttpResponse {
-    let username = username.into_inner();
-    if username.is_empty() {
-        return HttpResponse::BadRequest().body("Invalid username");
-    }
-    let collection: Collection<User> = cfg.mongo_db.collection("users");
-    let result = collection.find_one_and_update(
-        doc! { "username": &username },
-        doc! {"$set":{
-                        "first_name": json.first_name.to_owned(),
-                        "last_name": json.last_name.to_owned(),
-                        "username": json.username.to_owned(),
-                        "email": json.email.to_owned(),
-                     }}
-    ).await;
-
-    match result {
-        Ok(Some(_)) => HttpResponse::Ok().body(format!("success update user")),
-        Ok(None) => HttpResponse::NotFound().body(format!("User {username} not found!")),
-        Err(err) => {
-            error!("Error: {}", err);
-            HttpResponse::InternalServerError().body(err.to_string())
+/// Finds the user `user` refers to, failing unless `principal` may modify it.
+async fn find_modifiable(
+    cfg: &AppStates,
+    principal: &Principal,
+    user: String
+) -> Result<User, ApiErrorType> {
+    if user.is_empty() {
+        return Err(ApiErrorType::BadRequest);
+    }
+    match cfg.users.find(&UserKey::parse(user)).await? {
+        Some(target) => {
+            principal.require_self_or_admin(&target)?;
+            Ok(target)
         }
+        None => Err(ApiErrorType::UserNotFound),
     }
 }
 
-#[delete("/user/{username}")]
-async fn delete_user(cfg: web::Data<AppStates>, username: web::Path<String>) -> HttpResponse {
-    let username = username.into_inner();
-    if username.is_empty() {
-        return HttpResponse::BadRequest().body("Invalid username");
-    }
-    let collection: Collection<User> = cfg.mongo_db.collection("users");
-    let result = collection.delete_one(doc! { "username": &username }).await;
-    match result {
-        Ok(res) => {
-            if res.deleted_count == 1 {
-                HttpResponse::Ok().body(format!("User {username} has been deleted!"));
-            }
-            HttpResponse::NotFound().body(format!("User {username} not found!"))
+/// Updates a user. Only admins may update other users than themselves.
+/// A new email address has to be verified again.
+pub async fn update_user(
+    cfg: web::Data<AppStates>,
+    mailer: web::Data<dyn Mailer>,
+    mail: web::Data<MailConfig>,
+    jwt: web::Data<JwtConfig>,
+    principal: Principal,
+    user: web::Path<String>,
+    json: ValidatedJson<UserPayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    let target = find_modifiable(&cfg, &principal, user.into_inner()).await?;
+    let key = UserKey::parse(target.id);
+    let updated = match cfg.users.update(&key, json.into_inner()).await? {
+        Some(updated) => updated,
+        None => {
+            return Err(ApiErrorType::UserNotFound);
         }
-        Err(err) => {
-            error!("Error: {}", err);
-            HttpResponse::InternalServerError().body(err.to_string())
+    };
+    if updated.email != target.email {
+        cfg.users.set_email_verified(&key, &updated.email, false).await?;
+        if send_verification_email(mailer.get_ref(), &mail, &jwt, &updated).await.is_err() {
+            warn!("Verification email for user {} not sent", updated.id);
         }
     }
+    Ok(HttpResponse::Ok().body("success update user"))
+}
+
+/// Deletes a user. Only admins may delete other users than themselves.
+pub async fn delete_user(
+    cfg: web::Data<AppStates>,
+    principal: Principal,
+    user: web::Path<String>
+) -> Result<HttpResponse, ApiErrorType> {
+    let user = user.into_inner();
+    let target = find_modifiable(&cfg, &principal, user.clone()).await?;
+    if cfg.users.delete(&UserKey::parse(target.id)).await? {
+        Ok(HttpResponse::Ok().body(format!("User {user} has been deleted!")))
+    } else {
+        Err(ApiErrorType::UserNotFound)
+    }
+}
+
+/// Assigns a role to a user and returns the updated user.
+pub async fn set_user_role(
+    cfg: web::Data<AppStates>,
+    user: web::Path<String>,
+    json: web::Json<RolePayload>
+) -> Result<HttpResponse, ApiErrorType> {
+    match cfg.users.set_role(&UserKey::parse(user.into_inner()), json.into_inner().role).await? {
+        Some(user) => Ok(HttpResponse::Ok().json(user)),
+        None => Err(ApiErrorType::UserNotFound),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_http::Request;
+    use actix_web::{
+        body::MessageBody,
+        dev::{ Service, ServiceResponse },
+        http::{ header, StatusCode },
+        middleware::from_fn,
+        test,
+        App,
+        Error,
+    };
+    use chrono::{ DateTime, Duration, Utc };
+    use serde_json::{ json, Value };
+    use uuid::Uuid;
+
+    use crate::{
+        auth::jwt::jwt_auth,
+        configs::settings::{ JwtSettings, MailSettings },
+        models::role_model::Role,
+        test_support::{ create_user, memory_states, RecordingMailer },
+    };
+    use super::*;
+
+    // The user routes without their permission guards.
+    async fn app(
+        states: web::Data<AppStates>,
+        jwt: web::Data<JwtConfig>
+    ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+        let mailer: Arc<dyn Mailer> = Arc::new(RecordingMailer::default());
+        test::init_service(
+            App::new()
+                .app_data(states)
+                .app_data(jwt)
+                .app_data(web::Data::new(MailConfig::new(&MailSettings::default())))
+                .app_data(web::Data::from(mailer))
+                .wrap(from_fn(jwt_auth))
+                .route("/add_user", web::post().to(add_user))
+                .service(
+                    web
+                        ::resource("/user/{user}")
+                        .route(web::get().to(get_user))
+                        .route(web::put().to(update_user))
+                )
+        ).await
+    }
+
+    fn payload(username: &str) -> Value {
+        json!({
+            "first_name": "hello",
+            "last_name": "world",
+            "username": username,
+            "email": format!("{username}@example.com"),
+        })
+    }
+
+    fn get(user: &str) -> Request {
+        test::TestRequest::get().uri(&format!("/user/{user}")).to_request()
+    }
+
+    fn timestamp(user: &Value, field: &str) -> DateTime<Utc> {
+        user[field].as_str().unwrap().parse().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn add_user_ignores_client_ids_and_timestamps() {
+        let states = web::Data::new(memory_states());
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        let app = app(states, jwt).await;
+        let mut body = payload("hello");
+        body["id"] = json!("my-id");
+        body["created_at"] = json!("2000-01-01T00:00:00Z");
+
+        let request = test::TestRequest::post().uri("/add_user").set_json(body).to_request();
+        let user: Value = test::call_and_read_body_json(&app, request).await;
+
+        assert!(Uuid::parse_str(user["id"].as_str().unwrap()).is_ok());
+        assert_eq!(timestamp(&user, "created_at"), timestamp(&user, "updated_at"));
+        assert!(timestamp(&user, "created_at") > Utc::now() - Duration::minutes(1));
+    }
+
+    #[actix_web::test]
+    async fn users_are_found_by_id_and_username() {
+        let states = web::Data::new(memory_states());
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        let user = create_user(&states, "hello", Role::Viewer).await;
+        let app = app(states, jwt).await;
+
+        for key in [user.id.as_str(), "hello"] {
+            let found: Value = test::call_and_read_body_json(&app, get(key)).await;
+            assert_eq!(found["id"], user.id.as_str());
+        }
+    }
+
+    #[actix_web::test]
+    async fn update_keeps_id_and_created_at() {
+        let states = web::Data::new(memory_states());
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        let user = create_user(&states, "hello", Role::Viewer).await;
+        let token = jwt.issue(&user.id, &user.role.scopes()).unwrap();
+        let app = app(states, jwt).await;
+
+        let request = test::TestRequest
+            ::put()
+            .uri(&format!("/user/{}", user.id))
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .set_json(payload("renamed"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let updated: Value = test::call_and_read_body_json(&app, get(&user.id)).await;
+        assert_eq!(updated["username"], "renamed");
+        assert_eq!(timestamp(&updated, "created_at"), user.created_at);
+        assert!(timestamp(&updated, "updated_at") > user.updated_at);
+        // the old username no longer finds it
+        let response = test::call_service(&app, get("hello")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    async fn error_body(response: ServiceResponse<impl MessageBody>) -> Value {
+        let content_type = response.headers().get(header::CONTENT_TYPE).unwrap();
+        assert_eq!(content_type, "application/json");
+        test::read_body_json(response).await
+    }
+
+    #[actix_web::test]
+    async fn duplicate_username_is_a_conflict() {
+        let states = web::Data::new(memory_states());
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        create_user(&states, "hello", Role::Viewer).await;
+        let app = app(states.clone(), jwt).await;
+
+        let request = test::TestRequest
+            ::post()
+            .uri("/add_user")
+            .set_json(payload("hello"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = error_body(response).await;
+        assert_eq!(body["status"], 409);
+        assert_eq!(body["message"], "Conflict with an existing resource.");
+        assert_eq!(states.users.count().await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn unknown_user_is_not_found() {
+        let states = web::Data::new(memory_states());
+        let jwt = web::Data::new(JwtConfig::new(&JwtSettings::default()));
+        let user = create_user(&states, "hello", Role::Admin).await;
+        let token = jwt.issue(&user.id, &user.role.scopes()).unwrap();
+        let app = app(states, jwt).await;
+
+        let response = test::call_service(&app, get("nobody")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_body(response).await["status"], 404);
+
+        let request = test::TestRequest
+            ::put()
+            .uri(&format!("/user/{}", Uuid::new_v4()))
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .set_json(payload("renamed"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_body(response).await["message"], "User not found for the given ID");
+    }
 }
@@ -1,9 +1,19 @@
-use std::env;
-
 use actix_files::NamedFile;
 use actix_http::StatusCode;
 use actix_session::Session;
-use actix_web::{ get, http::header::ContentType, HttpRequest, HttpResponse, Responder, Result };
+use actix_web::{
+    get,
+    http::header::ContentType,
+    web,
+    HttpRequest,
+    HttpResponse,
+    Responder,
+    Result,
+};
+
+use log::debug;
+
+use crate::configs::settings::Settings;
 
 /// favicon handler
 #[get("/favicon")]
@@ -13,20 +23,26 @@ async fn favicon() -> Result<impl Responder> {
 
 /// simple index handler
 #[get("/welcome")]
-async fn welcome(req: HttpRequest, session: Session) -> Result<HttpResponse> {
-    println!("{req:?}");
+async fn welcome(
+    req: HttpRequest,
+    session: Session,
+    settings: web::Data<Settings>
+) -> Result<HttpResponse> {
+    debug!("{} {}", req.method(), req.path());
 
     // session
     let mut counter = 1;
     if let Some(count) = session.get::<i32>("counter")? {
-        println!("SESSION value: {count}");
+        debug!("SESSION value: {count}");
         counter = count + 1;
     }
 
     // set counter to session
     session.insert("counter", counter)?;
-    let static_path = env::var("PATH_STATIC").expect("PATH_STATIC Not set");
-
     // response
-    Ok(HttpResponse::build(StatusCode::OK).content_type(ContentType::html()).body(static_path))
+    Ok(
+        HttpResponse::build(StatusCode::OK)
+            .content_type(ContentType::html())
+            .body(settings.server.static_path.clone())
+    )
 }
@@ -0,0 +1,39 @@
+use std::{ fs, io, path::{ Path, PathBuf } };
+
+use async_trait::async_trait;
+use lettre::{ message::Mailbox, AsyncFileTransport, AsyncTransport, Tokio1Executor };
+use log::info;
+
+use super::mailer::{ Email, MailError, Mailer };
+
+/// Writes each email to an `.eml` file instead of sending it.
+/// Meant for tests and local development.
+pub struct FileMailer {
+    from: Mailbox,
+    dir: PathBuf,
+    transport: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileMailer {
+    /// Creates `dir` if it does not exist yet.
+    pub fn new(dir: impl AsRef<Path>, from: Mailbox) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(FileMailer {
+            from,
+            transport: AsyncFileTransport::new(&dir),
+            dir,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        let to = email.to.clone();
+        let message = email.into_message(&self.from)?;
+        let id = self.transport.send(message).await.map_err(MailError::new)?;
+        info!("Mail to {} written to {}", to, self.dir.join(format!("{id}.eml")).display());
+        Ok(())
+    }
+}
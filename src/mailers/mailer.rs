@@ -0,0 +1,54 @@
+use async_trait::async_trait;
+use derive_more::{ Display, Error };
+use lettre::{ message::{ header::ContentType, Mailbox }, Message };
+use log::error;
+
+use crate::models::error_model::ApiErrorType;
+
+#[derive(Debug, Display, Error)]
+#[display("{message}")]
+pub struct MailError {
+    message: String,
+}
+
+impl MailError {
+    pub fn new(message: impl ToString) -> Self {
+        MailError { message: message.to_string() }
+    }
+}
+
+// Delivery details are logged, never sent to the client.
+impl From<MailError> for ApiErrorType {
+    fn from(err: MailError) -> Self {
+        error!("Error: {}", err);
+        ApiErrorType::InternalServerError
+    }
+}
+
+/// A plain text email to a single recipient.
+#[derive(Clone, Debug)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    /// Builds the MIME message sent from `from`.
+    pub fn into_message(self, from: &Mailbox) -> Result<Message, MailError> {
+        let to: Mailbox = self.to.parse().map_err(MailError::new)?;
+        Message::builder()
+            .from(from.clone())
+            .to(to)
+            .subject(self.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(self.body)
+            .map_err(MailError::new)
+    }
+}
+
+/// Sends emails, so handlers don't depend on how they are delivered.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), MailError>;
+}
@@ -0,0 +1,3 @@
+pub mod mailer;
+pub mod smtp_mailer;
+pub mod file_mailer;
@@ -3,16 +3,28 @@ mod configs;
 mod models;
 mod handlers;
 mod constants;
+mod repositories;
+mod extractors;
+mod middlewares;
+mod auth;
+mod mailers;
+mod sessions;
+mod commands;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
 
-use std::{ convert::Infallible, env, io };
+use std::{ convert::Infallible, io, process, sync::Arc, time::Duration };
+use anyhow::Context as _;
 use actix_cors::Cors;
 use actix_files::{ Files, NamedFile };
-use actix_session::{ storage::CookieSessionStore, SessionMiddleware };
+use actix_session::storage::CookieSessionStore;
 use actix_web::{
     error::{ self, Error, InternalError, JsonPayloadError },
     http::{ header::{ self, ContentType }, Method, StatusCode },
-    middleware::{ Compress, Logger },
-    web::{ self, scope, JsonConfig },
+    middleware::{ from_fn, Compress, Logger },
+    rt,
+    web::{ self, scope, FormConfig, JsonConfig, PathConfig, QueryConfig },
     App,
     Either,
     HttpRequest,
@@ -22,31 +34,107 @@ use actix_web::{
     Result,
 };
 use chrono::{ SecondsFormat, Utc };
+use serde::de::IgnoredAny;
 use dotenvy::dotenv;
-use log::info;
-use models::{ error_model::ApiError, user_model::User };
+use log::{ debug, error, info, warn };
+use models::{ error_model::ApiError, role_model::Permission, user_model::User };
+use constants::{ CSRF_HEADER, REQUEST_ID_HEADER };
+use extractors::error_handlers::{ form_error_handler, path_error_handler, query_error_handler };
+use middlewares::{
+    csrf::csrf_protect,
+    database_ready::require_database,
+    error_format::{ error_format, wants_problem },
+    metrics::track_requests,
+    request_id::request_id,
+    session_activity::session_activity,
+    session_keys::rotate_session_keys,
+    trace::trace_requests,
+};
+use auth::{ jwt::{ jwt_auth, JwtConfig }, rbac::require };
 use handlers::{
-    qr_handler::{ generate_qr, get_svg },
-    user_handler::{ add_user, delete_user, get_user, get_users, update_user },
+    account_handler::{ forgot_password, resend_verification_email, reset_password, verify_email },
+    api_key_handler::{ create_api_key, list_api_keys, revoke_api_key },
+    auth_handler::{ get_csrf_token, login, login_two_factor, logout, me, refresh, register },
+    health_handler::{ live, ready },
+    metrics_handler::metrics,
+    qr_handler::{ generate_qr, get_svg, Info },
+    session_handler::{ delete_all_sessions, delete_session, list_sessions },
+    two_factor_handler::{ confirm_two_factor, enroll_two_factor, reset_two_factor },
+    user_handler::{
+        add_user,
+        delete_user,
+        get_user,
+        get_users,
+        set_user_role,
+        update_user,
+        ListQuery,
+    },
     welcome_handler::{ favicon, welcome },
 };
-use mongodb::{ bson::doc, options::IndexOptions, Client, IndexModel };
-use configs::db::{ init, AppStates };
+use mongodb::{ bson::{ doc, Document }, options::IndexOptions, Client, Database, IndexModel };
+use configs::{
+    db::{ connect, init, next_backoff, ping_mongo, wait_for_mongo, AppStates, DbBackend, DbStatus },
+    mail::{ init_mailer, MailConfig },
+    migrations::run_migrations,
+    session::{ SessionBackend, SessionConfig },
+    settings::{ DatabaseSettings, LogFormat, Settings },
+};
+use deadpool_postgres::Pool;
+use repositories::{
+    memory_api_key_repository::MemoryApiKeyRepository,
+    memory_refresh_token_repository::MemoryRefreshTokenRepository,
+    memory_two_factor_repository::MemoryTwoFactorRepository,
+    memory_used_token_repository::MemoryUsedTokenRepository,
+    memory_user_repository::MemoryUserRepository,
+    mongo_api_key_repository::{ MongoApiKeyRepository, API_KEY_COLLECTION },
+    mongo_refresh_token_repository::{ MongoRefreshTokenRepository, REFRESH_TOKEN_COLLECTION },
+    mongo_two_factor_repository::{ MongoTwoFactorRepository, TWO_FACTOR_COLLECTION },
+    mongo_used_token_repository::{ MongoUsedTokenRepository, USED_TOKEN_COLLECTION },
+    mongo_user_repository::{ backfill_users, MongoUserRepository },
+    postgres_api_key_repository::PostgresApiKeyRepository,
+    postgres_refresh_token_repository::PostgresRefreshTokenRepository,
+    postgres_two_factor_repository::PostgresTwoFactorRepository,
+    postgres_used_token_repository::PostgresUsedTokenRepository,
+    postgres_user_repository::PostgresUserRepository,
+    traced_user_repository::TracedUserRepository,
+};
+use sessions::{
+    app_session_store::AppSessionStore,
+    mongo_session_store::{ MongoSessionStore, SESSION_COLLECTION },
+    session_repository::SessionRepository,
+};
+use telemetry::{ logging::{ current_request_id, init_logger }, trace::init_tracer };
 use async_stream::stream;
+use clap::Parser;
+use commands::{
+    cli::{ Cli, Command, ServeArgs },
+    create_admin::create_admin,
+    seed::seed,
+};
 
-// NOTE: Not a suitable session key for production.
-static SESSION_SIGNING_KEY: &[u8] = &[0; 64];
-
-async fn default_handler(req_method: Method) -> Result<impl Responder> {
-    match req_method {
-        Method::GET => {
+async fn default_handler(req: HttpRequest) -> Result<impl Responder> {
+    let (status, message) = match *req.method() {
+        Method::GET if !wants_problem(&req) => {
             let file = NamedFile::open("static/404.html")?
                 .customize()
                 .with_status(StatusCode::NOT_FOUND);
-            Ok(Either::Left(file))
+            return Ok(Either::Left(file));
         }
-        _ => Ok(Either::Right(HttpResponse::MethodNotAllowed().finish())),
-    }
+        Method::GET => (StatusCode::NOT_FOUND, "Not found"),
+        _ => (StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+    };
+    Ok(
+        Either::Right(
+            (ApiError {
+                status: status.as_u16(),
+                time: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+                message: message.to_owned(),
+                debug_message: Some(format!("No route for {} {}", req.method(), req.path())),
+                sub_errors: Vec::new(),
+                request_id: current_request_id(),
+            }).into_response()
+        )
+    )
 }
 
 async fn streaming_response(path: web::Path<String>) -> HttpResponse {
@@ -64,17 +152,285 @@ async fn streaming_response(path: web::Path<String>) -> HttpResponse {
 }
 
 /// Creates an index on the "username" field to force the values to be unique.
-async fn create_username_index(client: &Client) {
+async fn create_username_index(db: &Database, collection: &str) -> mongodb::error::Result<()> {
     let options = IndexOptions::builder().unique(true).build();
     let model = IndexModel::builder()
         .keys(doc! { "username": 1 })
         .options(options)
         .build();
-    client
-        .database(&std::env::var("DB_NAME").unwrap_or_else(|_| "myApp".into()))
-        .collection::<User>(&std::env::var("COLL_NAME").unwrap_or_else(|_| "users".into()))
-        .create_index(model).await
-        .expect("creating an index should succeed");
+    db
+        .collection::<User>(collection)
+        .create_index(model).await?;
+    Ok(())
+}
+
+/// Creates a TTL index on "expires_at", so MongoDB deletes expired refresh tokens,
+/// and a unique index on "token_hash" for lookups.
+async fn create_refresh_token_index(db: &Database) -> mongodb::error::Result<()> {
+    let ttl = IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build();
+    let token_hash = IndexModel::builder()
+        .keys(doc! { "token_hash": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    db
+        .collection::<Document>(REFRESH_TOKEN_COLLECTION)
+        .create_indexes([ttl, token_hash]).await?;
+    Ok(())
+}
+
+/// Creates an index on the "key_hash" field, so API keys are looked up fast and never collide.
+async fn create_api_key_index(db: &Database) -> mongodb::error::Result<()> {
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "key_hash": 1 })
+        .options(options)
+        .build();
+    db
+        .collection::<Document>(API_KEY_COLLECTION)
+        .create_index(model).await?;
+    Ok(())
+}
+
+/// Creates an index on the "user_id" field, so each user has one set of two-factor settings.
+async fn create_two_factor_index(db: &Database) -> mongodb::error::Result<()> {
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "user_id": 1 })
+        .options(options)
+        .build();
+    db
+        .collection::<Document>(TWO_FACTOR_COLLECTION)
+        .create_index(model).await?;
+    Ok(())
+}
+
+/// Creates a TTL index on "expires_at", so MongoDB forgets used tokens once they expired,
+/// and a unique index on "jti", so a token can't be used twice.
+async fn create_used_token_index(db: &Database) -> mongodb::error::Result<()> {
+    let ttl = IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build();
+    let jti = IndexModel::builder()
+        .keys(doc! { "jti": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    db
+        .collection::<Document>(USED_TOKEN_COLLECTION)
+        .create_indexes([ttl, jti]).await?;
+    Ok(())
+}
+
+/// Creates a TTL index on "expires_at", so MongoDB deletes expired sessions,
+/// a unique index on "key_hash" for lookups
+/// and an index on "user_id" for listing a user's sessions.
+async fn create_session_index(db: &Database) -> mongodb::error::Result<()> {
+    let ttl = IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build();
+    let key_hash = IndexModel::builder()
+        .keys(doc! { "key_hash": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    let user_id = IndexModel::builder()
+        .keys(doc! { "user_id": 1 })
+        .build();
+    db
+        .collection::<Document>(SESSION_COLLECTION)
+        .create_indexes([ttl, key_hash, user_id]).await?;
+    Ok(())
+}
+
+/// Applies pending PostgreSQL migrations. A failed migration is rolled back.
+async fn migrate(pool: &Pool) -> anyhow::Result<()> {
+    let applied = run_migrations(pool).await.context("Error applying PostgreSQL migrations")?;
+    info!("Applied {} database migration(s)", applied);
+    Ok(())
+}
+
+/// Creates the PostgreSQL pool and applies the migrations if `migrate_on_startup` is set.
+async fn start_postgres(settings: &DatabaseSettings) -> anyhow::Result<Pool> {
+    let pool = connect(settings).context("Error creating PostgreSQL connection pool")?;
+    if settings.migrate_on_startup {
+        migrate(&pool).await?;
+    }
+    Ok(pool)
+}
+
+/// Creates an index on the "id" field, so lookups by id are fast and ids stay unique.
+async fn create_id_index(db: &Database, collection: &str) -> mongodb::error::Result<()> {
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "id": 1 })
+        .options(options)
+        .build();
+    db
+        .collection::<User>(collection)
+        .create_index(model).await?;
+    Ok(())
+}
+
+/// Backfills old user documents, then creates the indexes of every MongoDB collection
+/// but the sessions.
+async fn create_indexes(db: &Database, settings: &DatabaseSettings) -> mongodb::error::Result<()> {
+    let backfilled = backfill_users(db, &settings.users_collection).await?;
+    if backfilled > 0 {
+        info!("Gave {} old user document(s) an id and timestamps", backfilled);
+    }
+    create_username_index(db, &settings.users_collection).await?;
+    create_id_index(db, &settings.users_collection).await?;
+    create_refresh_token_index(db).await?;
+    create_api_key_index(db).await?;
+    create_two_factor_index(db).await?;
+    create_used_token_index(db).await
+}
+
+/// Creates the MongoDB indexes the configuration needs.
+async fn setup_mongo(
+    db: &Database,
+    settings: &DatabaseSettings,
+    sessions: bool
+) -> mongodb::error::Result<()> {
+    if settings.backend == DbBackend::Mongo {
+        create_indexes(db, settings).await?;
+    }
+    if sessions {
+        create_session_index(db).await?;
+    }
+    Ok(())
+}
+
+/// Connects MongoDB and sets it up, with the session indexes when `sessions` is set.
+/// Exits when MongoDB stays unreachable after the startup retries. With
+/// `database.mongo.degraded_start` it returns anyway, keeps connecting in the background
+/// and marks `status` ready once it succeeded.
+async fn start_mongo(
+    settings: &DatabaseSettings,
+    sessions: bool,
+    status: &Arc<DbStatus>
+) -> Database {
+    let client = exit_on_error(init(settings).await);
+    let db = client.database(&settings.name);
+    let started = match wait_for_mongo(&client, &settings.mongo).await {
+        Ok(()) => setup_mongo(&db, settings, sessions).await,
+        Err(err) => Err(err),
+    };
+    match started {
+        Ok(()) => status.set_ready(true),
+        Err(err) if settings.mongo.degraded_start => {
+            warn!("Starting without MongoDB, connecting in the background: {}", err);
+            rt::spawn(reconnect_mongo(client, settings.clone(), sessions, status.clone()));
+        }
+        Err(err) => {
+            error!("Error connecting to MongoDB: {}", err);
+            process::exit(1);
+        }
+    }
+    db
+}
+
+// Sets MongoDB up once it answers, for a server started in degraded mode.
+async fn reconnect_mongo(
+    client: Client,
+    settings: DatabaseSettings,
+    sessions: bool,
+    status: Arc<DbStatus>
+) {
+    let db = client.database(&settings.name);
+    let mut backoff = Duration::from_secs(settings.mongo.retry_backoff_seconds);
+    loop {
+        rt::time::sleep(backoff).await;
+        let started = match ping_mongo(&client).await {
+            Ok(()) => setup_mongo(&db, &settings, sessions).await,
+            Err(err) => Err(err),
+        };
+        match started {
+            Ok(()) => {
+                status.set_ready(true);
+                info!("Connected to MongoDB, leaving degraded mode");
+                return;
+            }
+            Err(err) => {
+                warn!("MongoDB is still not reachable: {}", err);
+                backoff = next_backoff(backoff);
+            }
+        }
+    }
+}
+
+/// Builds the repositories for the backend selected with `database.backend`.
+/// MongoDB is also connected for `SESSION_BACKEND=mongo`.
+async fn init_app_states(
+    settings: &DatabaseSettings,
+    sessions: SessionBackend
+) -> anyhow::Result<AppStates> {
+    let db_status = Arc::new(DbStatus::default());
+    let mongo_sessions = sessions == SessionBackend::Mongo;
+    let mongo = if settings.backend == DbBackend::Mongo || mongo_sessions {
+        Some(start_mongo(settings, mongo_sessions, &db_status).await)
+    } else {
+        db_status.set_ready(true);
+        None
+    };
+    let states = match settings.backend {
+        DbBackend::Mongo => {
+            let db = mongo.clone().expect("MongoDB is started for the mongo backend");
+            AppStates {
+                users: Arc::new(
+                    TracedUserRepository::new(
+                        Arc::new(MongoUserRepository::new(&db, &settings.users_collection)),
+                        "mongodb",
+                        &settings.users_collection
+                    )
+                ),
+                refresh_tokens: Arc::new(MongoRefreshTokenRepository::new(&db)),
+                api_keys: Arc::new(MongoApiKeyRepository::new(&db)),
+                two_factor: Arc::new(MongoTwoFactorRepository::new(&db)),
+                used_tokens: Arc::new(MongoUsedTokenRepository::new(&db)),
+                sessions: None,
+                mongo,
+                postgres: None,
+                db_status,
+            }
+        }
+        DbBackend::Postgres => {
+            let pool = start_postgres(settings).await?;
+            AppStates {
+                users: Arc::new(
+                    TracedUserRepository::new(
+                        Arc::new(PostgresUserRepository::new(pool.clone())),
+                        "postgresql",
+                        "users"
+                    )
+                ),
+                refresh_tokens: Arc::new(PostgresRefreshTokenRepository::new(pool.clone())),
+                api_keys: Arc::new(PostgresApiKeyRepository::new(pool.clone())),
+                two_factor: Arc::new(PostgresTwoFactorRepository::new(pool.clone())),
+                used_tokens: Arc::new(PostgresUsedTokenRepository::new(pool.clone())),
+                sessions: None,
+                mongo,
+                postgres: Some(pool),
+                db_status,
+            }
+        }
+        DbBackend::Memory =>
+            AppStates {
+                users: Arc::new(MemoryUserRepository::default()),
+                refresh_tokens: Arc::new(MemoryRefreshTokenRepository::default()),
+                api_keys: Arc::new(MemoryApiKeyRepository::default()),
+                two_factor: Arc::new(MemoryTwoFactorRepository::default()),
+                used_tokens: Arc::new(MemoryUsedTokenRepository::default()),
+                sessions: None,
+                mongo,
+                postgres: None,
+                db_status,
+            },
+    };
+    Ok(states)
 }
 
 // Handle json parser errors.
@@ -82,58 +438,150 @@ fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> Error {
     let detail = err.to_string();
     let resp = match &err {
         JsonPayloadError::ContentType =>
-            HttpResponse::UnsupportedMediaType().json(ApiError {
+            (ApiError {
                 status: 415,
                 time: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
                 message: "Unsupported media type".to_owned(),
                 debug_message: Some(detail),
                 sub_errors: Vec::new(),
-            }),
+                request_id: current_request_id(),
+            }).into_response(),
         JsonPayloadError::Deserialize(json_err) if json_err.is_data() => {
-            HttpResponse::UnprocessableEntity().json(ApiError {
+            (ApiError {
                 status: 422,
                 time: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
                 message: "Unprocessable payload".to_owned(),
                 debug_message: Some(detail),
                 sub_errors: Vec::new(),
-            })
+                request_id: current_request_id(),
+            }).into_response()
         }
         _ =>
-            HttpResponse::BadRequest().json(ApiError {
+            (ApiError {
                 status: 400,
                 time: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
                 message: "Bad request. Missing parameter and / or wrong payload.".to_owned(),
                 debug_message: Some(detail),
                 sub_errors: Vec::new(),
-            }),
+                request_id: current_request_id(),
+            }).into_response(),
     };
     InternalError::from_response(err, resp).into()
 }
 
+/// Sets up the schema of the configured backend: PostgreSQL migrations or MongoDB indexes.
+async fn migrate_schema(
+    settings: &DatabaseSettings,
+    sessions: SessionBackend
+) -> anyhow::Result<()> {
+    let mongo_sessions = sessions == SessionBackend::Mongo;
+    match settings.backend {
+        DbBackend::Postgres => {
+            let pool = connect(settings).context("Error creating PostgreSQL connection pool")?;
+            migrate(&pool).await?;
+        }
+        DbBackend::Memory if !mongo_sessions => {
+            info!("The memory backend has no schema to migrate");
+        }
+        _ => {}
+    }
+    if settings.backend == DbBackend::Mongo || mongo_sessions {
+        start_mongo(settings, mongo_sessions, &Arc::default()).await;
+        info!("Created the MongoDB indexes");
+    }
+    Ok(())
+}
+
+// Logs the error of a command and exits with status 1.
+fn exit_on_error<T>(result: anyhow::Result<T>) -> T {
+    result.unwrap_or_else(|err| {
+        error!("{:#}", err);
+        process::exit(1);
+    })
+}
+
 #[actix_web::main]
 async fn main() -> io::Result<()> {
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
-    // random key means that restarting server will invalidate existing session cookies
-    let key = actix_web::cookie::Key::from(SESSION_SIGNING_KEY);
     // Load .env file
     dotenv().ok();
-    // Initialize MongoDB connection
-    let client = init().await;
-    let db = client.database(&env::var("DB_NAME").unwrap_or_else(|_| "myApp".into()));
-    create_username_index(&client).await;
-    // let db_postgres = connect().await;
-
-    // Get Server host and port number from environment file.
-    let server_host = match env::var("SERVER.HOST") {
-        Ok(v) => v.to_string(),
-        Err(_) => "127.0.0.1".to_string(),
+    let cli = Cli::parse();
+    // the log format is configured, so the logger is set up once the settings are read
+    let settings = Settings::load();
+    init_logger(settings.as_ref().map_or(LogFormat::default(), |settings| settings.logging.format));
+    let mut settings = match settings {
+        Ok(settings) => settings,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
     };
+    for source in &settings.sources {
+        info!("Loaded configuration from {}", source.display());
+    }
+    info!("Using the {} profile", settings.profile.as_str());
+    if !matches!(cli.command, None | Some(Command::Serve(_))) {
+        // only the server waits for MongoDB in the background
+        settings.database.mongo.degraded_start = false;
+    }
 
-    let server_port: u16 = match env::var("SERVER.PORT") {
-        Ok(v) => v.parse().unwrap_or(8080),
-        Err(_) => 8080,
+    match cli.command.unwrap_or_else(|| Command::Serve(ServeArgs::default())) {
+        Command::Serve(args) => {
+            args.apply(&mut settings.server);
+            serve(settings).await
+        }
+        Command::Migrate => {
+            exit_on_error(migrate_schema(&settings.database, settings.session.backend).await);
+            Ok(())
+        }
+        Command::Seed(args) => {
+            let states = exit_on_error(
+                init_app_states(&settings.database, SessionBackend::Cookie).await
+            );
+            if settings.database.backend == DbBackend::Memory {
+                warn!("The memory backend forgets the seeded users when the command exits");
+            }
+            let inserted = exit_on_error(seed(&states, &args.file, args.format).await);
+            info!("Seeded {} user(s) from {}", inserted, args.file.display());
+            Ok(())
+        }
+        Command::CreateAdmin(args) => {
+            let states = exit_on_error(
+                init_app_states(&settings.database, SessionBackend::Cookie).await
+            );
+            let user = exit_on_error(create_admin(&states, args).await);
+            info!("Created admin {} with id {}", user.username, user.id);
+            Ok(())
+        }
+        Command::CheckConfig => {
+            // the settings are valid, this also checks the SMTP URL and the outbox
+            exit_on_error(init_mailer(&settings.mail));
+            info!("Configuration of the {} profile is valid", settings.profile.as_str());
+            Ok(())
+        }
+    }
+}
+
+/// Connects the storage and serves HTTP until the server is stopped.
+async fn serve(settings: Settings) -> io::Result<()> {
+    let tracer_provider = exit_on_error(init_tracer(&settings.tracing));
+    let session = web::Data::new(SessionConfig::new(&settings.session));
+    let mut states = exit_on_error(init_app_states(&settings.database, session.backend).await);
+    let mongo_sessions = match session.backend {
+        SessionBackend::Mongo => states.mongo.as_ref().map(MongoSessionStore::new),
+        SessionBackend::Cookie => None,
     };
-    info!("Starting actix-web server in {}:{}", server_host, server_port);
+    states.sessions = mongo_sessions
+        .clone()
+        .map(|store| Arc::new(store) as Arc<dyn SessionRepository>);
+    let states = web::Data::new(states);
+    let error_output = settings.server.error_format;
+    let jwt = web::Data::new(JwtConfig::new(&settings.jwt));
+    let mail = web::Data::new(MailConfig::new(&settings.mail));
+    let mailer = web::Data::from(exit_on_error(init_mailer(&settings.mail)));
+
+    let server = settings.server.clone();
+    let settings = web::Data::new(settings);
+    info!("Starting actix-web server in {}:{}", server.host, server.port);
 
     HttpServer::new(move || {
         // CORS
@@ -146,39 +594,158 @@ async fn main() -> io::Result<()> {
             .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
             .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT])
             .allowed_header(header::CONTENT_TYPE)
+            .allowed_header(CSRF_HEADER)
+            .allowed_header(REQUEST_ID_HEADER)
+            .expose_headers([REQUEST_ID_HEADER])
             .max_age(3600);
+        let session_store = match &mongo_sessions {
+            Some(store) => AppSessionStore::Mongo(store.clone()),
+            None => AppSessionStore::Cookie(CookieSessionStore::default()),
+        };
 
         App::new()
-            .app_data(
-                web::Data::new(AppStates {
-                    // postgres_db: db_postgres,
-                    // client_mongo: client.clone(),
-                    mongo_db: db.clone(),
-                })
-            )
+            .app_data(states.clone())
+            .app_data(web::Data::new(error_output))
+            .app_data(jwt.clone())
+            .app_data(mail.clone())
+            .app_data(mailer.clone())
+            .app_data(session.clone())
+            .app_data(settings.clone())
             .app_data(JsonConfig::default().error_handler(json_error_handler))
+            .app_data(QueryConfig::default().error_handler(query_error_handler::<IgnoredAny>))
+            .app_data(PathConfig::default().error_handler(path_error_handler))
+            .app_data(FormConfig::default().error_handler(form_error_handler))
+            // verify bearer tokens, register before error_format so its 401s are rendered too
+            .wrap(from_fn(jwt_auth))
+            // require the CSRF token on unsafe requests authenticated by the session cookie
+            .wrap(from_fn(csrf_protect))
+            // render ApiError responses as problem details when configured or requested
+            .wrap(from_fn(error_format))
             .wrap(cors)
             // enable automatic response compression - usually register this first
             .wrap(Compress::default())
-            // cookie session middleware
-            .wrap(
-                SessionMiddleware::builder(CookieSessionStore::default(), key.clone())
-                    .cookie_secure(false)
-                    .build()
-            )
+            // record session metadata, register before the sessions so it runs inside them
+            .wrap(from_fn(session_activity))
+            // session middleware, with the cookie or MongoDB store
+            .wrap(session.middleware(session_store))
+            // accept cookies encrypted with previous session keys, register after the sessions
+            .wrap(from_fn(rotate_session_keys))
             .service(
                 // Prefix route
                 scope("/api")
-                    .service(get_user)
+                    // fail fast while a server started in degraded mode waits for MongoDB
+                    .wrap(from_fn(require_database))
+                    // user routes are guarded with the permission they require
+                    .service(
+                        web
+                            ::resource("/user/{user}")
+                            .route(web::get().to(get_user).wrap(require(Permission::UsersRead)))
+                            .route(
+                                web::put().to(update_user).wrap(require(Permission::UsersWrite))
+                            )
+                            .route(
+                                web::delete().to(delete_user).wrap(require(Permission::UsersWrite))
+                            )
+                    )
+                    .service(
+                        web
+                            ::resource("/user/{user}/role")
+                            .route(
+                                web::put().to(set_user_role).wrap(require(Permission::UsersAdmin))
+                            )
+                    )
+                    .service(
+                        web
+                            ::resource("/user/{user}/two_factor")
+                            .route(
+                                web
+                                    ::delete()
+                                    .to(reset_two_factor)
+                                    .wrap(require(Permission::UsersAdmin))
+                            )
+                    )
+                    .service(
+                        web
+                            ::resource("/users")
+                            .app_data(
+                                QueryConfig::default().error_handler(
+                                    query_error_handler::<ListQuery>
+                                )
+                            )
+                            .route(web::get().to(get_users).wrap(require(Permission::UsersRead)))
+                    )
+                    .service(
+                        web
+                            ::resource("/add_user")
+                            .route(web::post().to(add_user).wrap(require(Permission::UsersCreate)))
+                    )
                     .service(get_svg)
-                    .service(get_users)
-                    .service(add_user)
-                    .service(generate_qr)
-                    .service(update_user)
-                    .service(delete_user)
+                    .service(
+                        web
+                            ::resource("/api_keys")
+                            .route(
+                                web::get().to(list_api_keys).wrap(require(Permission::ApiKeysAdmin))
+                            )
+                            .route(
+                                web
+                                    ::post()
+                                    .to(create_api_key)
+                                    .wrap(require(Permission::ApiKeysAdmin))
+                            )
+                    )
+                    .service(
+                        web
+                            ::resource("/api_keys/{id}")
+                            .route(
+                                web
+                                    ::delete()
+                                    .to(revoke_api_key)
+                                    .wrap(require(Permission::ApiKeysAdmin))
+                            )
+                    )
+                    .service(
+                        web
+                            ::resource("/qr")
+                            .app_data(
+                                QueryConfig::default().error_handler(query_error_handler::<Info>)
+                            )
+                            .route(web::get().to(generate_qr))
+                    )
+                    .service(register)
+                    .service(login)
+                    .service(login_two_factor)
+                    .service(refresh)
+                    .service(logout)
+                    .service(get_csrf_token)
+                    .service(me)
+                    .service(enroll_two_factor)
+                    .service(confirm_two_factor)
+                    .service(verify_email)
+                    .service(resend_verification_email)
+                    .service(forgot_password)
+                    .service(reset_password)
+                    .service(list_sessions)
+                    .service(delete_session)
+                    .service(delete_all_sessions)
             )
+            // handle every request in a server span, continuing the caller's trace
+            .wrap(from_fn(trace_requests))
+            // count requests and their latency, register next to the logger to time everything
+            .wrap(from_fn(track_requests))
             // enable logger - always register Actix Web Logger middleware last
-            .wrap(Logger::default())
+            .wrap(
+                Logger::default()
+                    .exclude("/health/live")
+                    .exclude("/health/ready")
+                    .exclude("/metrics")
+            )
+            // tag logs, errors and responses with the request id, register after the logger
+            .wrap(from_fn(request_id))
+            // liveness and readiness probes
+            .service(live)
+            .service(ready)
+            // Prometheus metrics
+            .service(metrics)
             // register favicon
             .service(favicon)
             // register simple route, handle all methods
@@ -211,7 +778,7 @@ async fn main() -> io::Result<()> {
             .service(
                 web::resource("/").route(
                     web::get().to(|req: HttpRequest| async move {
-                        println!("{req:?}");
+                        debug!("{} {}", req.method(), req.path());
                         HttpResponse::Found()
                             .insert_header((header::LOCATION, "static/welcome.html"))
                             .finish()
@@ -221,7 +788,15 @@ async fn main() -> io::Result<()> {
             // default
             .default_service(web::to(default_handler))
     })
-        .bind((server_host, server_port))?
-        .workers(2)
-        .run().await
+        .bind((server.host, server.port))?
+        .workers(server.workers)
+        .run().await?;
+
+    // export the spans still buffered
+    if let Some(provider) = tracer_provider {
+        if let Err(err) = provider.shutdown() {
+            warn!("Error exporting the last spans: {}", err);
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,165 @@
+use actix_session::{ Session, SessionExt };
+use actix_web::{
+    body::MessageBody,
+    dev::{ ServiceRequest, ServiceResponse },
+    http::{ header, Method },
+    middleware::Next,
+    Error,
+    ResponseError,
+};
+use log::error;
+
+use crate::{
+    auth::token::{ generate_token, hash_token },
+    constants::{ API_KEY_HEADER, CSRF_HEADER, SESSION_CSRF_TOKEN, SESSION_USER_ID },
+    models::error_model::ApiErrorType,
+};
+
+/// The CSRF token bound to `session`, created on first use.
+pub fn session_csrf_token(session: &Session) -> Result<String, ApiErrorType> {
+    if let Some(token) = session.get::<String>(SESSION_CSRF_TOKEN).ok().flatten() {
+        return Ok(token);
+    }
+    let token = generate_token();
+    session.insert(SESSION_CSRF_TOKEN, &token).map_err(|err| {
+        error!("Error: {}", err);
+        ApiErrorType::InternalServerError
+    })?;
+    Ok(token)
+}
+
+/// Rejects POST, PUT, PATCH and DELETE requests that are authenticated by the session cookie
+/// unless they carry the session's CSRF token in the `X-CSRF-Token` header. Requests with an
+/// `Authorization` or `X-API-Key` header don't use the cookie and are let through.
+/// Register it before the session middleware, so it runs inside it.
+pub async fn csrf_protect(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if uses_session_cookie(&req) && !has_csrf_token(&req) {
+        let resp = ApiErrorType::CsrfTokenInvalid.error_response();
+        return Ok(req.into_response(resp).map_into_right_body());
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+// An unsafe request that would be authenticated by the session.
+fn uses_session_cookie(req: &ServiceRequest) -> bool {
+    let method = req.method();
+    let safe = matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE);
+    let headers = req.headers();
+    !safe &&
+        !headers.contains_key(header::AUTHORIZATION) &&
+        !headers.contains_key(API_KEY_HEADER) &&
+        req.get_session().get::<String>(SESSION_USER_ID).ok().flatten().is_some()
+}
+
+fn has_csrf_token(req: &ServiceRequest) -> bool {
+    let sent = req
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let expected = req.get_session().get::<String>(SESSION_CSRF_TOKEN).ok().flatten();
+    match (sent, expected) {
+        // compare the hashes, so the time taken doesn't reveal the token
+        (Some(sent), Some(expected)) => hash_token(sent.trim()) == hash_token(&expected),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_http::Request;
+    use actix_session::{ storage::CookieSessionStore, SessionMiddleware };
+    use actix_web::{
+        cookie::{ Cookie, Key },
+        dev::Service,
+        http::StatusCode,
+        middleware::from_fn,
+        test,
+        web,
+        App,
+        HttpResponse,
+    };
+    use serde_json::Value;
+
+    use super::*;
+
+    async fn login(session: Session) -> Result<HttpResponse, ApiErrorType> {
+        session.insert(SESSION_USER_ID, "user").map_err(|_| ApiErrorType::InternalServerError)?;
+        Ok(HttpResponse::Ok().body(session_csrf_token(&session)?))
+    }
+
+    async fn app() -> impl Service<
+        Request,
+        Response = ServiceResponse<impl MessageBody>,
+        Error = Error
+    > {
+        test::init_service(
+            App::new()
+                .wrap(from_fn(csrf_protect))
+                .wrap(
+                    SessionMiddleware::builder(CookieSessionStore::default(), Key::generate())
+                        .cookie_secure(false)
+                        .build()
+                )
+                .route("/login", web::post().to(login))
+                .route("/action", web::post().to(HttpResponse::Ok))
+        ).await
+    }
+
+    // Logs in and returns the session cookie and its CSRF token.
+    async fn session_cookie(
+        app: &impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error>
+    ) -> (Cookie<'static>, String) {
+        let request = test::TestRequest::post().uri("/login").to_request();
+        let response = test::call_service(app, request).await;
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        let body = test::read_body(response).await;
+        (cookie, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn action(cookie: &Cookie<'static>) -> test::TestRequest {
+        test::TestRequest::post().uri("/action").cookie(cookie.clone())
+    }
+
+    #[actix_web::test]
+    async fn session_request_without_token_is_rejected() {
+        let app = app().await;
+        let (cookie, _) = session_cookie(&app).await;
+        let response = test::call_service(&app, action(&cookie).to_request()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["message"], ApiErrorType::CsrfTokenInvalid.to_string());
+    }
+
+    #[actix_web::test]
+    async fn session_request_with_wrong_token_is_rejected() {
+        let app = app().await;
+        let (cookie, _) = session_cookie(&app).await;
+        let request = action(&cookie).insert_header((CSRF_HEADER, "wrong")).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn session_request_with_token_passes() {
+        let app = app().await;
+        let (cookie, token) = session_cookie(&app).await;
+        let request = action(&cookie).insert_header((CSRF_HEADER, token)).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn bearer_and_api_key_requests_are_exempt() {
+        let app = app().await;
+        let (cookie, _) = session_cookie(&app).await;
+        let headers = [(header::AUTHORIZATION.as_str(), "Bearer token"), (API_KEY_HEADER, "key")];
+        for (name, value) in headers {
+            let request = action(&cookie).insert_header((name, value)).to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+}
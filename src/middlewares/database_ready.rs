@@ -0,0 +1,59 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ ServiceRequest, ServiceResponse },
+    http::header::{ self, HeaderValue },
+    middleware::Next,
+    web,
+    Error,
+    ResponseError,
+};
+
+use crate::{ configs::db::AppStates, models::error_model::ApiErrorType };
+
+/// Answers `503 Service Unavailable` while the database isn't set up, so requests to a server
+/// started in degraded mode fail fast instead of waiting for MongoDB to time out.
+pub async fn require_database(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let waiting = req
+        .app_data::<web::Data<AppStates>>()
+        .is_some_and(|states| !states.db_status.is_ready());
+    if waiting {
+        let mut resp = ApiErrorType::ServiceUnavailable.error_response();
+        resp.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from_static("5"));
+        return Ok(req.into_response(resp).map_into_right_body());
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{ http::StatusCode, middleware::from_fn, test, App, HttpResponse };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::test_support::memory_states;
+
+    #[actix_web::test]
+    async fn requests_fail_fast_until_the_database_is_ready() {
+        let states = web::Data::new(memory_states());
+        states.db_status.set_ready(false);
+        let app = test::init_service(
+            App::new()
+                .app_data(states.clone())
+                .wrap(from_fn(require_database))
+                .route("/", web::get().to(HttpResponse::Ok))
+        ).await;
+
+        let response = test::call_service(&app, test::TestRequest::get().to_request()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "5");
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["status"], 503);
+
+        states.db_status.set_ready(true);
+        let response = test::call_service(&app, test::TestRequest::get().to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
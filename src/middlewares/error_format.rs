@@ -0,0 +1,162 @@
+use actix_web::{
+    body::{ BoxBody, EitherBody, MessageBody },
+    dev::{ ServiceRequest, ServiceResponse },
+    error::InternalError,
+    http::header::{ self, q, HeaderValue, Header },
+    middleware::Next,
+    web,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+
+use crate::models::error_model::{ ApiError, ErrorFormat, PROBLEM_JSON };
+
+/// Whether errors for this request should be rendered as `application/problem+json`,
+/// either because it is configured or because the client asked for it.
+pub fn wants_problem(req: &HttpRequest) -> bool {
+    let configured = req
+        .app_data::<web::Data<ErrorFormat>>()
+        .is_some_and(|format| *format.get_ref() == ErrorFormat::Problem);
+    configured ||
+        header::Accept
+            ::parse(req)
+            .is_ok_and(|accept| {
+                accept
+                    .iter()
+                    .any(|item| item.item.essence_str() == PROBLEM_JSON && item.quality > q(0.0))
+            })
+}
+
+/// Re-renders `ApiError` responses as RFC 7807 problem details when `wants_problem` says so.
+/// Errors returned by the inner middleware are rendered the same way and passed on as errors,
+/// since they come without the request. Anything without an `ApiError` passes through untouched.
+pub async fn error_format(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if !wants_problem(req.request()) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let path = req.path().to_owned();
+    match next.call(req).await {
+        Ok(res) => {
+            let (req, response) = res.into_parts();
+            Ok(ServiceResponse::new(req, into_problem(response, &path)?))
+        }
+        Err(err) => {
+            let response = into_problem(err.error_response(), &path)?.map_into_boxed_body();
+            Err(InternalError::from_response(err, response).into())
+        }
+    }
+}
+
+// Replaces the body of a response carrying an `ApiError` with its problem details.
+fn into_problem<B>(
+    mut response: HttpResponse<B>,
+    path: &str
+) -> Result<HttpResponse<EitherBody<B>>, Error> {
+    let api_error = response.extensions_mut().remove::<ApiError>();
+    match api_error {
+        Some(api_error) => {
+            let body = serde_json::to_string(&api_error.into_problem(path))?;
+            Ok(
+                response.map_body(|head, _| {
+                    head.headers_mut().insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(PROBLEM_JSON)
+                    );
+                    EitherBody::right(BoxBody::new(body))
+                })
+            )
+        }
+        None => Ok(response.map_into_left_body()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{ body::to_bytes, dev::Service, http::StatusCode, middleware::from_fn, test, App };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::models::error_model::ApiErrorType;
+
+    // Fails every request the way an auth middleware would, by returning `Err`.
+    async fn reject(
+        _req: ServiceRequest,
+        _next: Next<impl MessageBody + 'static>
+    ) -> Result<ServiceResponse<impl MessageBody>, Error> {
+        Err::<ServiceResponse, _>(ApiErrorType::AuthenticationError.into())
+    }
+
+    async fn missing() -> Result<HttpResponse, ApiErrorType> {
+        Err(ApiErrorType::NotFound)
+    }
+
+    async fn app() -> impl Service<
+        actix_http::Request,
+        Response = ServiceResponse<impl MessageBody>,
+        Error = Error
+    > {
+        test::init_service(
+            App::new()
+                .wrap(from_fn(error_format))
+                .route("/missing", web::get().to(missing))
+                .service(
+                    web
+                        ::scope("/private")
+                        .wrap(from_fn(reject))
+                        .route("", web::get().to(HttpResponse::Ok))
+                )
+        ).await
+    }
+
+    fn get(uri: &str, problem: bool) -> actix_http::Request {
+        let request = test::TestRequest::get().uri(uri);
+        match problem {
+            true => request.insert_header((header::ACCEPT, PROBLEM_JSON)),
+            false => request,
+        }.to_request()
+    }
+
+    // Renders a middleware error the way the server answers it.
+    async fn error_body(err: Error) -> (StatusCode, Option<HeaderValue>, Value) {
+        let response = err.error_response();
+        let status = response.status();
+        let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+        let body = to_bytes(response.into_body()).await.unwrap();
+        (status, content_type, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn handler_error_is_rendered_as_problem() {
+        let app = app().await;
+        let response = test::call_service(&app, get("/missing", true)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), PROBLEM_JSON);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["instance"], "/missing");
+    }
+
+    #[actix_web::test]
+    async fn middleware_error_is_rendered_as_problem() {
+        let app = app().await;
+        let err = app.call(get("/private", true)).await.err().unwrap();
+        let (status, content_type, body) = error_body(err).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(content_type.unwrap(), PROBLEM_JSON);
+        assert_eq!(body["status"], 401);
+        assert_eq!(body["instance"], "/private");
+    }
+
+    #[actix_web::test]
+    async fn middleware_error_keeps_json_by_default() {
+        let app = app().await;
+        let err = app.call(get("/private", false)).await.err().unwrap();
+        let (status, _, body) = error_body(err).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["message"], ApiErrorType::AuthenticationError.to_string());
+    }
+}
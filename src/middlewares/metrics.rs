@@ -0,0 +1,85 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ ServiceRequest, ServiceResponse },
+    middleware::Next,
+    Error,
+};
+
+use crate::telemetry::metrics::{ with_route, METRICS };
+
+// Label of requests that match no route, so scanners can't create a label per path.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+// Counts a request as in flight until dropped, also when the client disconnects
+// and the request future is dropped before it completes.
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        METRICS.http_requests_in_flight.inc();
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        METRICS.http_requests_in_flight.dec();
+    }
+}
+
+/// Counts requests and records their latency, labeled by route pattern, method and status.
+/// Register it next to `Logger`, so it times the other middlewares too.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let route = req.match_pattern().unwrap_or_else(|| UNMATCHED_ROUTE.to_owned());
+    let method = req.method().to_string();
+    let started = Instant::now();
+
+    let in_flight = InFlight::start();
+    let result = with_route(route.clone(), next.call(req)).await;
+    drop(in_flight);
+
+    let status = match &result {
+        Ok(res) => res.status(),
+        Err(err) => err.as_response_error().status_code(),
+    };
+    let labels = [route.as_str(), method.as_str(), status.as_str()];
+    METRICS.http_requests.with_label_values(&labels).inc();
+    METRICS.http_request_duration
+        .with_label_values(&labels)
+        .observe(started.elapsed().as_secs_f64());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::pending;
+
+    use actix_web::{ dev::Service, middleware::from_fn, test, web, App, HttpResponse };
+    use futures::FutureExt;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn dropped_request_leaves_flight() {
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(track_requests))
+                .route("/hang", web::get().to(pending::<HttpResponse>))
+        ).await;
+        let before = METRICS.http_requests_in_flight.get();
+
+        let call = app.call(test::TestRequest::get().uri("/hang").to_request());
+        let mut call = Box::pin(call);
+        assert!((&mut call).now_or_never().is_none());
+        assert_eq!(METRICS.http_requests_in_flight.get(), before + 1);
+
+        // like a client disconnecting before the response
+        drop(call);
+        assert_eq!(METRICS.http_requests_in_flight.get(), before);
+    }
+}
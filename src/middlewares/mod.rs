@@ -0,0 +1,8 @@
+pub mod csrf;
+pub mod database_ready;
+pub mod error_format;
+pub mod metrics;
+pub mod request_id;
+pub mod session_keys;
+pub mod session_activity;
+pub mod trace;
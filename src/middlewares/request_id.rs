@@ -0,0 +1,183 @@
+use std::{ pin::Pin, task::{ Context, Poll } };
+
+use actix_web::{
+    body::{ BodySize, BoxBody, MessageBody },
+    dev::{ ServiceRequest, ServiceResponse },
+    error::InternalError,
+    http::header::{ HeaderName, HeaderValue },
+    middleware::Next,
+    web::Bytes,
+    Error,
+};
+use uuid::Uuid;
+
+use crate::{ constants::REQUEST_ID_HEADER, telemetry::logging::{ with_request_id, REQUEST_ID } };
+
+// Longest incoming id that is kept.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Takes the request id from the `X-Request-Id` header, or generates one, and sends it back
+/// in the response. Log lines and `ApiError`s of the request carry it.
+/// Register it after `Logger`, so the id covers every other middleware.
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map_or_else(|| Uuid::new_v4().to_string(), str::to_owned);
+    let header = HeaderValue::from_str(&id).ok();
+
+    with_request_id(id.clone(), async move {
+        match next.call(req).await {
+            Ok(mut res) => {
+                if let Some(value) = header {
+                    res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+                let res = res.map_into_boxed_body();
+                Ok(res.map_body(|_, body| ScopedBody { id, body: Some(body) }))
+            }
+            // render errors of the middlewares here, so their body gets the request id too
+            Err(err) => {
+                let mut resp = err.error_response();
+                if let Some(value) = header {
+                    resp.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+                Err(InternalError::from_response(err, resp).into())
+            }
+        }
+    }).await
+}
+
+// Ids from clients end up in the logs, only short printable ones are accepted.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() &&
+        id.len() <= MAX_REQUEST_ID_LEN &&
+        id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+}
+
+// Streams and drops the body with the request id set. `Logger` logs when the body is dropped,
+// after the response left the middleware.
+struct ScopedBody {
+    id: String,
+    body: Option<BoxBody>,
+}
+
+impl MessageBody for ScopedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.as_ref().map_or(BodySize::None, MessageBody::size)
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        match &mut this.body {
+            Some(body) => REQUEST_ID.sync_scope(this.id.clone(), || Pin::new(body).poll_next(cx)),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+impl Drop for ScopedBody {
+    fn drop(&mut self) {
+        if let Some(body) = self.body.take() {
+            REQUEST_ID.sync_scope(self.id.clone(), || drop(body));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        body::to_bytes,
+        dev::Service,
+        middleware::from_fn,
+        test,
+        web,
+        App,
+        HttpResponse,
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{ models::error_model::ApiErrorType, telemetry::logging::current_request_id };
+
+    async fn echo_id() -> HttpResponse {
+        HttpResponse::Ok().body(current_request_id().unwrap_or_default())
+    }
+
+    async fn not_found() -> Result<HttpResponse, ApiErrorType> {
+        Err(ApiErrorType::UserNotFound)
+    }
+
+    async fn reject(
+        _req: ServiceRequest,
+        _next: Next<impl MessageBody + 'static>
+    ) -> Result<ServiceResponse<impl MessageBody>, Error> {
+        Err::<ServiceResponse, _>(ApiErrorType::AuthenticationError.into())
+    }
+
+    fn with_id(uri: &str, id: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(uri).insert_header((REQUEST_ID_HEADER, id))
+    }
+
+    #[actix_web::test]
+    async fn incoming_id_is_used_and_sent_back() {
+        let app = test::init_service(
+            App::new().wrap(from_fn(request_id)).route("/", web::get().to(echo_id))
+        ).await;
+
+        let response = test::call_service(&app, with_id("/", "abc-123").to_request()).await;
+        assert_eq!(response.headers().get(REQUEST_ID_HEADER).unwrap(), "abc-123");
+        assert_eq!(test::read_body(response).await, "abc-123");
+    }
+
+    #[actix_web::test]
+    async fn missing_or_invalid_ids_are_replaced() {
+        let app = test::init_service(
+            App::new().wrap(from_fn(request_id)).route("/", web::get().to(echo_id))
+        ).await;
+        let too_long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+
+        for request in [
+            test::TestRequest::get(),
+            with_id("/", "has space"),
+            with_id("/", &too_long),
+            with_id("/", ""),
+        ] {
+            let response = test::call_service(&app, request.to_request()).await;
+            let header = response.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+            let id = header.to_str().unwrap();
+            assert!(Uuid::parse_str(id).is_ok(), "{id}");
+            assert_eq!(test::read_body(response).await, id);
+        }
+    }
+
+    #[actix_web::test]
+    async fn errors_carry_the_request_id() {
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(request_id))
+                .route("/handler", web::get().to(not_found))
+                .service(web::resource("/middleware").wrap(from_fn(reject)).to(echo_id))
+        ).await;
+
+        let response = test::call_service(&app, with_id("/handler", "abc-123").to_request()).await;
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["request_id"], "abc-123");
+
+        let err = app.call(with_id("/middleware", "abc-123").to_request()).await.err().unwrap();
+        let response = err.error_response();
+        assert_eq!(response.headers().get(REQUEST_ID_HEADER).unwrap(), "abc-123");
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["request_id"], "abc-123");
+    }
+}
@@ -0,0 +1,68 @@
+use actix_session::{ Session, SessionExt, SessionStatus };
+use actix_web::{
+    body::MessageBody,
+    dev::{ ServiceRequest, ServiceResponse },
+    http::header,
+    middleware::Next,
+    Error,
+    HttpRequest,
+};
+use chrono::{ DateTime, Duration, Utc };
+use log::error;
+use uuid::Uuid;
+
+use crate::constants::{
+    SESSION_CREATED_AT,
+    SESSION_ID,
+    SESSION_IP,
+    SESSION_LAST_SEEN_AT,
+    SESSION_USER_AGENT,
+};
+
+// `last_seen_at` is only written this often, so not every request changes the session.
+const LAST_SEEN_INTERVAL_SECONDS: i64 = 60;
+
+/// Records an id, creation and last-seen time, IP and user agent in every non-empty session,
+/// after the handler ran. Register it before the session middleware, so it runs inside it.
+pub async fn session_activity(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let res = next.call(req).await?;
+    let session = res.request().get_session();
+    if session.status() != SessionStatus::Purged && !session.entries().is_empty() {
+        if let Err(err) = record_activity(&session, res.request()) {
+            error!("Error: {}", err);
+        }
+    }
+    Ok(res)
+}
+
+fn record_activity(session: &Session, req: &HttpRequest) -> Result<(), actix_web::Error> {
+    let now = Utc::now();
+    if session.get::<String>(SESSION_ID)?.is_none() {
+        session.insert(SESSION_ID, Uuid::new_v4().to_string())?;
+        session.insert(SESSION_CREATED_AT, now)?;
+    }
+    let last_seen_at = session.get::<DateTime<Utc>>(SESSION_LAST_SEEN_AT)?;
+    let recent = last_seen_at.is_some_and(|seen| {
+        now - seen < Duration::seconds(LAST_SEEN_INTERVAL_SECONDS)
+    });
+    if !recent {
+        session.insert(SESSION_LAST_SEEN_AT, now)?;
+    }
+
+    let ip = req.connection_info().realip_remote_addr().map(str::to_owned);
+    if ip.is_some() && session.get::<String>(SESSION_IP)? != ip {
+        session.insert(SESSION_IP, ip)?;
+    }
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    if user_agent.is_some() && session.get::<String>(SESSION_USER_AGENT)? != user_agent {
+        session.insert(SESSION_USER_AGENT, user_agent)?;
+    }
+    Ok(())
+}
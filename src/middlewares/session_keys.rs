@@ -0,0 +1,66 @@
+use actix_web::{
+    body::MessageBody,
+    cookie::{ Cookie, CookieJar },
+    dev::{ ServiceRequest, ServiceResponse },
+    http::header::{ self, HeaderValue },
+    middleware::Next,
+    web,
+    Error,
+};
+
+use crate::configs::session::SessionConfig;
+
+/// Re-encrypts a session cookie that was encrypted with one of the previous `SESSION_KEYS`
+/// with the active key, before `SessionMiddleware` reads it. Register it after the session
+/// middleware. The browser gets a cookie with the active key the next time the session changes.
+pub async fn rotate_session_keys(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let rotated = req
+        .app_data::<web::Data<SessionConfig>>()
+        .filter(|config| !config.previous_keys.is_empty())
+        .and_then(|config| reencrypt_cookie(&req, config));
+    if let Some(cookie_header) = rotated {
+        req.headers_mut().insert(header::COOKIE, cookie_header);
+    }
+    next.call(req).await
+}
+
+// The request's `Cookie` header with the session cookie re-encrypted,
+// or `None` if there is nothing to rotate.
+fn reencrypt_cookie(req: &ServiceRequest, config: &SessionConfig) -> Option<HeaderValue> {
+    let header = req
+        .headers()
+        .get_all(header::COOKIE)
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join("; ");
+    let mut cookies: Vec<Cookie> = header
+        .split(';')
+        .filter_map(|cookie| Cookie::parse_encoded(cookie.trim()).ok())
+        .collect();
+    let session_cookie = cookies.iter_mut().find(|cookie| cookie.name() == config.cookie_name)?;
+
+    let mut jar = CookieJar::new();
+    jar.add_original(session_cookie.clone().into_owned());
+    if jar.private(&config.active_key).get(&config.cookie_name).is_some() {
+        return None;
+    }
+    let decrypted = config.previous_keys
+        .iter()
+        .find_map(|key| jar.private(key).get(&config.cookie_name))?;
+
+    let mut rotated = CookieJar::new();
+    rotated
+        .private_mut(&config.active_key)
+        .add(Cookie::new(config.cookie_name.clone(), decrypted.value().to_owned()));
+    session_cookie.set_value(rotated.get(&config.cookie_name)?.value().to_owned());
+
+    let header = cookies
+        .iter()
+        .map(|cookie| cookie.stripped().encoded().to_string())
+        .collect::<Vec<_>>()
+        .join("; ");
+    HeaderValue::from_str(&header).ok()
+}
@@ -0,0 +1,137 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ ServiceRequest, ServiceResponse },
+    http::header::{ HeaderMap, HeaderName },
+    middleware::Next,
+    Error,
+};
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{ FutureExt, SpanKind, Status, TraceContextExt, Tracer },
+    KeyValue,
+};
+
+use crate::telemetry::trace::tracer;
+
+// Reads the propagation headers, like `traceparent`, of a request.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// Handles every request in a server span, continuing the trace of its `traceparent` header.
+/// Spans started while handling the request, like database calls, are its children.
+pub async fn trace_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+    let method = req.method().to_string();
+    let route = req.match_pattern();
+    let name = match &route {
+        Some(route) => format!("{method} {route}"),
+        None => method.clone(),
+    };
+    let mut attributes = vec![
+        KeyValue::new("http.request.method", method),
+        KeyValue::new("url.path", req.path().to_owned())
+    ];
+    if let Some(route) = route {
+        attributes.push(KeyValue::new("http.route", route));
+    }
+
+    let tracer = tracer();
+    let span = tracer
+        .span_builder(name)
+        .with_kind(SpanKind::Server)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, &parent);
+    let cx = parent.with_span(span);
+    let result = next.call(req).with_context(cx.clone()).await;
+
+    let span = cx.span();
+    let status = match &result {
+        Ok(res) => res.status(),
+        Err(err) => err.as_response_error().status_code(),
+    };
+    span.set_attribute(KeyValue::new("http.response.status_code", i64::from(status.as_u16())));
+    if status.is_server_error() {
+        span.set_status(Status::error(status.to_string()));
+    }
+    span.end();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{ http::StatusCode, middleware::from_fn, test, web, App, HttpResponse };
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        handlers::user_handler::get_user,
+        repositories::{
+            memory_user_repository::MemoryUserRepository,
+            traced_user_repository::TracedUserRepository,
+        },
+        telemetry::trace::{ record_spans, recorded_spans },
+        test_support::memory_states,
+    };
+
+    #[actix_web::test]
+    async fn requests_continue_the_callers_trace() {
+        record_spans();
+        let mut states = memory_states();
+        let users = Arc::new(MemoryUserRepository::default());
+        states.users = Arc::new(TracedUserRepository::new(users, "memory", "users"));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(states))
+                .wrap(from_fn(trace_requests))
+                .route("/user/{user}", web::get().to(get_user))
+                .route("/fail", web::get().to(HttpResponse::InternalServerError))
+        ).await;
+        let trace_id = Uuid::new_v4().simple().to_string();
+        let parent_id = "00f067aa0ba902b7";
+        let call = |uri: &str| {
+            test::TestRequest
+                ::get()
+                .uri(uri)
+                .insert_header(("traceparent", format!("00-{trace_id}-{parent_id}-01")))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, call("/user/nobody")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = test::call_service(&app, call("/fail")).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let spans = recorded_spans(&trace_id);
+        assert_eq!(spans.len(), 3, "{spans:?}");
+        let (find, server, failed) = (&spans[0], &spans[1], &spans[2]);
+        assert_eq!(server["name"], "GET /user/{user}");
+        assert_eq!(server["kind"], "server");
+        assert_eq!(server["parent_span_id"], parent_id);
+        assert_eq!(server["attributes"]["http.route"], "/user/{user}");
+        assert_eq!(server["attributes"]["http.response.status_code"], "404");
+        // client errors are not span errors
+        assert_eq!(server["status"], "unset");
+        assert_eq!(find["name"], "find users");
+        assert_eq!(find["parent_span_id"], server["span_id"]);
+        assert_eq!(find["attributes"]["db.system.name"], "memory");
+        assert_eq!(failed["name"], "GET /fail");
+        assert_eq!(failed["status"], "error");
+    }
+}
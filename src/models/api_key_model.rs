@@ -0,0 +1,96 @@
+use chrono::{ DateTime, Utc };
+use serde::{ Deserialize, Deserializer, Serialize };
+use uuid::Uuid;
+use validator::{ Validate, ValidationError };
+
+use super::role_model::Permission;
+
+// Prepended to every key, so leaked keys are easy to recognize.
+pub const API_KEY_MARKER: &str = "axk_";
+// Number of characters of the key that are stored in clear text in `prefix`.
+const API_KEY_PREFIX_LEN: usize = API_KEY_MARKER.len() + 8;
+
+/// An API key for service-to-service calls, sent in the `X-API-Key` header.
+/// Only the hash of the key is stored, next to its first characters for identification.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub prefix: String,
+    #[serde(deserialize_with = "known_scopes")]
+    pub scopes: Vec<Permission>,
+    // id of the admin that created the key
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    /// Creates the record for the newly generated `key`.
+    pub fn new(key: &str, payload: ApiKeyPayload, created_by: String) -> Self {
+        ApiKey {
+            id: Uuid::new_v4().to_string(),
+            name: payload.name,
+            prefix: key.chars().take(API_KEY_PREFIX_LEN).collect(),
+            scopes: payload.scopes,
+            created_by,
+            created_at: Utc::now(),
+            last_used_at: None,
+            revoked_at: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ApiKeyPayload {
+    #[validate(length(min = 1, max = 64, message = "Name must be 1 to 64 characters"))]
+    pub name: String,
+    #[validate(custom(function = "validate_scopes"))]
+    pub scopes: Vec<Permission>,
+}
+
+fn validate_scopes(scopes: &[Permission]) -> Result<(), ValidationError> {
+    if scopes.is_empty() {
+        let mut error = ValidationError::new("scopes");
+        error.message = Some("At least one scope is required".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+// Stored keys may hold scopes that have since been removed, those are dropped.
+fn known_scopes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Permission>, D::Error> {
+    let scopes = Vec::<String>::deserialize(deserializer)?;
+    Ok(scopes.iter().filter_map(|scope| scope.parse().ok()).collect())
+}
+
+/// Response to creating a key. The key itself is shown this one time only.
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_keys_drop_unknown_scopes() {
+        let stored = serde_json::json!({
+            "id": "1",
+            "name": "batch",
+            "prefix": "axk_12345678",
+            "scopes": ["users:read", "qr:generate"],
+            "created_by": "2",
+            "created_at": "2024-01-01T00:00:00Z",
+            "last_used_at": null,
+            "revoked_at": null,
+        });
+        let api_key: ApiKey = serde_json::from_value(stored).unwrap();
+
+        assert_eq!(api_key.scopes, [Permission::UsersRead]);
+    }
+}
@@ -0,0 +1,93 @@
+use serde::{ Deserialize, Serialize };
+use validator::Validate;
+
+use super::user_model::{ User, UserPayload };
+
+#[derive(Deserialize, Validate)]
+pub struct RegisterPayload {
+    #[serde(flatten)]
+    #[validate(nested)]
+    pub user: UserPayload,
+    #[validate(length(min = 8, max = 128, message = "Password must be 8 to 128 characters"))]
+    pub password: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct LoginPayload {
+    #[validate(length(min = 1, message = "Username is required"))]
+    pub username: String,
+    #[validate(length(min = 1, message = "Password is required"))]
+    pub password: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct TwoFactorLoginPayload {
+    #[validate(length(min = 1, message = "Challenge token is required"))]
+    pub challenge_token: String,
+    // a TOTP code or one of the recovery codes
+    #[validate(length(min = 1, message = "Code is required"))]
+    pub code: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct RefreshPayload {
+    #[validate(length(min = 1, message = "Refresh token is required"))]
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct LogoutPayload {
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct EmailTokenPayload {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ForgotPasswordPayload {
+    #[validate(length(min = 1, message = "Username is required"))]
+    pub username: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ResetPasswordPayload {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+    #[validate(length(min = 8, max = 128, message = "Password must be 8 to 128 characters"))]
+    pub password: String,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    pub refresh_token: String,
+}
+
+/// Answer to a correct password of an account with two-factor authentication. The login is
+/// completed by posting `challenge_token` with a code to `/api/auth/two_factor`.
+#[derive(Serialize)]
+pub struct TwoFactorChallenge {
+    pub two_factor_required: bool,
+    pub challenge_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(Serialize)]
+pub struct LoginResponse {
+    pub user: User,
+    #[serde(flatten)]
+    pub tokens: TokenResponse,
+    // for unsafe requests that rely on the session cookie
+    pub csrf_token: String,
+}
+
+/// CSRF token of the cookie session, sent back in the `X-CSRF-Token` header.
+#[derive(Serialize)]
+pub struct CsrfTokenResponse {
+    pub csrf_token: String,
+}
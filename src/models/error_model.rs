@@ -1,8 +1,17 @@
+use std::str::FromStr;
+
 use actix_web::{ error::ResponseError, http::StatusCode, HttpResponse };
 use chrono::{ SecondsFormat, Utc };
 use derive_more::{ Display, Error };
-use serde::Serialize;
-use validator::ValidationErrors;
+use log::error;
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+use validator::{ ValidationErrors, ValidationErrorsKind };
+
+use crate::{
+    repositories::user_repository::RepositoryError,
+    telemetry::logging::current_request_id,
+};
 
 // -- Error handing.
 #[derive(Debug, Display, Error)]
@@ -16,13 +25,31 @@ pub enum ApiErrorType {
     #[display("User not found for the given ID")]
     UserNotFound,
 
+    #[display("Resource not found.")]
+    NotFound,
+
+    #[display("Conflict with an existing resource.")]
+    Conflict,
+
     #[display("Authentication error.")]
     AuthenticationError,
 
-    // Figure out a way to use this when JWT does not have authorization.
+    // Authenticated, but the token or user lacks the required scope.
     #[display("Authorization error.")]
     AuthorizationError,
 
+    // Blocked by `REQUIRE_VERIFIED_EMAIL` until the user verified their email address.
+    #[display("Email address not verified.")]
+    EmailNotVerified,
+
+    // An unsafe request with the session cookie lacks the session's CSRF token.
+    #[display("Invalid CSRF token.")]
+    CsrfTokenInvalid,
+
+    // The database is not connected yet, see `database.mongo.degraded_start`.
+    #[display("Service unavailable.")]
+    ServiceUnavailable,
+
     #[display("Validation error on field")] ValidationError {
         validation_error: ValidationErrors,
         object: String,
@@ -30,14 +57,18 @@ pub enum ApiErrorType {
 
     #[display("Invalid credential.")]
     InvalidCredential,
+
+    // Locked out of the second factor for a while after `MAX_TWO_FACTOR_ATTEMPTS` wrong codes.
+    #[display("Too many attempts.")]
+    TooManyAttempts,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ValidationError {
-    object: String,
-    field: String,
-    rejected_value: String,
-    message: String,
+    pub object: String,
+    pub field: String,
+    pub rejected_value: String,
+    pub message: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -47,6 +78,76 @@ pub struct ApiError {
     pub message: String,
     pub debug_message: Option<String>,
     pub sub_errors: Vec<ValidationError>,
+    // the `X-Request-Id` of the request, to find its log lines
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+// RFC 7807 problem details, with `time`, `sub_errors` and `request_id` as extension members.
+#[derive(Debug, Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: Option<String>,
+    pub instance: String,
+    pub time: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sub_errors: Vec<ValidationError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+// Error output format, set with `server.error_format`.
+// In `Json` mode a client can still ask for problem details through its `Accept` header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorFormat {
+    #[default]
+    Json,
+    #[serde(alias = "problem+json")]
+    Problem,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "json" => Ok(ErrorFormat::Json),
+            "problem" | "problem+json" => Ok(ErrorFormat::Problem),
+            other => Err(format!("unknown error format `{other}`, expected json or problem")),
+        }
+    }
+}
+
+impl ApiError {
+    /// Builds the JSON response and keeps the error in the response extensions,
+    /// so the `error_format` middleware can render it as problem details instead.
+    pub fn into_response(self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        let mut resp = HttpResponse::build(status).json(&self);
+        resp.extensions_mut().insert(self);
+        resp
+    }
+
+    pub fn into_problem(self, instance: &str) -> ProblemDetails {
+        ProblemDetails {
+            problem_type: "about:blank".to_owned(),
+            title: self.message,
+            status: self.status,
+            detail: self.debug_message,
+            instance: instance.to_owned(),
+            time: self.time,
+            sub_errors: self.sub_errors,
+            request_id: self.request_id,
+        }
+    }
 }
 
 // Set Debug Error messages for Global error.
@@ -60,16 +161,45 @@ impl ApiErrorType {
                 "Bad request. Missing parameter or wrong payload.".to_owned()
             }
             ApiErrorType::UserNotFound => "User not found for given ID".to_owned(),
+            ApiErrorType::NotFound => "No resource found for the given ID".to_owned(),
+            ApiErrorType::Conflict => {
+                "A resource with the same unique value already exists.".to_owned()
+            }
             ApiErrorType::AuthenticationError => {
                 "User not authenticated. Please reauthenticate and try again.".to_owned()
             }
             ApiErrorType::AuthorizationError => {
                 "User not authorized to access this resource.".to_owned()
             }
+            ApiErrorType::EmailNotVerified => {
+                "Follow the link mailed to the email address before logging in.".to_owned()
+            }
+            ApiErrorType::CsrfTokenInvalid => {
+                "Send the session's CSRF token in the X-CSRF-Token header.".to_owned()
+            }
+            ApiErrorType::ServiceUnavailable => {
+                "The database is not reachable yet. Try again later.".to_owned()
+            }
             ApiErrorType::ValidationError { .. } => "Validation error".to_owned(),
             ApiErrorType::InvalidCredential => {
                 "Invalid Credential. Checking email address and password".to_owned()
             }
+            ApiErrorType::TooManyAttempts => {
+                "Too many wrong codes. Try again later.".to_owned()
+            }
+        }
+    }
+}
+
+// Map storage errors to API errors. Backend details are logged, never sent to the client.
+impl From<RepositoryError> for ApiErrorType {
+    fn from(err: RepositoryError) -> Self {
+        match err {
+            RepositoryError::DuplicateKey => ApiErrorType::Conflict,
+            RepositoryError::Backend { message } => {
+                error!("Error: {}", message);
+                ApiErrorType::InternalServerError
+            }
         }
     }
 }
@@ -82,10 +212,16 @@ impl ResponseError for ApiErrorType {
             ApiErrorType::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
             ApiErrorType::BadRequest => StatusCode::BAD_REQUEST,
             ApiErrorType::UserNotFound => StatusCode::NOT_FOUND,
+            ApiErrorType::NotFound => StatusCode::NOT_FOUND,
+            ApiErrorType::Conflict => StatusCode::CONFLICT,
             ApiErrorType::AuthenticationError => StatusCode::UNAUTHORIZED,
             ApiErrorType::AuthorizationError => StatusCode::FORBIDDEN,
+            ApiErrorType::EmailNotVerified => StatusCode::FORBIDDEN,
+            ApiErrorType::CsrfTokenInvalid => StatusCode::FORBIDDEN,
+            ApiErrorType::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
             ApiErrorType::ValidationError { .. } => StatusCode::UNPROCESSABLE_ENTITY,
             ApiErrorType::InvalidCredential => StatusCode::UNAUTHORIZED,
+            ApiErrorType::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS,
         }
     }
 
@@ -95,27 +231,73 @@ impl ResponseError for ApiErrorType {
         match self {
             // Iterate thru validation error object
             ApiErrorType::ValidationError { validation_error, object } => {
-                for (field, field_errors) in validation_error.field_errors() {
-                    for field_error in field_errors {
-                        validation_sub_errs.push(ValidationError {
-                            object: object.to_string(),
-                            field: field.to_owned(),
-                            rejected_value: field_error.params.get("value").unwrap().to_string(),
-                            message: field_error.message.as_ref().unwrap().to_string(),
-                        });
-                    }
-                }
+                collect_sub_errors(validation_error, object, &mut validation_sub_errs);
             }
             _ => {
                 validation_sub_errs = vec![];
             }
         }
-        HttpResponse::build(self.status_code()).json(ApiError {
+        (ApiError {
             status: self.status_code().as_u16(),
             time: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
             message: self.to_string(),
             debug_message: Some(self.debug_message()),
             sub_errors: validation_sub_errs,
-        })
+            request_id: current_request_id(),
+        }).into_response()
+    }
+}
+
+// Flattens field errors into `ValidationError`s. Nested structs keep their leaf field
+// names, since they are `#[serde(flatten)]`ed into the payload.
+fn collect_sub_errors(errors: &ValidationErrors, object: &str, sub_errors: &mut Vec<ValidationError>) {
+    for (field, kind) in errors.errors() {
+        match kind {
+            ValidationErrorsKind::Field(field_errors) => {
+                for field_error in field_errors {
+                    // custom validators may leave out the value or the message
+                    let rejected_value = match field_error.params.get("value") {
+                        Some(Value::String(value)) => value.to_owned(),
+                        Some(value) => value.to_string(),
+                        None => String::new(),
+                    };
+                    let message = match &field_error.message {
+                        Some(message) => message.to_string(),
+                        None => field_error.code.to_string(),
+                    };
+                    sub_errors.push(ValidationError {
+                        object: object.to_string(),
+                        field: field.to_string(),
+                        rejected_value,
+                        message,
+                    });
+                }
+            }
+            ValidationErrorsKind::Struct(nested) => collect_sub_errors(nested, object, sub_errors),
+            ValidationErrorsKind::List(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::to_bytes;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn repository_errors_map_to_typed_errors() {
+        let conflict = ApiErrorType::from(RepositoryError::DuplicateKey);
+        assert_eq!(conflict.status_code(), StatusCode::CONFLICT);
+
+        let backend = ApiErrorType::from(RepositoryError::Backend {
+            message: "connection refused by db.internal".to_owned(),
+        });
+        assert_eq!(backend.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        // the backend details stay in the log
+        let body = to_bytes(backend.error_response().into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], 500);
+        assert!(!body.to_string().contains("db.internal"));
     }
 }
@@ -1,2 +1,9 @@
 pub mod user_model;
 pub mod error_model;
+pub mod auth_model;
+pub mod refresh_token_model;
+pub mod role_model;
+pub mod api_key_model;
+pub mod two_factor_model;
+pub mod session_model;
+pub mod health_model;
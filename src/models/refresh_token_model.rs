@@ -0,0 +1,23 @@
+use chrono::{ DateTime, Utc };
+
+/// A stored refresh token. Only the SHA-256 hash of the token is kept.
+///
+/// Every refresh replaces the token with a new one of the same `family_id`, so a token
+/// that is presented after it was used means it leaked and the whole family is revoked.
+#[derive(Clone, Debug)]
+pub struct RefreshToken {
+    pub token_hash: String,
+    pub family_id: String,
+    pub user_id: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    // set once the token has been exchanged for a new one
+    pub used_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+}
+
+impl RefreshToken {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
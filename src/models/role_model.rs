@@ -0,0 +1,104 @@
+use std::str::FromStr;
+
+use derive_more::Display;
+use serde::{ Deserialize, Serialize };
+
+/// Actions guarded by role-based access control. They double as access token
+/// and API key scopes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Permission {
+    #[serde(rename = "users:read")]
+    UsersRead,
+    // update or delete a user record; only the caller's own unless they also have `UsersAdmin`
+    #[serde(rename = "users:write")]
+    UsersWrite,
+    #[serde(rename = "users:create")]
+    UsersCreate,
+    // act on other users' records and assign roles
+    #[serde(rename = "users:admin")]
+    UsersAdmin,
+    #[serde(rename = "api_keys:admin")]
+    ApiKeysAdmin,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::UsersRead => "users:read",
+            Permission::UsersWrite => "users:write",
+            Permission::UsersCreate => "users:create",
+            Permission::UsersAdmin => "users:admin",
+            Permission::ApiKeysAdmin => "api_keys:admin",
+        }
+    }
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        [
+            Permission::UsersRead,
+            Permission::UsersWrite,
+            Permission::UsersCreate,
+            Permission::UsersAdmin,
+            Permission::ApiKeysAdmin,
+        ]
+            .into_iter()
+            .find(|permission| permission.as_str() == value)
+            .ok_or_else(|| format!("unknown permission `{value}`"))
+    }
+}
+
+/// Role of a user. New users are viewers, only admins can change roles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Display, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[display("admin")]
+    Admin,
+    #[display("editor")]
+    Editor,
+    #[default]
+    #[display("viewer")]
+    Viewer,
+}
+
+impl Role {
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::Admin =>
+                &[
+                    Permission::UsersRead,
+                    Permission::UsersWrite,
+                    Permission::UsersCreate,
+                    Permission::UsersAdmin,
+                    Permission::ApiKeysAdmin,
+                ],
+            Role::Editor => &[Permission::UsersRead, Permission::UsersWrite, Permission::UsersCreate],
+            Role::Viewer => &[Permission::UsersRead, Permission::UsersWrite],
+        }
+    }
+
+    /// The role's permissions as access token scopes.
+    pub fn scopes(&self) -> Vec<&'static str> {
+        self.permissions().iter().map(Permission::as_str).collect()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RolePayload {
+    pub role: Role,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "admin" => Ok(Role::Admin),
+            "editor" => Ok(Role::Editor),
+            "viewer" => Ok(Role::Viewer),
+            other => Err(format!("unknown role `{other}`")),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+use chrono::{ DateTime, Utc };
+use serde::{ de::DeserializeOwned, Serialize };
+
+use crate::constants::{
+    SESSION_CREATED_AT,
+    SESSION_ID,
+    SESSION_IP,
+    SESSION_LAST_SEEN_AT,
+    SESSION_USER_AGENT,
+};
+
+/// A session of the caller, as listed by `GET /api/me/sessions`.
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    // whether this is the session of the listing request
+    pub current: bool,
+}
+
+/// Reads a value out of a session's state, where values are stored as JSON.
+pub fn state_value<T: DeserializeOwned>(state: &HashMap<String, String>, key: &str) -> Option<T> {
+    state.get(key).and_then(|value| serde_json::from_str(value).ok())
+}
+
+impl SessionInfo {
+    /// Reads the metadata the `session_activity` middleware recorded in a session's state.
+    /// Returns `None` for sessions without it.
+    pub fn from_state(state: &HashMap<String, String>) -> Option<Self> {
+        Some(SessionInfo {
+            id: state_value(state, SESSION_ID)?,
+            created_at: state_value(state, SESSION_CREATED_AT)?,
+            last_seen_at: state_value(state, SESSION_LAST_SEEN_AT)?,
+            ip: state_value(state, SESSION_IP),
+            user_agent: state_value(state, SESSION_USER_AGENT),
+            current: false,
+        })
+    }
+}
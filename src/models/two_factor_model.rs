@@ -0,0 +1,44 @@
+use chrono::{ DateTime, Utc };
+use serde::{ Deserialize, Serialize };
+use validator::Validate;
+
+/// TOTP settings of a user. Created unconfirmed on enrollment, `enabled` once the
+/// user proved their authenticator app works.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TwoFactor {
+    pub user_id: String,
+    // base32 encoded TOTP secret
+    pub secret: String,
+    pub enabled: bool,
+    // SHA-256 hashes of the unused recovery codes
+    pub recovery_code_hashes: Vec<String>,
+    // time step of the last accepted code, so a code can't be replayed
+    pub last_used_step: i64,
+    // codes tried since the last successful login, capped by MAX_TWO_FACTOR_ATTEMPTS
+    #[serde(default)]
+    pub failed_attempts: i32,
+    // unix time until which codes are refused, 0 when not locked out
+    #[serde(default)]
+    pub locked_until: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CodePayload {
+    #[validate(length(min = 1, message = "Code is required"))]
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct EnrollmentResponse {
+    // for typing into apps that can't scan the QR code
+    pub secret: String,
+    pub otpauth_url: String,
+    // QR code of `otpauth_url`
+    pub svg: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
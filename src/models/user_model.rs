@@ -1,9 +1,102 @@
+use chrono::{ DateTime, Utc };
 use serde::{ Deserialize, Serialize };
+use uuid::Uuid;
+use validator::{ Validate, ValidationError };
+
+use super::role_model::Role;
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct User {
+    // Server generated UUID, never changes after creation.
+    pub id: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub username: String,
+    pub email: String,
+    // only admins can change it, records stored before roles existed are viewers
+    #[serde(default)]
+    pub role: Role,
+    // set once the owner followed the link mailed to `email`, cleared when `email` changes
+    #[serde(default)]
+    pub email_verified: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Client supplied fields for creating or updating a user.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Validate)]
+pub struct UserPayload {
+    #[validate(length(min = 1, max = 64, message = "First name must be 1 to 64 characters"))]
     pub first_name: String,
+    #[validate(length(min = 1, max = 64, message = "Last name must be 1 to 64 characters"))]
     pub last_name: String,
+    #[validate(custom(function = "validate_username"))]
     pub username: String,
+    #[validate(
+        email(message = "Email must be a valid address"),
+        length(max = 254, message = "Email must be at most 254 characters")
+    )]
     pub email: String,
 }
+
+/// Usernames are 3 to 32 characters of ASCII letters, digits, `_`, `-` and `.`.
+/// 32 hex digits are a UUID in simple form, so those are rejected to keep usernames
+/// from ever being looked up as an id.
+fn validate_username(username: &str) -> Result<(), ValidationError> {
+    let valid_length = (3..=32).contains(&username.len());
+    let valid_chars = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if valid_length && valid_chars && Uuid::parse_str(username).is_err() {
+        return Ok(());
+    }
+
+    let mut error = ValidationError::new("username");
+    error.message = Some(
+        "Username must be 3 to 32 characters of letters, digits, '_', '-' or '.', \
+         and not a UUID".into()
+    );
+    error.add_param("value".into(), &username);
+    Err(error)
+}
+
+impl User {
+    /// Creates an unverified viewer with a fresh id and both timestamps set to now.
+    pub fn new(payload: UserPayload) -> Self {
+        let now = Utc::now();
+        User {
+            id: Uuid::new_v4().to_string(),
+            first_name: payload.first_name,
+            last_name: payload.last_name,
+            username: payload.username,
+            email: payload.email,
+            role: Role::default(),
+            email_verified: false,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usernames_are_validated() {
+        for username in ["abc", "hello.world_2-x", &"z".repeat(32)] {
+            assert!(validate_username(username).is_ok(), "{username}");
+        }
+        for username in ["ab", &"z".repeat(33), "hello world", "héllo", "a/b"] {
+            assert!(validate_username(username).is_err(), "{username}");
+        }
+    }
+
+    #[test]
+    fn usernames_never_parse_as_ids() {
+        let id = Uuid::new_v4();
+        assert!(validate_username(&id.simple().to_string()).is_err());
+        assert!(validate_username(&id.simple().to_string().to_uppercase()).is_err());
+        assert!(validate_username(&id.to_string()).is_err());
+    }
+}
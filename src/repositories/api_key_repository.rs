@@ -0,0 +1,25 @@
+use async_trait::async_trait;
+
+use crate::models::api_key_model::ApiKey;
+use super::user_repository::RepositoryError;
+
+/// Storage for `ApiKey` records, kept in the same database as the users.
+#[async_trait]
+pub trait ApiKeyRepository: Send + Sync {
+    /// Stores a new key with the hash of its secret. It is kept out of `ApiKey`
+    /// so it can never end up in a response.
+    async fn insert(&self, api_key: ApiKey, key_hash: String) -> Result<(), RepositoryError>;
+
+    /// Finds the key with `key_hash`, including revoked ones.
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError>;
+
+    /// Lists all keys, oldest first.
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError>;
+
+    /// Revokes the key with `id`.
+    /// Returns `false` if there is no such key or it was already revoked.
+    async fn revoke(&self, id: &str) -> Result<bool, RepositoryError>;
+
+    /// Records that the key with `id` was just used.
+    async fn touch(&self, id: &str) -> Result<(), RepositoryError>;
+}
@@ -0,0 +1,64 @@
+use std::sync::{ PoisonError, RwLock };
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::models::api_key_model::ApiKey;
+use super::{ api_key_repository::ApiKeyRepository, user_repository::RepositoryError };
+
+/// Keeps API keys in process memory, with their hashes. Meant for tests and local development.
+#[derive(Default)]
+pub struct MemoryApiKeyRepository {
+    api_keys: RwLock<Vec<(ApiKey, String)>>,
+}
+
+#[async_trait]
+impl ApiKeyRepository for MemoryApiKeyRepository {
+    async fn insert(&self, api_key: ApiKey, key_hash: String) -> Result<(), RepositoryError> {
+        let mut api_keys = self.api_keys.write().unwrap_or_else(PoisonError::into_inner);
+        if api_keys.iter().any(|(existing, hash)| existing.id == api_key.id || *hash == key_hash) {
+            return Err(RepositoryError::DuplicateKey);
+        }
+        api_keys.push((api_key, key_hash));
+        Ok(())
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError> {
+        let api_keys = self.api_keys.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(
+            api_keys
+                .iter()
+                .find(|(_, hash)| hash == key_hash)
+                .map(|(api_key, _)| api_key.clone())
+        )
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError> {
+        let api_keys = self.api_keys.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(
+            api_keys
+                .iter()
+                .map(|(api_key, _)| api_key.clone())
+                .collect()
+        )
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, RepositoryError> {
+        let mut api_keys = self.api_keys.write().unwrap_or_else(PoisonError::into_inner);
+        match api_keys.iter_mut().find(|(api_key, _)| api_key.id == id) {
+            Some((api_key, _)) if api_key.revoked_at.is_none() => {
+                api_key.revoked_at = Some(Utc::now());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn touch(&self, id: &str) -> Result<(), RepositoryError> {
+        let mut api_keys = self.api_keys.write().unwrap_or_else(PoisonError::into_inner);
+        if let Some((api_key, _)) = api_keys.iter_mut().find(|(api_key, _)| api_key.id == id) {
+            api_key.last_used_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::{ collections::HashMap, sync::{ PoisonError, RwLock } };
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::models::refresh_token_model::RefreshToken;
+use super::{ refresh_token_repository::RefreshTokenRepository, user_repository::RepositoryError };
+
+/// Keeps refresh tokens in process memory, keyed by hash. Meant for tests and local development.
+#[derive(Default)]
+pub struct MemoryRefreshTokenRepository {
+    tokens: RwLock<HashMap<String, RefreshToken>>,
+}
+
+#[async_trait]
+impl RefreshTokenRepository for MemoryRefreshTokenRepository {
+    async fn insert(&self, token: RefreshToken) -> Result<(), RepositoryError> {
+        let mut tokens = self.tokens.write().unwrap_or_else(PoisonError::into_inner);
+        tokens.retain(|_, existing| !existing.is_expired());
+        if tokens.contains_key(&token.token_hash) {
+            return Err(RepositoryError::DuplicateKey);
+        }
+        tokens.insert(token.token_hash.clone(), token);
+        Ok(())
+    }
+
+    async fn find(&self, token_hash: &str) -> Result<Option<RefreshToken>, RepositoryError> {
+        let tokens = self.tokens.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(tokens.get(token_hash).cloned())
+    }
+
+    async fn mark_used(&self, token_hash: &str) -> Result<bool, RepositoryError> {
+        let mut tokens = self.tokens.write().unwrap_or_else(PoisonError::into_inner);
+        match tokens.get_mut(token_hash) {
+            Some(token) if token.used_at.is_none() && !token.revoked => {
+                token.used_at = Some(Utc::now());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> Result<(), RepositoryError> {
+        let mut tokens = self.tokens.write().unwrap_or_else(PoisonError::into_inner);
+        tokens
+            .values_mut()
+            .filter(|token| token.family_id == family_id)
+            .for_each(|token| {
+                token.revoked = true;
+            });
+        Ok(())
+    }
+
+    async fn revoke_user(&self, user_id: &str) -> Result<(), RepositoryError> {
+        let mut tokens = self.tokens.write().unwrap_or_else(PoisonError::into_inner);
+        tokens
+            .values_mut()
+            .filter(|token| token.user_id == user_id)
+            .for_each(|token| {
+                token.revoked = true;
+            });
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use std::{ collections::HashMap, sync::{ PoisonError, RwLock } };
+
+use async_trait::async_trait;
+
+use crate::models::two_factor_model::TwoFactor;
+use super::{ two_factor_repository::TwoFactorRepository, user_repository::RepositoryError };
+
+/// Keeps two-factor settings in process memory, keyed by user id.
+/// Meant for tests and local development.
+#[derive(Default)]
+pub struct MemoryTwoFactorRepository {
+    two_factors: RwLock<HashMap<String, TwoFactor>>,
+}
+
+#[async_trait]
+impl TwoFactorRepository for MemoryTwoFactorRepository {
+    async fn find(&self, user_id: &str) -> Result<Option<TwoFactor>, RepositoryError> {
+        let two_factors = self.two_factors.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(two_factors.get(user_id).cloned())
+    }
+
+    async fn save(&self, two_factor: TwoFactor) -> Result<(), RepositoryError> {
+        let mut two_factors = self.two_factors.write().unwrap_or_else(PoisonError::into_inner);
+        two_factors.insert(two_factor.user_id.clone(), two_factor);
+        Ok(())
+    }
+
+    async fn record_step(&self, user_id: &str, step: i64) -> Result<bool, RepositoryError> {
+        let mut two_factors = self.two_factors.write().unwrap_or_else(PoisonError::into_inner);
+        match two_factors.get_mut(user_id) {
+            Some(two_factor) if two_factor.last_used_step < step => {
+                two_factor.last_used_step = step;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_id: &str,
+        code_hash: &str
+    ) -> Result<bool, RepositoryError> {
+        let mut two_factors = self.two_factors.write().unwrap_or_else(PoisonError::into_inner);
+        let Some(two_factor) = two_factors.get_mut(user_id) else {
+            return Ok(false);
+        };
+        let before = two_factor.recovery_code_hashes.len();
+        two_factor.recovery_code_hashes.retain(|hash| hash != code_hash);
+        Ok(two_factor.recovery_code_hashes.len() < before)
+    }
+
+    async fn record_attempt(
+        &self,
+        user_id: &str,
+        max_attempts: i32,
+        now: i64,
+        lock_until: i64
+    ) -> Result<bool, RepositoryError> {
+        let mut two_factors = self.two_factors.write().unwrap_or_else(PoisonError::into_inner);
+        match two_factors.get_mut(user_id) {
+            Some(two_factor) if two_factor.locked_until <= now => {
+                two_factor.failed_attempts += 1;
+                if two_factor.failed_attempts >= max_attempts {
+                    two_factor.locked_until = lock_until;
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn reset_attempts(&self, user_id: &str) -> Result<(), RepositoryError> {
+        let mut two_factors = self.two_factors.write().unwrap_or_else(PoisonError::into_inner);
+        if let Some(two_factor) = two_factors.get_mut(user_id) {
+            two_factor.failed_attempts = 0;
+            two_factor.locked_until = 0;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<bool, RepositoryError> {
+        let mut two_factors = self.two_factors.write().unwrap_or_else(PoisonError::into_inner);
+        Ok(two_factors.remove(user_id).is_some())
+    }
+}
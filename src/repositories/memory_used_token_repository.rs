@@ -0,0 +1,31 @@
+use std::{ collections::HashMap, sync::{ PoisonError, RwLock } };
+
+use async_trait::async_trait;
+use chrono::{ DateTime, Utc };
+
+use super::{ used_token_repository::UsedTokenRepository, user_repository::RepositoryError };
+
+/// Keeps used token ids in process memory. Meant for tests and local development.
+#[derive(Default)]
+pub struct MemoryUsedTokenRepository {
+    // expiry by token id
+    tokens: RwLock<HashMap<String, DateTime<Utc>>>,
+}
+
+#[async_trait]
+impl UsedTokenRepository for MemoryUsedTokenRepository {
+    async fn mark_used(
+        &self,
+        jti: &str,
+        expires_at: DateTime<Utc>
+    ) -> Result<bool, RepositoryError> {
+        let mut tokens = self.tokens.write().unwrap_or_else(PoisonError::into_inner);
+        let now = Utc::now();
+        tokens.retain(|_, expires_at| *expires_at > now);
+        if tokens.contains_key(jti) {
+            return Ok(false);
+        }
+        tokens.insert(jti.to_owned(), expires_at);
+        Ok(true)
+    }
+}
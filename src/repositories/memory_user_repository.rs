@@ -0,0 +1,271 @@
+use std::{ collections::HashMap, sync::{ PoisonError, RwLock } };
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::models::{ role_model::Role, user_model::{ User, UserPayload } };
+use super::user_repository::{ RepositoryError, UserKey, UserRepository };
+
+/// Keeps users in process memory. Meant for tests and local development.
+#[derive(Default)]
+pub struct MemoryUserRepository {
+    users: RwLock<Vec<User>>,
+    // password hashes by user id
+    password_hashes: RwLock<HashMap<String, String>>,
+}
+
+#[async_trait]
+impl UserRepository for MemoryUserRepository {
+    async fn find(&self, key: &UserKey) -> Result<Option<User>, RepositoryError> {
+        let users = self.users.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(users.iter().find(|user| key.matches(user)).cloned())
+    }
+
+    async fn list(
+        &self,
+        search: &str,
+        limit: i64,
+        offset: u64
+    ) -> Result<Vec<User>, RepositoryError> {
+        let search = search.to_lowercase();
+        let users = self.users.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(
+            users
+                .iter()
+                .filter(|user| user.username.to_lowercase().contains(&search))
+                .skip(usize::try_from(offset).unwrap_or(usize::MAX))
+                .take(usize::try_from(limit).unwrap_or(0))
+                .cloned()
+                .collect()
+        )
+    }
+
+    async fn insert(
+        &self,
+        user: User,
+        password_hash: Option<String>
+    ) -> Result<(), RepositoryError> {
+        let mut users = self.users.write().unwrap_or_else(PoisonError::into_inner);
+        if
+            users
+                .iter()
+                .any(|existing| existing.username == user.username || existing.id == user.id)
+        {
+            return Err(RepositoryError::DuplicateKey);
+        }
+        if let Some(password_hash) = password_hash {
+            self.password_hashes
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(user.id.clone(), password_hash);
+        }
+        users.push(user);
+        Ok(())
+    }
+
+    async fn password_hash(&self, key: &UserKey) -> Result<Option<String>, RepositoryError> {
+        let users = self.users.read().unwrap_or_else(PoisonError::into_inner);
+        let password_hashes = self.password_hashes.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(
+            users
+                .iter()
+                .find(|user| key.matches(user))
+                .and_then(|user| password_hashes.get(&user.id).cloned())
+        )
+    }
+
+    async fn update(
+        &self,
+        key: &UserKey,
+        changes: UserPayload
+    ) -> Result<Option<User>, RepositoryError> {
+        let mut users = self.users.write().unwrap_or_else(PoisonError::into_inner);
+        if
+            users
+                .iter()
+                .any(|existing| existing.username == changes.username && !key.matches(existing))
+        {
+            return Err(RepositoryError::DuplicateKey);
+        }
+        Ok(
+            users
+                .iter_mut()
+                .find(|existing| key.matches(existing))
+                .map(|existing| {
+                    existing.first_name = changes.first_name;
+                    existing.last_name = changes.last_name;
+                    existing.username = changes.username;
+                    existing.email = changes.email;
+                    existing.updated_at = Utc::now();
+                    existing.clone()
+                })
+        )
+    }
+
+    async fn set_role(&self, key: &UserKey, role: Role) -> Result<Option<User>, RepositoryError> {
+        let mut users = self.users.write().unwrap_or_else(PoisonError::into_inner);
+        Ok(
+            users
+                .iter_mut()
+                .find(|existing| key.matches(existing))
+                .map(|existing| {
+                    existing.role = role;
+                    existing.updated_at = Utc::now();
+                    existing.clone()
+                })
+        )
+    }
+
+    async fn set_email_verified(
+        &self,
+        key: &UserKey,
+        email: &str,
+        verified: bool
+    ) -> Result<bool, RepositoryError> {
+        let mut users = self.users.write().unwrap_or_else(PoisonError::into_inner);
+        match users.iter_mut().find(|existing| key.matches(existing) && existing.email == email) {
+            Some(existing) => {
+                existing.email_verified = verified;
+                existing.updated_at = Utc::now();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_password_hash(
+        &self,
+        key: &UserKey,
+        password_hash: String
+    ) -> Result<bool, RepositoryError> {
+        let mut users = self.users.write().unwrap_or_else(PoisonError::into_inner);
+        let Some(existing) = users.iter_mut().find(|existing| key.matches(existing)) else {
+            return Ok(false);
+        };
+        existing.updated_at = Utc::now();
+        self.password_hashes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(existing.id.clone(), password_hash);
+        Ok(true)
+    }
+
+    async fn delete(&self, key: &UserKey) -> Result<bool, RepositoryError> {
+        let mut users = self.users.write().unwrap_or_else(PoisonError::into_inner);
+        let mut password_hashes = self.password_hashes.write().unwrap_or_else(PoisonError::into_inner);
+        let before = users.len();
+        users.retain(|user| {
+            let matches = key.matches(user);
+            if matches {
+                password_hashes.remove(&user.id);
+            }
+            !matches
+        });
+        Ok(users.len() < before)
+    }
+
+    async fn count(&self) -> Result<u64, RepositoryError> {
+        let users = self.users.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(users.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn payload(username: &str) -> UserPayload {
+        UserPayload {
+            first_name: "hello".to_owned(),
+            last_name: "world".to_owned(),
+            username: username.to_owned(),
+            email: format!("{username}@example.com"),
+        }
+    }
+
+    async fn repository_with(usernames: &[&str]) -> MemoryUserRepository {
+        let repository = MemoryUserRepository::default();
+        for username in usernames {
+            repository.insert(User::new(payload(username)), None).await.unwrap();
+        }
+        repository
+    }
+
+    #[actix_web::test]
+    async fn insert_rejects_taken_username() {
+        let repository = repository_with(&["hello"]).await;
+        let result = repository.insert(User::new(payload("hello")), None).await;
+        assert!(matches!(result, Err(RepositoryError::DuplicateKey)));
+        assert_eq!(repository.count().await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn update_rejects_taken_username() {
+        let repository = repository_with(&["hello", "world"]).await;
+        let key = UserKey::Username("world".to_owned());
+        let result = repository.update(&key, payload("hello")).await;
+        assert!(matches!(result, Err(RepositoryError::DuplicateKey)));
+    }
+
+    #[actix_web::test]
+    async fn update_keeps_id_and_created_at() {
+        let repository = repository_with(&["hello"]).await;
+        let user = repository.find(&UserKey::Username("hello".to_owned())).await.unwrap().unwrap();
+        let key = UserKey::parse(user.id.clone());
+        let updated = repository.update(&key, payload("renamed")).await.unwrap().unwrap();
+        assert_eq!(updated.id, user.id);
+        assert_eq!(updated.created_at, user.created_at);
+        assert_eq!(updated.username, "renamed");
+        assert!(updated.updated_at >= user.updated_at);
+    }
+
+    #[actix_web::test]
+    async fn missing_user_is_not_updated_or_deleted() {
+        let repository = repository_with(&["hello"]).await;
+        for key in [UserKey::Username("missing".to_owned()), UserKey::Id(Uuid::new_v4())] {
+            assert!(repository.update(&key, payload("missing")).await.unwrap().is_none());
+            assert!(repository.set_role(&key, Role::Admin).await.unwrap().is_none());
+            assert!(!repository.set_password_hash(&key, "hash".to_owned()).await.unwrap());
+            assert!(!repository.delete(&key).await.unwrap());
+        }
+        assert_eq!(repository.count().await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn delete_removes_user_and_password_hash() {
+        let repository = MemoryUserRepository::default();
+        repository.insert(User::new(payload("hello")), Some("hash".to_owned())).await.unwrap();
+        let key = UserKey::Username("hello".to_owned());
+        assert!(repository.delete(&key).await.unwrap());
+        assert!(repository.find(&key).await.unwrap().is_none());
+        assert!(repository.password_hash(&key).await.unwrap().is_none());
+        assert!(!repository.delete(&key).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn list_pages_through_matches() {
+        let repository = repository_with(&["user1", "user2", "user3", "other"]).await;
+        let usernames = |users: Vec<User>| {
+            users
+                .into_iter()
+                .map(|user| user.username)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(usernames(repository.list("USER", 2, 0).await.unwrap()), ["user1", "user2"]);
+        assert_eq!(usernames(repository.list("user", 2, 2).await.unwrap()), ["user3"]);
+        assert!(repository.list("user", 2, 4).await.unwrap().is_empty());
+        assert!(repository.list("", 0, 0).await.unwrap().is_empty());
+        assert_eq!(repository.list("", 10, 0).await.unwrap().len(), 4);
+    }
+
+    #[actix_web::test]
+    async fn list_search_is_plain_text() {
+        let repository = repository_with(&["a.b", "axb", "a_b"]).await;
+        for (search, expected) in [(".", 1), ("_", 1), ("a.", 1), ("(", 0), ("[a-z]", 0)] {
+            let users = repository.list(search, 10, 0).await.unwrap();
+            assert_eq!(users.len(), expected, "{search}");
+        }
+    }
+}
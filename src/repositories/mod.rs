@@ -0,0 +1,21 @@
+pub mod user_repository;
+pub mod mongo_user_repository;
+pub mod postgres_user_repository;
+pub mod memory_user_repository;
+pub mod traced_user_repository;
+pub mod refresh_token_repository;
+pub mod mongo_refresh_token_repository;
+pub mod postgres_refresh_token_repository;
+pub mod memory_refresh_token_repository;
+pub mod api_key_repository;
+pub mod mongo_api_key_repository;
+pub mod postgres_api_key_repository;
+pub mod memory_api_key_repository;
+pub mod two_factor_repository;
+pub mod mongo_two_factor_repository;
+pub mod postgres_two_factor_repository;
+pub mod memory_two_factor_repository;
+pub mod used_token_repository;
+pub mod mongo_used_token_repository;
+pub mod postgres_used_token_repository;
+pub mod memory_used_token_repository;
@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use mongodb::{ bson::{ doc, to_bson, to_document, Bson, Document }, Collection, Database };
+
+use crate::models::api_key_model::ApiKey;
+use super::{ api_key_repository::ApiKeyRepository, user_repository::RepositoryError };
+
+pub const API_KEY_COLLECTION: &str = "api_keys";
+
+pub struct MongoApiKeyRepository {
+    collection: Collection<ApiKey>,
+}
+
+impl MongoApiKeyRepository {
+    pub fn new(db: &Database) -> Self {
+        MongoApiKeyRepository {
+            collection: db.collection(API_KEY_COLLECTION),
+        }
+    }
+}
+
+// serialize timestamps the same way `ApiKey` does
+fn now() -> Result<Bson, RepositoryError> {
+    to_bson(&Utc::now()).map_err(|err| RepositoryError::Backend { message: err.to_string() })
+}
+
+#[async_trait]
+impl ApiKeyRepository for MongoApiKeyRepository {
+    async fn insert(&self, api_key: ApiKey, key_hash: String) -> Result<(), RepositoryError> {
+        let mut document = to_document(&api_key).map_err(|err| RepositoryError::Backend {
+            message: err.to_string(),
+        })?;
+        document.insert("key_hash", key_hash);
+        self.collection.clone_with_type::<Document>().insert_one(document).await?;
+        Ok(())
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError> {
+        Ok(self.collection.find_one(doc! { "key_hash": key_hash }).await?)
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError> {
+        let cursor = self.collection.find(doc! {}).sort(doc! { "created_at": 1 }).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, RepositoryError> {
+        let result = self.collection.update_one(
+            doc! { "id": id, "revoked_at": null },
+            doc! { "$set": { "revoked_at": now()? } }
+        ).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    async fn touch(&self, id: &str) -> Result<(), RepositoryError> {
+        self.collection.update_one(
+            doc! { "id": id },
+            doc! { "$set": { "last_used_at": now()? } }
+        ).await?;
+        Ok(())
+    }
+}
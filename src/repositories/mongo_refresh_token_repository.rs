@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+use chrono::{ DateTime as ChronoDateTime, Utc };
+use mongodb::{ bson::{ doc, DateTime }, Collection, Database };
+use serde::{ Deserialize, Serialize };
+
+use crate::models::refresh_token_model::RefreshToken;
+use super::{ refresh_token_repository::RefreshTokenRepository, user_repository::RepositoryError };
+
+pub const REFRESH_TOKEN_COLLECTION: &str = "refresh_tokens";
+
+// Timestamps are stored as BSON dates, the TTL index on `expires_at` ignores anything else.
+#[derive(Serialize, Deserialize)]
+struct RefreshTokenDocument {
+    token_hash: String,
+    family_id: String,
+    user_id: String,
+    created_at: DateTime,
+    expires_at: DateTime,
+    used_at: Option<DateTime>,
+    revoked: bool,
+}
+
+fn to_bson_date(time: ChronoDateTime<Utc>) -> DateTime {
+    DateTime::from_millis(time.timestamp_millis())
+}
+
+fn to_chrono(time: DateTime) -> ChronoDateTime<Utc> {
+    ChronoDateTime::from_timestamp_millis(time.timestamp_millis()).unwrap_or_default()
+}
+
+impl From<RefreshToken> for RefreshTokenDocument {
+    fn from(token: RefreshToken) -> Self {
+        RefreshTokenDocument {
+            token_hash: token.token_hash,
+            family_id: token.family_id,
+            user_id: token.user_id,
+            created_at: to_bson_date(token.created_at),
+            expires_at: to_bson_date(token.expires_at),
+            used_at: token.used_at.map(to_bson_date),
+            revoked: token.revoked,
+        }
+    }
+}
+
+impl From<RefreshTokenDocument> for RefreshToken {
+    fn from(document: RefreshTokenDocument) -> Self {
+        RefreshToken {
+            token_hash: document.token_hash,
+            family_id: document.family_id,
+            user_id: document.user_id,
+            created_at: to_chrono(document.created_at),
+            expires_at: to_chrono(document.expires_at),
+            used_at: document.used_at.map(to_chrono),
+            revoked: document.revoked,
+        }
+    }
+}
+
+/// MongoDB backend. Expired tokens are removed by the TTL index created in `main`.
+pub struct MongoRefreshTokenRepository {
+    collection: Collection<RefreshTokenDocument>,
+}
+
+impl MongoRefreshTokenRepository {
+    pub fn new(db: &Database) -> Self {
+        MongoRefreshTokenRepository {
+            collection: db.collection(REFRESH_TOKEN_COLLECTION),
+        }
+    }
+}
+
+#[async_trait]
+impl RefreshTokenRepository for MongoRefreshTokenRepository {
+    async fn insert(&self, token: RefreshToken) -> Result<(), RepositoryError> {
+        self.collection.insert_one(RefreshTokenDocument::from(token)).await?;
+        Ok(())
+    }
+
+    async fn find(&self, token_hash: &str) -> Result<Option<RefreshToken>, RepositoryError> {
+        let document = self.collection.find_one(doc! { "token_hash": token_hash }).await?;
+        Ok(document.map(RefreshToken::from))
+    }
+
+    async fn mark_used(&self, token_hash: &str) -> Result<bool, RepositoryError> {
+        let result = self.collection.update_one(
+            doc! { "token_hash": token_hash, "used_at": null, "revoked": false },
+            doc! { "$set": { "used_at": DateTime::now() } }
+        ).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> Result<(), RepositoryError> {
+        self.collection.update_many(
+            doc! { "family_id": family_id },
+            doc! { "$set": { "revoked": true } }
+        ).await?;
+        Ok(())
+    }
+
+    async fn revoke_user(&self, user_id: &str) -> Result<(), RepositoryError> {
+        self.collection.update_many(
+            doc! { "user_id": user_id },
+            doc! { "$set": { "revoked": true } }
+        ).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use async_trait::async_trait;
+use mongodb::{ bson::doc, Collection, Database };
+
+use crate::models::two_factor_model::TwoFactor;
+use super::{ two_factor_repository::TwoFactorRepository, user_repository::RepositoryError };
+
+pub const TWO_FACTOR_COLLECTION: &str = "two_factor";
+
+pub struct MongoTwoFactorRepository {
+    collection: Collection<TwoFactor>,
+}
+
+impl MongoTwoFactorRepository {
+    pub fn new(db: &Database) -> Self {
+        MongoTwoFactorRepository {
+            collection: db.collection(TWO_FACTOR_COLLECTION),
+        }
+    }
+}
+
+#[async_trait]
+impl TwoFactorRepository for MongoTwoFactorRepository {
+    async fn find(&self, user_id: &str) -> Result<Option<TwoFactor>, RepositoryError> {
+        Ok(self.collection.find_one(doc! { "user_id": user_id }).await?)
+    }
+
+    async fn save(&self, two_factor: TwoFactor) -> Result<(), RepositoryError> {
+        self.collection
+            .replace_one(doc! { "user_id": &two_factor.user_id }, &two_factor)
+            .upsert(true).await?;
+        Ok(())
+    }
+
+    async fn record_step(&self, user_id: &str, step: i64) -> Result<bool, RepositoryError> {
+        let result = self.collection.update_one(
+            doc! { "user_id": user_id, "last_used_step": { "$lt": step } },
+            doc! { "$set": { "last_used_step": step } }
+        ).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_id: &str,
+        code_hash: &str
+    ) -> Result<bool, RepositoryError> {
+        let result = self.collection.update_one(
+            doc! { "user_id": user_id, "recovery_code_hashes": code_hash },
+            doc! { "$pull": { "recovery_code_hashes": code_hash } }
+        ).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    async fn record_attempt(
+        &self,
+        user_id: &str,
+        max_attempts: i32,
+        now: i64,
+        lock_until: i64
+    ) -> Result<bool, RepositoryError> {
+        // documents from before the lockout lack the counters, `$not` matches them too
+        let attempts = doc! { "$add": [{ "$ifNull": ["$failed_attempts", 0] }, 1] };
+        let locked_until = doc! {
+            "$cond": [
+                { "$gte": [attempts.clone(), max_attempts] },
+                lock_until,
+                { "$ifNull": ["$locked_until", 0_i64] },
+            ],
+        };
+        let result = self.collection.update_one(
+            doc! { "user_id": user_id, "locked_until": { "$not": { "$gt": now } } },
+            vec![doc! { "$set": { "failed_attempts": attempts, "locked_until": locked_until } }]
+        ).await?;
+        Ok(result.matched_count == 1)
+    }
+
+    async fn reset_attempts(&self, user_id: &str) -> Result<(), RepositoryError> {
+        self.collection.update_one(
+            doc! { "user_id": user_id },
+            doc! { "$set": { "failed_attempts": 0, "locked_until": 0_i64 } }
+        ).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<bool, RepositoryError> {
+        let result = self.collection.delete_one(doc! { "user_id": user_id }).await?;
+        Ok(result.deleted_count == 1)
+    }
+}
@@ -0,0 +1,48 @@
+use async_trait::async_trait;
+use chrono::{ DateTime as ChronoDateTime, Utc };
+use mongodb::{ bson::DateTime, Collection, Database };
+use serde::{ Deserialize, Serialize };
+
+use super::{ used_token_repository::UsedTokenRepository, user_repository::RepositoryError };
+
+pub const USED_TOKEN_COLLECTION: &str = "used_tokens";
+
+// `expires_at` is a BSON date for the TTL index.
+#[derive(Serialize, Deserialize)]
+struct UsedTokenDocument {
+    jti: String,
+    expires_at: DateTime,
+}
+
+/// MongoDB backend. Expired ids are removed by the TTL index created in `main`,
+/// the unique index on `jti` makes a second use fail.
+pub struct MongoUsedTokenRepository {
+    collection: Collection<UsedTokenDocument>,
+}
+
+impl MongoUsedTokenRepository {
+    pub fn new(db: &Database) -> Self {
+        MongoUsedTokenRepository {
+            collection: db.collection(USED_TOKEN_COLLECTION),
+        }
+    }
+}
+
+#[async_trait]
+impl UsedTokenRepository for MongoUsedTokenRepository {
+    async fn mark_used(
+        &self,
+        jti: &str,
+        expires_at: ChronoDateTime<Utc>
+    ) -> Result<bool, RepositoryError> {
+        let document = UsedTokenDocument {
+            jti: jti.to_owned(),
+            expires_at: DateTime::from_millis(expires_at.timestamp_millis()),
+        };
+        match self.collection.insert_one(document).await.map_err(RepositoryError::from) {
+            Ok(_) => Ok(true),
+            Err(RepositoryError::DuplicateKey) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
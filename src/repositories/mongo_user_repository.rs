@@ -0,0 +1,253 @@
+use async_trait::async_trait;
+use chrono::{ DateTime, Utc };
+use uuid::Uuid;
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{ doc, to_bson, to_document, Document },
+    error::{ Error, ErrorKind, WriteFailure },
+    options::ReturnDocument,
+    Collection,
+    Database,
+};
+
+use crate::models::{ role_model::Role, user_model::{ User, UserPayload } };
+use super::user_repository::{ RepositoryError, UserKey, UserRepository };
+
+// MongoDB duplicate key error code.
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+pub struct MongoUserRepository {
+    collection: Collection<User>,
+}
+
+impl MongoUserRepository {
+    /// Keeps users in `collection`, `database.users_collection` in the settings.
+    pub fn new(db: &Database, collection: &str) -> Self {
+        MongoUserRepository {
+            collection: db.collection(collection),
+        }
+    }
+}
+
+// A backslash makes any punctuation a literal in a regex, so `search` matches as plain text.
+fn escape_regex(search: &str) -> String {
+    let mut escaped = String::with_capacity(search.len());
+    for c in search.chars() {
+        if c.is_ascii_punctuation() {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl From<Error> for RepositoryError {
+    fn from(err: Error) -> Self {
+        let duplicate = match *err.kind {
+            ErrorKind::Write(WriteFailure::WriteError(ref write_error)) => {
+                write_error.code == DUPLICATE_KEY_CODE
+            }
+            ErrorKind::Command(ref command_error) => command_error.code == DUPLICATE_KEY_CODE,
+            _ => false,
+        };
+        if duplicate {
+            RepositoryError::DuplicateKey
+        } else {
+            RepositoryError::Backend { message: err.to_string() }
+        }
+    }
+}
+
+/// Gives user documents stored before ids and timestamps existed an `id`, `created_at` and
+/// `updated_at`, like PostgreSQL migration 0002. `created_at` is taken from the ObjectId when
+/// there is one. Runs before the unique index on `id` is created. Returns the documents updated.
+pub async fn backfill_users(db: &Database, collection: &str) -> Result<u64, Error> {
+    let collection = db.collection::<Document>(collection);
+    let missing = doc! {
+        "$or": [
+            { "id": { "$exists": false } },
+            { "created_at": { "$exists": false } },
+            { "updated_at": { "$exists": false } },
+        ],
+    };
+    let mut cursor = collection.find(missing).await?;
+    let mut updated = 0;
+    while let Some(document) = cursor.try_next().await? {
+        let Some(object_id) = document.get("_id") else {
+            continue;
+        };
+        let created_at = object_id
+            .as_object_id()
+            .and_then(|object_id| {
+                DateTime::from_timestamp_millis(object_id.timestamp().timestamp_millis())
+            })
+            .unwrap_or_else(Utc::now);
+        let created_at = to_bson(&created_at)?;
+        let mut fields = Document::new();
+        if !document.contains_key("id") {
+            fields.insert("id", Uuid::new_v4().to_string());
+        }
+        if !document.contains_key("created_at") {
+            fields.insert("created_at", created_at.clone());
+        }
+        if !document.contains_key("updated_at") {
+            let updated_at = document.get("created_at").cloned().unwrap_or(created_at);
+            fields.insert("updated_at", updated_at);
+        }
+        collection.update_one(doc! { "_id": object_id.clone() }, doc! { "$set": fields }).await?;
+        updated += 1;
+    }
+    Ok(updated)
+}
+
+fn filter(key: &UserKey) -> Document {
+    match key {
+        UserKey::Id(id) => doc! { "id": id.to_string() },
+        UserKey::Username(username) => doc! { "username": username },
+    }
+}
+
+#[async_trait]
+impl UserRepository for MongoUserRepository {
+    async fn find(&self, key: &UserKey) -> Result<Option<User>, RepositoryError> {
+        Ok(self.collection.find_one(filter(key)).await?)
+    }
+
+    async fn list(
+        &self,
+        search: &str,
+        limit: i64,
+        offset: u64
+    ) -> Result<Vec<User>, RepositoryError> {
+        let cursor = self.collection
+            .find(doc! { "username": {"$regex": escape_regex(search), "$options": "i"} })
+            .limit(limit)
+            .skip(offset).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn insert(
+        &self,
+        user: User,
+        password_hash: Option<String>
+    ) -> Result<(), RepositoryError> {
+        let mut document = to_document(&user).map_err(|err| RepositoryError::Backend {
+            message: err.to_string(),
+        })?;
+        if let Some(password_hash) = password_hash {
+            document.insert("password_hash", password_hash);
+        }
+        self.collection.clone_with_type::<Document>().insert_one(document).await?;
+        Ok(())
+    }
+
+    async fn password_hash(&self, key: &UserKey) -> Result<Option<String>, RepositoryError> {
+        let document = self.collection
+            .clone_with_type::<Document>()
+            .find_one(filter(key))
+            .projection(doc! { "password_hash": 1 }).await?;
+        Ok(document.and_then(|document| document.get_str("password_hash").ok().map(str::to_owned)))
+    }
+
+    async fn update(
+        &self,
+        key: &UserKey,
+        changes: UserPayload
+    ) -> Result<Option<User>, RepositoryError> {
+        // serialize the timestamp the same way `User` does
+        let updated_at = to_bson(&Utc::now()).map_err(|err| RepositoryError::Backend {
+            message: err.to_string(),
+        })?;
+        let result = self.collection
+            .find_one_and_update(
+                filter(key),
+                doc! {"$set":{
+                            "first_name": changes.first_name,
+                            "last_name": changes.last_name,
+                            "username": changes.username,
+                            "email": changes.email,
+                            "updated_at": updated_at,
+                         }}
+            )
+            .return_document(ReturnDocument::After).await?;
+        Ok(result)
+    }
+
+    async fn set_role(&self, key: &UserKey, role: Role) -> Result<Option<User>, RepositoryError> {
+        let updated_at = to_bson(&Utc::now()).map_err(|err| RepositoryError::Backend {
+            message: err.to_string(),
+        })?;
+        let result = self.collection
+            .find_one_and_update(
+                filter(key),
+                doc! { "$set": { "role": role.to_string(), "updated_at": updated_at } }
+            )
+            .return_document(ReturnDocument::After).await?;
+        Ok(result)
+    }
+
+    async fn set_email_verified(
+        &self,
+        key: &UserKey,
+        email: &str,
+        verified: bool
+    ) -> Result<bool, RepositoryError> {
+        let updated_at = to_bson(&Utc::now()).map_err(|err| RepositoryError::Backend {
+            message: err.to_string(),
+        })?;
+        let mut filter = filter(key);
+        filter.insert("email", email);
+        let result = self.collection.update_one(
+            filter,
+            doc! { "$set": { "email_verified": verified, "updated_at": updated_at } }
+        ).await?;
+        Ok(result.matched_count == 1)
+    }
+
+    async fn set_password_hash(
+        &self,
+        key: &UserKey,
+        password_hash: String
+    ) -> Result<bool, RepositoryError> {
+        let updated_at = to_bson(&Utc::now()).map_err(|err| RepositoryError::Backend {
+            message: err.to_string(),
+        })?;
+        let result = self.collection.update_one(
+            filter(key),
+            doc! { "$set": { "password_hash": password_hash, "updated_at": updated_at } }
+        ).await?;
+        Ok(result.matched_count == 1)
+    }
+
+    async fn delete(&self, key: &UserKey) -> Result<bool, RepositoryError> {
+        let result = self.collection.delete_one(filter(key)).await?;
+        Ok(result.deleted_count == 1)
+    }
+
+    async fn count(&self) -> Result<u64, RepositoryError> {
+        Ok(self.collection.count_documents(doc! {}).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::{ options::ClientOptions, Client };
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn users_are_kept_in_the_configured_collection() {
+        // the client connects lazily, so no server is needed
+        let options = ClientOptions::parse("mongodb://localhost:27017").await.unwrap();
+        let db = Client::with_options(options).unwrap().database("app");
+        let repository = MongoUserRepository::new(&db, "members");
+        assert_eq!(repository.collection.namespace().to_string(), "app.members");
+    }
+
+    #[test]
+    fn regex_is_escaped() {
+        assert_eq!(escape_regex("hello"), "hello");
+        assert_eq!(escape_regex("a.b(c)*"), r"a\.b\(c\)\*");
+        assert_eq!(escape_regex(r"\d"), r"\\d");
+    }
+}
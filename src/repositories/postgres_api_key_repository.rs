@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+use deadpool_postgres::Pool;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::models::api_key_model::ApiKey;
+use super::{ api_key_repository::ApiKeyRepository, user_repository::RepositoryError };
+
+const API_KEY_COLUMNS: &str =
+    "id, name, prefix, scopes, created_by, created_at, last_used_at, revoked_at";
+
+pub struct PostgresApiKeyRepository {
+    pool: Pool,
+}
+
+impl PostgresApiKeyRepository {
+    pub fn new(pool: Pool) -> Self {
+        PostgresApiKeyRepository { pool }
+    }
+}
+
+fn api_key_from_row(row: &Row) -> ApiKey {
+    ApiKey {
+        id: row.get::<_, Uuid>("id").to_string(),
+        name: row.get("name"),
+        prefix: row.get("prefix"),
+        // scopes that are no longer known are dropped
+        scopes: row
+            .get::<_, Vec<String>>("scopes")
+            .iter()
+            .filter_map(|scope| scope.parse().ok())
+            .collect(),
+        created_by: row.get::<_, Uuid>("created_by").to_string(),
+        created_at: row.get("created_at"),
+        last_used_at: row.get("last_used_at"),
+        revoked_at: row.get("revoked_at"),
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for PostgresApiKeyRepository {
+    async fn insert(&self, api_key: ApiKey, key_hash: String) -> Result<(), RepositoryError> {
+        let id = Uuid::parse_str(&api_key.id)?;
+        let created_by = Uuid::parse_str(&api_key.created_by)?;
+        let scopes: Vec<&str> = api_key.scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect();
+        let client = self.pool.get().await?;
+        client.execute(
+            &format!(
+                "INSERT INTO api_keys ({API_KEY_COLUMNS}, key_hash)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"
+            ),
+            &[
+                &id,
+                &api_key.name,
+                &api_key.prefix,
+                &scopes,
+                &created_by,
+                &api_key.created_at,
+                &api_key.last_used_at,
+                &api_key.revoked_at,
+                &key_hash,
+            ]
+        ).await?;
+        Ok(())
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError> {
+        let client = self.pool.get().await?;
+        let row = client.query_opt(
+            &format!("SELECT {API_KEY_COLUMNS} FROM api_keys WHERE key_hash = $1"),
+            &[&key_hash]
+        ).await?;
+        Ok(row.as_ref().map(api_key_from_row))
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, RepositoryError> {
+        let client = self.pool.get().await?;
+        let rows = client.query(
+            &format!("SELECT {API_KEY_COLUMNS} FROM api_keys ORDER BY created_at"),
+            &[]
+        ).await?;
+        Ok(rows.iter().map(api_key_from_row).collect())
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, RepositoryError> {
+        // not a UUID, so not a key id either
+        let Ok(id) = Uuid::parse_str(id) else {
+            return Ok(false);
+        };
+        let client = self.pool.get().await?;
+        let updated = client.execute(
+            "UPDATE api_keys SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL",
+            &[&id]
+        ).await?;
+        Ok(updated == 1)
+    }
+
+    async fn touch(&self, id: &str) -> Result<(), RepositoryError> {
+        let id = Uuid::parse_str(id)?;
+        let client = self.pool.get().await?;
+        client.execute("UPDATE api_keys SET last_used_at = now() WHERE id = $1", &[&id]).await?;
+        Ok(())
+    }
+}
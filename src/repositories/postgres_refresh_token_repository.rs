@@ -0,0 +1,99 @@
+use async_trait::async_trait;
+use deadpool_postgres::Pool;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::models::refresh_token_model::RefreshToken;
+use super::{ refresh_token_repository::RefreshTokenRepository, user_repository::RepositoryError };
+
+const REFRESH_TOKEN_COLUMNS: &str =
+    "token_hash, family_id, user_id, created_at, expires_at, used_at, revoked";
+
+/// PostgreSQL backend. There is no TTL in PostgreSQL, so expired tokens are
+/// deleted whenever a new one is inserted.
+pub struct PostgresRefreshTokenRepository {
+    pool: Pool,
+}
+
+impl PostgresRefreshTokenRepository {
+    pub fn new(pool: Pool) -> Self {
+        PostgresRefreshTokenRepository { pool }
+    }
+}
+
+fn refresh_token_from_row(row: &Row) -> RefreshToken {
+    RefreshToken {
+        token_hash: row.get("token_hash"),
+        family_id: row.get::<_, Uuid>("family_id").to_string(),
+        user_id: row.get::<_, Uuid>("user_id").to_string(),
+        created_at: row.get("created_at"),
+        expires_at: row.get("expires_at"),
+        used_at: row.get("used_at"),
+        revoked: row.get("revoked"),
+    }
+}
+
+#[async_trait]
+impl RefreshTokenRepository for PostgresRefreshTokenRepository {
+    async fn insert(&self, token: RefreshToken) -> Result<(), RepositoryError> {
+        let family_id = Uuid::parse_str(&token.family_id)?;
+        let user_id = Uuid::parse_str(&token.user_id)?;
+        let client = self.pool.get().await?;
+        client.execute("DELETE FROM refresh_tokens WHERE expires_at < now()", &[]).await?;
+        client.execute(
+            &format!(
+                "INSERT INTO refresh_tokens ({REFRESH_TOKEN_COLUMNS})
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)"
+            ),
+            &[
+                &token.token_hash,
+                &family_id,
+                &user_id,
+                &token.created_at,
+                &token.expires_at,
+                &token.used_at,
+                &token.revoked,
+            ]
+        ).await?;
+        Ok(())
+    }
+
+    async fn find(&self, token_hash: &str) -> Result<Option<RefreshToken>, RepositoryError> {
+        let client = self.pool.get().await?;
+        let row = client.query_opt(
+            &format!("SELECT {REFRESH_TOKEN_COLUMNS} FROM refresh_tokens WHERE token_hash = $1"),
+            &[&token_hash]
+        ).await?;
+        Ok(row.as_ref().map(refresh_token_from_row))
+    }
+
+    async fn mark_used(&self, token_hash: &str) -> Result<bool, RepositoryError> {
+        let client = self.pool.get().await?;
+        let updated = client.execute(
+            "UPDATE refresh_tokens SET used_at = now()
+             WHERE token_hash = $1 AND used_at IS NULL AND NOT revoked",
+            &[&token_hash]
+        ).await?;
+        Ok(updated == 1)
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> Result<(), RepositoryError> {
+        let family_id = Uuid::parse_str(family_id)?;
+        let client = self.pool.get().await?;
+        client.execute(
+            "UPDATE refresh_tokens SET revoked = TRUE WHERE family_id = $1",
+            &[&family_id]
+        ).await?;
+        Ok(())
+    }
+
+    async fn revoke_user(&self, user_id: &str) -> Result<(), RepositoryError> {
+        let user_id = Uuid::parse_str(user_id)?;
+        let client = self.pool.get().await?;
+        client.execute(
+            "UPDATE refresh_tokens SET revoked = TRUE WHERE user_id = $1",
+            &[&user_id]
+        ).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use async_trait::async_trait;
+use deadpool_postgres::Pool;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::models::two_factor_model::TwoFactor;
+use super::{ two_factor_repository::TwoFactorRepository, user_repository::RepositoryError };
+
+const TWO_FACTOR_COLUMNS: &str =
+    "user_id, secret, enabled, recovery_code_hashes, last_used_step, failed_attempts, \
+     locked_until, created_at";
+
+pub struct PostgresTwoFactorRepository {
+    pool: Pool,
+}
+
+impl PostgresTwoFactorRepository {
+    pub fn new(pool: Pool) -> Self {
+        PostgresTwoFactorRepository { pool }
+    }
+}
+
+fn two_factor_from_row(row: &Row) -> TwoFactor {
+    TwoFactor {
+        user_id: row.get::<_, Uuid>("user_id").to_string(),
+        secret: row.get("secret"),
+        enabled: row.get("enabled"),
+        recovery_code_hashes: row.get("recovery_code_hashes"),
+        last_used_step: row.get("last_used_step"),
+        failed_attempts: row.get("failed_attempts"),
+        locked_until: row.get("locked_until"),
+        created_at: row.get("created_at"),
+    }
+}
+
+#[async_trait]
+impl TwoFactorRepository for PostgresTwoFactorRepository {
+    async fn find(&self, user_id: &str) -> Result<Option<TwoFactor>, RepositoryError> {
+        let user_id = Uuid::parse_str(user_id)?;
+        let client = self.pool.get().await?;
+        let row = client.query_opt(
+            &format!("SELECT {TWO_FACTOR_COLUMNS} FROM user_two_factor WHERE user_id = $1"),
+            &[&user_id]
+        ).await?;
+        Ok(row.as_ref().map(two_factor_from_row))
+    }
+
+    async fn save(&self, two_factor: TwoFactor) -> Result<(), RepositoryError> {
+        let user_id = Uuid::parse_str(&two_factor.user_id)?;
+        let client = self.pool.get().await?;
+        client.execute(
+            &format!(
+                "INSERT INTO user_two_factor ({TWO_FACTOR_COLUMNS})
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                 ON CONFLICT (user_id) DO UPDATE SET secret = $2, enabled = $3,
+                 recovery_code_hashes = $4, last_used_step = $5, failed_attempts = $6,
+                 locked_until = $7, created_at = $8"
+            ),
+            &[
+                &user_id,
+                &two_factor.secret,
+                &two_factor.enabled,
+                &two_factor.recovery_code_hashes,
+                &two_factor.last_used_step,
+                &two_factor.failed_attempts,
+                &two_factor.locked_until,
+                &two_factor.created_at,
+            ]
+        ).await?;
+        Ok(())
+    }
+
+    async fn record_step(&self, user_id: &str, step: i64) -> Result<bool, RepositoryError> {
+        let user_id = Uuid::parse_str(user_id)?;
+        let client = self.pool.get().await?;
+        let updated = client.execute(
+            "UPDATE user_two_factor SET last_used_step = $2
+             WHERE user_id = $1 AND last_used_step < $2",
+            &[&user_id, &step]
+        ).await?;
+        Ok(updated == 1)
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_id: &str,
+        code_hash: &str
+    ) -> Result<bool, RepositoryError> {
+        let user_id = Uuid::parse_str(user_id)?;
+        let client = self.pool.get().await?;
+        let updated = client.execute(
+            "UPDATE user_two_factor
+             SET recovery_code_hashes = array_remove(recovery_code_hashes, $2)
+             WHERE user_id = $1 AND $2 = ANY(recovery_code_hashes)",
+            &[&user_id, &code_hash]
+        ).await?;
+        Ok(updated == 1)
+    }
+
+    async fn record_attempt(
+        &self,
+        user_id: &str,
+        max_attempts: i32,
+        now: i64,
+        lock_until: i64
+    ) -> Result<bool, RepositoryError> {
+        let user_id = Uuid::parse_str(user_id)?;
+        let client = self.pool.get().await?;
+        let updated = client.execute(
+            "UPDATE user_two_factor SET failed_attempts = failed_attempts + 1,
+             locked_until = CASE WHEN failed_attempts + 1 >= $2 THEN $4 ELSE locked_until END
+             WHERE user_id = $1 AND locked_until <= $3",
+            &[&user_id, &max_attempts, &now, &lock_until]
+        ).await?;
+        Ok(updated == 1)
+    }
+
+    async fn reset_attempts(&self, user_id: &str) -> Result<(), RepositoryError> {
+        let user_id = Uuid::parse_str(user_id)?;
+        let client = self.pool.get().await?;
+        client.execute(
+            "UPDATE user_two_factor SET failed_attempts = 0, locked_until = 0 WHERE user_id = $1",
+            &[&user_id]
+        ).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<bool, RepositoryError> {
+        let user_id = Uuid::parse_str(user_id)?;
+        let client = self.pool.get().await?;
+        let deleted = client.execute(
+            "DELETE FROM user_two_factor WHERE user_id = $1",
+            &[&user_id]
+        ).await?;
+        Ok(deleted == 1)
+    }
+}
@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+use chrono::{ DateTime, Utc };
+use deadpool_postgres::Pool;
+
+use super::{ used_token_repository::UsedTokenRepository, user_repository::RepositoryError };
+
+/// PostgreSQL backend. Expired ids are deleted whenever a token is used.
+pub struct PostgresUsedTokenRepository {
+    pool: Pool,
+}
+
+impl PostgresUsedTokenRepository {
+    pub fn new(pool: Pool) -> Self {
+        PostgresUsedTokenRepository { pool }
+    }
+}
+
+#[async_trait]
+impl UsedTokenRepository for PostgresUsedTokenRepository {
+    async fn mark_used(
+        &self,
+        jti: &str,
+        expires_at: DateTime<Utc>
+    ) -> Result<bool, RepositoryError> {
+        let client = self.pool.get().await?;
+        client.execute("DELETE FROM used_tokens WHERE expires_at < now()", &[]).await?;
+        let inserted = client.execute(
+            "INSERT INTO used_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            &[&jti, &expires_at]
+        ).await?;
+        Ok(inserted == 1)
+    }
+}
@@ -0,0 +1,223 @@
+use async_trait::async_trait;
+use deadpool_postgres::{ Pool, PoolError };
+use chrono::Utc;
+use tokio_postgres::{ error::SqlState, types::ToSql, Error, Row };
+use uuid::Uuid;
+
+use crate::models::{ role_model::Role, user_model::{ User, UserPayload } };
+use super::user_repository::{ RepositoryError, UserKey, UserRepository };
+
+const USER_COLUMNS: &str =
+    "id, first_name, last_name, username, email, role, email_verified, created_at, updated_at";
+
+/// PostgreSQL backend on top of a `deadpool_postgres` connection pool.
+/// The schema is owned by `configs::migrations`.
+pub struct PostgresUserRepository {
+    pool: Pool,
+}
+
+impl PostgresUserRepository {
+    pub fn new(pool: Pool) -> Self {
+        PostgresUserRepository { pool }
+    }
+}
+
+impl From<Error> for RepositoryError {
+    fn from(err: Error) -> Self {
+        if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+            RepositoryError::DuplicateKey
+        } else {
+            RepositoryError::Backend { message: err.to_string() }
+        }
+    }
+}
+
+impl From<PoolError> for RepositoryError {
+    fn from(err: PoolError) -> Self {
+        match err {
+            PoolError::Backend(err) => err.into(),
+            err => RepositoryError::Backend { message: err.to_string() },
+        }
+    }
+}
+
+fn user_from_row(row: &Row) -> User {
+    User {
+        id: row.get::<_, Uuid>("id").to_string(),
+        first_name: row.get("first_name"),
+        last_name: row.get("last_name"),
+        username: row.get("username"),
+        email: row.get("email"),
+        // the column is constrained to known roles
+        role: row.get::<_, &str>("role").parse().unwrap_or_default(),
+        email_verified: row.get("email_verified"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+// WHERE clause and its parameter for `key`, numbered `$index`.
+fn condition(key: &UserKey, index: usize) -> (String, Box<dyn ToSql + Sync + Send>) {
+    match key {
+        UserKey::Id(id) => (format!("id = ${index}"), Box::new(*id)),
+        UserKey::Username(username) => (format!("username = ${index}"), Box::new(username.clone())),
+    }
+}
+
+#[async_trait]
+impl UserRepository for PostgresUserRepository {
+    async fn find(&self, key: &UserKey) -> Result<Option<User>, RepositoryError> {
+        let (condition, param) = condition(key, 1);
+        let client = self.pool.get().await?;
+        let row = client.query_opt(
+            &format!("SELECT {USER_COLUMNS} FROM users WHERE {condition}"),
+            &[&*param]
+        ).await?;
+        Ok(row.as_ref().map(user_from_row))
+    }
+
+    async fn list(
+        &self,
+        search: &str,
+        limit: i64,
+        offset: u64
+    ) -> Result<Vec<User>, RepositoryError> {
+        let offset = i64::try_from(offset).unwrap_or(i64::MAX);
+        // `\` is the default escape character of LIKE, so `search` matches as plain text
+        let pattern = format!(
+            "%{}%",
+            search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+        );
+        let client = self.pool.get().await?;
+        let rows = client.query(
+            &format!(
+                "SELECT {USER_COLUMNS} FROM users
+                 WHERE username ILIKE $1 ORDER BY created_at LIMIT $2 OFFSET $3"
+            ),
+            &[&pattern, &limit, &offset]
+        ).await?;
+        Ok(rows.iter().map(user_from_row).collect())
+    }
+
+    async fn insert(
+        &self,
+        user: User,
+        password_hash: Option<String>
+    ) -> Result<(), RepositoryError> {
+        let id = Uuid::parse_str(&user.id)?;
+        let client = self.pool.get().await?;
+        client.execute(
+            &format!(
+                "INSERT INTO users ({USER_COLUMNS}, password_hash)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"
+            ),
+            &[
+                &id,
+                &user.first_name,
+                &user.last_name,
+                &user.username,
+                &user.email,
+                &user.role.to_string(),
+                &user.email_verified,
+                &user.created_at,
+                &user.updated_at,
+                &password_hash,
+            ]
+        ).await?;
+        Ok(())
+    }
+
+    async fn password_hash(&self, key: &UserKey) -> Result<Option<String>, RepositoryError> {
+        let (condition, param) = condition(key, 1);
+        let client = self.pool.get().await?;
+        let row = client.query_opt(
+            &format!("SELECT password_hash FROM users WHERE {condition}"),
+            &[&*param]
+        ).await?;
+        Ok(row.and_then(|row| row.get("password_hash")))
+    }
+
+    async fn update(
+        &self,
+        key: &UserKey,
+        changes: UserPayload
+    ) -> Result<Option<User>, RepositoryError> {
+        let (condition, param) = condition(key, 6);
+        let client = self.pool.get().await?;
+        let row = client.query_opt(
+            &format!(
+                "UPDATE users SET first_name = $1, last_name = $2, username = $3, email = $4,
+                 updated_at = $5 WHERE {condition} RETURNING {USER_COLUMNS}"
+            ),
+            &[
+                &changes.first_name,
+                &changes.last_name,
+                &changes.username,
+                &changes.email,
+                &Utc::now(),
+                &*param,
+            ]
+        ).await?;
+        Ok(row.as_ref().map(user_from_row))
+    }
+
+    async fn set_role(&self, key: &UserKey, role: Role) -> Result<Option<User>, RepositoryError> {
+        let (condition, param) = condition(key, 3);
+        let client = self.pool.get().await?;
+        let row = client.query_opt(
+            &format!(
+                "UPDATE users SET role = $1, updated_at = $2 WHERE {condition} RETURNING {USER_COLUMNS}"
+            ),
+            &[&role.to_string(), &Utc::now(), &*param]
+        ).await?;
+        Ok(row.as_ref().map(user_from_row))
+    }
+
+    async fn set_email_verified(
+        &self,
+        key: &UserKey,
+        email: &str,
+        verified: bool
+    ) -> Result<bool, RepositoryError> {
+        let (condition, param) = condition(key, 4);
+        let client = self.pool.get().await?;
+        let updated = client.execute(
+            &format!(
+                "UPDATE users SET email_verified = $1, updated_at = $2
+                 WHERE email = $3 AND {condition}"
+            ),
+            &[&verified, &Utc::now(), &email, &*param]
+        ).await?;
+        Ok(updated == 1)
+    }
+
+    async fn set_password_hash(
+        &self,
+        key: &UserKey,
+        password_hash: String
+    ) -> Result<bool, RepositoryError> {
+        let (condition, param) = condition(key, 3);
+        let client = self.pool.get().await?;
+        let updated = client.execute(
+            &format!("UPDATE users SET password_hash = $1, updated_at = $2 WHERE {condition}"),
+            &[&password_hash, &Utc::now(), &*param]
+        ).await?;
+        Ok(updated == 1)
+    }
+
+    async fn delete(&self, key: &UserKey) -> Result<bool, RepositoryError> {
+        let (condition, param) = condition(key, 1);
+        let client = self.pool.get().await?;
+        let deleted = client.execute(
+            &format!("DELETE FROM users WHERE {condition}"),
+            &[&*param]
+        ).await?;
+        Ok(deleted == 1)
+    }
+
+    async fn count(&self) -> Result<u64, RepositoryError> {
+        let client = self.pool.get().await?;
+        let count: i64 = client.query_one("SELECT COUNT(*) FROM users", &[]).await?.get(0);
+        Ok(count as u64)
+    }
+}
@@ -0,0 +1,24 @@
+use async_trait::async_trait;
+
+use crate::models::refresh_token_model::RefreshToken;
+use super::user_repository::RepositoryError;
+
+/// Storage for `RefreshToken` records, kept in the same database as the users.
+#[async_trait]
+pub trait RefreshTokenRepository: Send + Sync {
+    /// Stores a newly issued token.
+    async fn insert(&self, token: RefreshToken) -> Result<(), RepositoryError>;
+
+    /// Finds the token with `token_hash`, whether it is used, revoked or expired.
+    async fn find(&self, token_hash: &str) -> Result<Option<RefreshToken>, RepositoryError>;
+
+    /// Marks the token as used, unless it already is or was revoked. Returns `false` in that
+    /// case, so two concurrent refreshes with the same token can't both succeed.
+    async fn mark_used(&self, token_hash: &str) -> Result<bool, RepositoryError>;
+
+    /// Revokes every token of `family_id`.
+    async fn revoke_family(&self, family_id: &str) -> Result<(), RepositoryError>;
+
+    /// Revokes every token of `user_id`, e.g. after the password was reset.
+    async fn revoke_user(&self, user_id: &str) -> Result<(), RepositoryError>;
+}
@@ -0,0 +1,103 @@
+use std::{ future::Future, sync::Arc };
+
+use async_trait::async_trait;
+use opentelemetry::KeyValue;
+
+use crate::{
+    models::{ role_model::Role, user_model::{ User, UserPayload } },
+    telemetry::trace::traced,
+};
+use super::user_repository::{ RepositoryError, UserKey, UserRepository };
+
+/// Wraps another `UserRepository` and runs every call in a database client span,
+/// named after the operation and the collection or table, like `find users`.
+pub struct TracedUserRepository {
+    inner: Arc<dyn UserRepository>,
+    // `db.system.name`, like `mongodb` or `postgresql`
+    system: &'static str,
+    collection: String,
+}
+
+impl TracedUserRepository {
+    pub fn new(inner: Arc<dyn UserRepository>, system: &'static str, collection: &str) -> Self {
+        TracedUserRepository { inner, system, collection: collection.to_owned() }
+    }
+
+    async fn span<T>(
+        &self,
+        operation: &'static str,
+        future: impl Future<Output = Result<T, RepositoryError>>
+    ) -> Result<T, RepositoryError> {
+        let attributes = vec![
+            KeyValue::new("db.system.name", self.system),
+            KeyValue::new("db.collection.name", self.collection.clone()),
+            KeyValue::new("db.operation.name", operation)
+        ];
+        traced(format!("{operation} {}", self.collection), attributes, future).await
+    }
+}
+
+#[async_trait]
+impl UserRepository for TracedUserRepository {
+    async fn find(&self, key: &UserKey) -> Result<Option<User>, RepositoryError> {
+        self.span("find", self.inner.find(key)).await
+    }
+
+    async fn list(
+        &self,
+        search: &str,
+        limit: i64,
+        offset: u64
+    ) -> Result<Vec<User>, RepositoryError> {
+        self.span("list", self.inner.list(search, limit, offset)).await
+    }
+
+    async fn insert(
+        &self,
+        user: User,
+        password_hash: Option<String>
+    ) -> Result<(), RepositoryError> {
+        self.span("insert", self.inner.insert(user, password_hash)).await
+    }
+
+    async fn password_hash(&self, key: &UserKey) -> Result<Option<String>, RepositoryError> {
+        self.span("password_hash", self.inner.password_hash(key)).await
+    }
+
+    async fn update(
+        &self,
+        key: &UserKey,
+        changes: UserPayload
+    ) -> Result<Option<User>, RepositoryError> {
+        self.span("update", self.inner.update(key, changes)).await
+    }
+
+    async fn set_role(&self, key: &UserKey, role: Role) -> Result<Option<User>, RepositoryError> {
+        self.span("set_role", self.inner.set_role(key, role)).await
+    }
+
+    async fn set_email_verified(
+        &self,
+        key: &UserKey,
+        email: &str,
+        verified: bool
+    ) -> Result<bool, RepositoryError> {
+        self.span("set_email_verified", self.inner.set_email_verified(key, email, verified)).await
+    }
+
+    async fn set_password_hash(
+        &self,
+        key: &UserKey,
+        password_hash: String
+    ) -> Result<bool, RepositoryError> {
+        self.span("set_password_hash", self.inner.set_password_hash(key, password_hash)).await
+    }
+
+    async fn delete(&self, key: &UserKey) -> Result<bool, RepositoryError> {
+        self.span("delete", self.inner.delete(key)).await
+    }
+
+    async fn count(&self) -> Result<u64, RepositoryError> {
+        self.span("count", self.inner.count()).await
+    }
+}
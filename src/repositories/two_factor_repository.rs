@@ -0,0 +1,45 @@
+use async_trait::async_trait;
+
+use crate::models::two_factor_model::TwoFactor;
+use super::user_repository::RepositoryError;
+
+/// Storage for `TwoFactor` records, one per user, kept in the same database as the users.
+#[async_trait]
+pub trait TwoFactorRepository: Send + Sync {
+    /// Finds the two-factor settings of `user_id`, confirmed or not.
+    async fn find(&self, user_id: &str) -> Result<Option<TwoFactor>, RepositoryError>;
+
+    /// Inserts or replaces the two-factor settings of `two_factor.user_id`.
+    async fn save(&self, two_factor: TwoFactor) -> Result<(), RepositoryError>;
+
+    /// Records that a code of time step `step` was accepted. Returns `false` if a code of
+    /// this or a later step was accepted before, so each code works once.
+    async fn record_step(&self, user_id: &str, step: i64) -> Result<bool, RepositoryError>;
+
+    /// Removes the recovery code with `code_hash`. Returns `false` if it is not there,
+    /// so each recovery code works once.
+    async fn use_recovery_code(
+        &self,
+        user_id: &str,
+        code_hash: &str
+    ) -> Result<bool, RepositoryError>;
+
+    /// Counts a code about to be checked for `user_id`, unless they are locked out at unix
+    /// time `now`. The attempt that reaches `max_attempts`, and every later one until
+    /// `reset_attempts`, locks them out until `lock_until`. Returns `false` when locked out
+    /// or without two-factor settings. Counting before checking keeps parallel guesses
+    /// under the cap.
+    async fn record_attempt(
+        &self,
+        user_id: &str,
+        max_attempts: i32,
+        now: i64,
+        lock_until: i64
+    ) -> Result<bool, RepositoryError>;
+
+    /// Resets the attempts and the lockout of `user_id` after a successful login.
+    async fn reset_attempts(&self, user_id: &str) -> Result<(), RepositoryError>;
+
+    /// Removes the two-factor settings of `user_id`. Returns `false` if there are none.
+    async fn delete(&self, user_id: &str) -> Result<bool, RepositoryError>;
+}
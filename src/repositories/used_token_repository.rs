@@ -0,0 +1,17 @@
+use async_trait::async_trait;
+use chrono::{ DateTime, Utc };
+
+use super::user_repository::RepositoryError;
+
+/// Remembers the `jti` of single-use tokens, such as the ones mailed for email verification and
+/// password resets, until they expire anyway.
+#[async_trait]
+pub trait UsedTokenRepository: Send + Sync {
+    /// Marks the token `jti` as used. Returns `false` if it already was, so two requests
+    /// with the same token can't both succeed.
+    async fn mark_used(
+        &self,
+        jti: &str,
+        expires_at: DateTime<Utc>
+    ) -> Result<bool, RepositoryError>;
+}
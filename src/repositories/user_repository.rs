@@ -0,0 +1,105 @@
+use async_trait::async_trait;
+use derive_more::{ Display, Error };
+use uuid::Uuid;
+
+use crate::models::{ role_model::Role, user_model::{ User, UserPayload } };
+
+// -- Repository errors.
+#[derive(Debug, Display, Error)]
+pub enum RepositoryError {
+    #[display("A user with the same username already exists.")]
+    DuplicateKey,
+
+    #[display("{message}")] Backend {
+        message: String,
+    },
+}
+
+impl From<uuid::Error> for RepositoryError {
+    fn from(err: uuid::Error) -> Self {
+        RepositoryError::Backend { message: err.to_string() }
+    }
+}
+
+/// Identifies a single user, either by its immutable id or by its current username.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UserKey {
+    Id(Uuid),
+    Username(String),
+}
+
+impl UserKey {
+    /// Treats `value` as an id when it is a UUID, otherwise as a username.
+    pub fn parse(value: String) -> Self {
+        match Uuid::parse_str(&value) {
+            Ok(id) => UserKey::Id(id),
+            Err(_) => UserKey::Username(value),
+        }
+    }
+
+    pub fn matches(&self, user: &User) -> bool {
+        match self {
+            UserKey::Id(id) => user.id == id.to_string(),
+            UserKey::Username(username) => &user.username == username,
+        }
+    }
+}
+
+/// Storage for `User` records, so handlers don't depend on a specific database.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Finds the user identified by `key`.
+    async fn find(&self, key: &UserKey) -> Result<Option<User>, RepositoryError>;
+
+    /// Lists users whose username contains `search`, ignoring case, skipping `offset` and
+    /// returning at most `limit`. `search` is plain text, not a pattern.
+    async fn list(
+        &self,
+        search: &str,
+        limit: i64,
+        offset: u64
+    ) -> Result<Vec<User>, RepositoryError>;
+
+    /// Inserts a new user, with an optional password hash for logging in.
+    /// Fails with `DuplicateKey` when the username is taken.
+    async fn insert(&self, user: User, password_hash: Option<String>) -> Result<(), RepositoryError>;
+
+    /// Gets the password hash of the user identified by `key`. It is kept out of `User`
+    /// so it can never end up in a response.
+    async fn password_hash(&self, key: &UserKey) -> Result<Option<String>, RepositoryError>;
+
+    /// Applies `changes` to the user identified by `key` and bumps `updated_at`.
+    /// The id and `created_at` are never changed. Returns the updated user, if any.
+    async fn update(
+        &self,
+        key: &UserKey,
+        changes: UserPayload
+    ) -> Result<Option<User>, RepositoryError>;
+
+    /// Sets the role of the user identified by `key` and bumps `updated_at`.
+    /// Returns the updated user, if any.
+    async fn set_role(&self, key: &UserKey, role: Role) -> Result<Option<User>, RepositoryError>;
+
+    /// Sets `email_verified` of the user identified by `key` and bumps `updated_at`, if its
+    /// email is still `email`. Returns `false` if there is no such user or the email changed.
+    async fn set_email_verified(
+        &self,
+        key: &UserKey,
+        email: &str,
+        verified: bool
+    ) -> Result<bool, RepositoryError>;
+
+    /// Replaces the password hash of the user identified by `key` and bumps `updated_at`.
+    /// Returns `false` if there is no such user.
+    async fn set_password_hash(
+        &self,
+        key: &UserKey,
+        password_hash: String
+    ) -> Result<bool, RepositoryError>;
+
+    /// Deletes the user identified by `key`. Returns `false` if there is no such user.
+    async fn delete(&self, key: &UserKey) -> Result<bool, RepositoryError>;
+
+    /// Counts all stored users.
+    async fn count(&self) -> Result<u64, RepositoryError>;
+}
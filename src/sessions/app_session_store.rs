@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use actix_session::storage::{
+    CookieSessionStore,
+    LoadError,
+    SaveError,
+    SessionKey,
+    SessionStore,
+    UpdateError,
+};
+use actix_web::cookie::time::Duration;
+
+use crate::telemetry::metrics::METRICS;
+use super::mongo_session_store::MongoSessionStore;
+
+/// The session store selected with `SESSION_BACKEND`. `SessionMiddleware` is generic over
+/// its store, so this keeps the app's type the same for every backend.
+pub enum AppSessionStore {
+    Cookie(CookieSessionStore),
+    Mongo(MongoSessionStore),
+}
+
+impl AppSessionStore {
+    // label of the session metrics
+    fn backend(&self) -> &'static str {
+        match self {
+            AppSessionStore::Cookie(_) => "cookie",
+            AppSessionStore::Mongo(_) => "mongo",
+        }
+    }
+}
+
+impl SessionStore for AppSessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        match self {
+            AppSessionStore::Cookie(store) => store.load(session_key).await,
+            AppSessionStore::Mongo(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: HashMap<String, String>,
+        ttl: &Duration
+    ) -> Result<SessionKey, SaveError> {
+        // `save` is only called for new sessions, changes go through `update`
+        let key = match self {
+            AppSessionStore::Cookie(store) => store.save(session_state, ttl).await,
+            AppSessionStore::Mongo(store) => store.save(session_state, ttl).await,
+        }?;
+        METRICS.sessions_created.with_label_values(&[self.backend()]).inc();
+        Ok(key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        ttl: &Duration
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            AppSessionStore::Cookie(store) => store.update(session_key, session_state, ttl).await,
+            AppSessionStore::Mongo(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        match self {
+            AppSessionStore::Cookie(store) => store.update_ttl(session_key, ttl).await,
+            AppSessionStore::Mongo(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        match self {
+            AppSessionStore::Cookie(store) => store.delete(session_key).await,
+            AppSessionStore::Mongo(store) => store.delete(session_key).await,
+        }?;
+        METRICS.sessions_ended.with_label_values(&[self.backend()]).inc();
+        Ok(())
+    }
+}
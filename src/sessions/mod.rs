@@ -0,0 +1,3 @@
+pub mod app_session_store;
+pub mod session_repository;
+pub mod mongo_session_store;
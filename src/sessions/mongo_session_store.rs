@@ -0,0 +1,160 @@
+use std::{ cmp::Reverse, collections::HashMap };
+
+use actix_session::storage::{ LoadError, SaveError, SessionKey, SessionStore, UpdateError };
+use actix_web::cookie::time::Duration;
+use async_trait::async_trait;
+use futures::stream::TryStreamExt;
+use mongodb::{ bson::{ doc, DateTime }, Collection, Database };
+use serde::{ Deserialize, Serialize };
+
+use crate::{
+    auth::token::{ generate_token, hash_token },
+    constants::{ SESSION_ID, SESSION_USER_ID },
+    models::session_model::{ state_value, SessionInfo },
+    repositories::user_repository::RepositoryError,
+};
+use super::session_repository::SessionRepository;
+
+pub const SESSION_COLLECTION: &str = "sessions";
+
+// Only the hash of the session key is stored, like for refresh tokens.
+// The session id and user id are copied out of the state, so sessions can be looked up by them.
+// `expires_at` is a BSON date for the TTL index.
+#[derive(Serialize, Deserialize)]
+struct SessionDocument {
+    key_hash: String,
+    session_id: Option<String>,
+    user_id: Option<String>,
+    state: HashMap<String, String>,
+    expires_at: DateTime,
+}
+
+fn expires_at(ttl: &Duration) -> DateTime {
+    DateTime::from_millis(DateTime::now().timestamp_millis() + ttl.whole_milliseconds() as i64)
+}
+
+/// Keeps session state in MongoDB, the cookie only carries a random session key.
+/// Expired sessions are removed by the TTL index created in `main`.
+#[derive(Clone)]
+pub struct MongoSessionStore {
+    collection: Collection<SessionDocument>,
+}
+
+impl MongoSessionStore {
+    pub fn new(db: &Database) -> Self {
+        MongoSessionStore {
+            collection: db.collection(SESSION_COLLECTION),
+        }
+    }
+}
+
+impl SessionStore for MongoSessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        // the TTL monitor runs once a minute, so expired sessions may still be there
+        let document = self.collection
+            .find_one(
+                doc! {
+                    "key_hash": hash_token(session_key.as_ref()),
+                    "expires_at": { "$gt": DateTime::now() },
+                }
+            ).await
+            .map_err(|err| LoadError::Other(err.into()))?;
+        Ok(document.map(|document| document.state))
+    }
+
+    async fn save(
+        &self,
+        session_state: HashMap<String, String>,
+        ttl: &Duration
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_token();
+        self.collection
+            .insert_one(SessionDocument {
+                key_hash: hash_token(&session_key),
+                session_id: state_value(&session_state, SESSION_ID),
+                user_id: state_value(&session_state, SESSION_USER_ID),
+                state: session_state,
+                expires_at: expires_at(ttl),
+            }).await
+            .map_err(|err| SaveError::Other(err.into()))?;
+        SessionKey::try_from(session_key).map_err(|err| SaveError::Other(err.into()))
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        ttl: &Duration
+    ) -> Result<SessionKey, UpdateError> {
+        let state = mongodb::bson
+            ::to_bson(&session_state)
+            .map_err(|err| UpdateError::Serialization(err.into()))?;
+        let result = self.collection
+            .update_one(
+                doc! { "key_hash": hash_token(session_key.as_ref()) },
+                doc! {
+                    "$set": {
+                        "session_id": state_value::<String>(&session_state, SESSION_ID),
+                        "user_id": state_value::<String>(&session_state, SESSION_USER_ID),
+                        "state": state,
+                        "expires_at": expires_at(ttl),
+                    },
+                }
+            ).await
+            .map_err(|err| UpdateError::Other(err.into()))?;
+        if result.matched_count == 1 {
+            return Ok(session_key);
+        }
+        // the session expired in the meantime, start a new one with the state
+        self.save(session_state, ttl).await.map_err(|err| UpdateError::Other(err.into()))
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        self.collection.update_one(
+            doc! { "key_hash": hash_token(session_key.as_ref()) },
+            doc! { "$set": { "expires_at": expires_at(ttl) } }
+        ).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        self.collection.delete_one(doc! { "key_hash": hash_token(session_key.as_ref()) }).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionRepository for MongoSessionStore {
+    async fn list(&self, user_id: &str) -> Result<Vec<SessionInfo>, RepositoryError> {
+        let cursor = self.collection.find(
+            doc! { "user_id": user_id, "expires_at": { "$gt": DateTime::now() } }
+        ).await?;
+        let documents: Vec<SessionDocument> = cursor.try_collect().await?;
+        let mut sessions: Vec<SessionInfo> = documents
+            .iter()
+            .filter_map(|document| SessionInfo::from_state(&document.state))
+            .collect();
+        sessions.sort_by_key(|session| Reverse(session.last_seen_at));
+        Ok(sessions)
+    }
+
+    async fn delete(&self, user_id: &str, session_id: &str) -> Result<bool, RepositoryError> {
+        let result = self.collection.delete_one(
+            doc! { "user_id": user_id, "session_id": session_id }
+        ).await?;
+        Ok(result.deleted_count == 1)
+    }
+
+    async fn delete_all(&self, user_id: &str) -> Result<(), RepositoryError> {
+        self.collection.delete_many(doc! { "user_id": user_id }).await?;
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<u64, RepositoryError> {
+        let unexpired = doc! { "expires_at": { "$gt": DateTime::now() } };
+        Ok(self.collection.count_documents(unexpired).await?)
+    }
+}
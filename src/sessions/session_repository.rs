@@ -0,0 +1,20 @@
+use async_trait::async_trait;
+
+use crate::{ models::session_model::SessionInfo, repositories::user_repository::RepositoryError };
+
+/// Lists and ends the sessions of a user. Only server-side session stores can do that,
+/// a cookie session is valid until the cookie expires.
+#[async_trait]
+pub trait SessionRepository: Send + Sync {
+    /// Lists the unexpired sessions of `user_id`, most recently seen first.
+    async fn list(&self, user_id: &str) -> Result<Vec<SessionInfo>, RepositoryError>;
+
+    /// Ends the session `session_id` of `user_id`. Returns `false` if there is no such session.
+    async fn delete(&self, user_id: &str, session_id: &str) -> Result<bool, RepositoryError>;
+
+    /// Ends every session of `user_id`.
+    async fn delete_all(&self, user_id: &str) -> Result<(), RepositoryError>;
+
+    /// Counts the unexpired sessions of all users.
+    async fn count(&self) -> Result<u64, RepositoryError>;
+}
@@ -0,0 +1,61 @@
+use std::{ future::Future, io::Write };
+
+use chrono::{ SecondsFormat, Utc };
+use env_logger::Env;
+use serde_json::json;
+
+use crate::{ configs::settings::LogFormat, telemetry::trace::current_trace_id };
+
+tokio::task_local! {
+    // the id of the request being handled, set by the `request_id` middleware
+    pub static REQUEST_ID: String;
+}
+
+/// The id of the request being handled, `None` outside of a request.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Runs `future` with `id` as the request id of its log lines and errors.
+pub async fn with_request_id<F: Future>(id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(id, future).await
+}
+
+/// Sets up the logger for `format`. Levels come from `RUST_LOG`, `info` by default.
+/// Lines logged while handling a request carry its id.
+pub fn init_logger(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_env(Env::new().default_filter_or("info"));
+    match format {
+        LogFormat::Text => {
+            builder.format(|buf, record| {
+                let level = buf.default_level_style(record.level());
+                write!(
+                    buf,
+                    "[{} {level}{}{level:#} {}",
+                    buf.timestamp(),
+                    record.level(),
+                    record.target()
+                )?;
+                if let Some(id) = current_request_id() {
+                    write!(buf, " request_id={id}")?;
+                }
+                writeln!(buf, "] {}", record.args())
+            })
+        }
+        LogFormat::Json => {
+            builder.format(|buf, record| {
+                let line =
+                    json!({
+                    "time": Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "request_id": current_request_id(),
+                    "trace_id": current_trace_id(),
+                    "message": record.args().to_string(),
+                });
+                writeln!(buf, "{line}")
+            })
+        }
+    };
+    builder.init();
+}
@@ -0,0 +1,136 @@
+use std::{ future::Future, sync::LazyLock, time::Duration };
+
+use mongodb::event::command::CommandEvent;
+use prometheus::{
+    Encoder,
+    HistogramOpts,
+    HistogramVec,
+    IntCounterVec,
+    IntGauge,
+    IntGaugeVec,
+    Opts,
+    Registry,
+    TextEncoder,
+};
+
+// Label of work done outside of a request, like index setup at startup.
+const NO_ROUTE: &str = "none";
+
+tokio::task_local! {
+    // the route pattern of the request being handled, set by the `track_requests` middleware
+    static ROUTE: String;
+}
+
+/// The Prometheus collectors of the server, served by `GET /metrics`.
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub http_requests_in_flight: IntGauge,
+    pub mongo_command_duration: HistogramVec,
+    pub mongo_command_errors: IntCounterVec,
+    pub qr_generated: IntCounterVec,
+    pub qr_generation_duration: HistogramVec,
+    pub sessions_created: IntCounterVec,
+    pub sessions_ended: IntCounterVec,
+    // counted in the session store on every scrape, so it survives restarts
+    pub sessions_active: IntGaugeVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let metrics = Metrics {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests handled"),
+                &["route", "method", "status"]
+            ).unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+                &["route", "method", "status"]
+            ).unwrap(),
+            http_requests_in_flight: IntGauge::new(
+                "http_requests_in_flight",
+                "HTTP requests being handled"
+            ).unwrap(),
+            mongo_command_duration: HistogramVec::new(
+                HistogramOpts::new("mongodb_command_duration_seconds", "MongoDB command latency"),
+                &["handler", "command"]
+            ).unwrap(),
+            mongo_command_errors: IntCounterVec::new(
+                Opts::new("mongodb_command_errors_total", "MongoDB commands that failed"),
+                &["handler", "command"]
+            ).unwrap(),
+            qr_generated: IntCounterVec::new(
+                Opts::new("qr_generated_total", "QR codes generated"),
+                &["format"]
+            ).unwrap(),
+            qr_generation_duration: HistogramVec::new(
+                HistogramOpts::new("qr_generation_duration_seconds", "QR code generation time")
+                    .buckets(prometheus::exponential_buckets(0.0001, 4.0, 8).unwrap()),
+                &["format"]
+            ).unwrap(),
+            sessions_created: IntCounterVec::new(
+                Opts::new("sessions_created_total", "Sessions created"),
+                &["backend"]
+            ).unwrap(),
+            sessions_ended: IntCounterVec::new(
+                Opts::new("sessions_ended_total", "Sessions ended by logout or purge"),
+                &["backend"]
+            ).unwrap(),
+            sessions_active: IntGaugeVec::new(
+                Opts::new("sessions_active", "Unexpired server-side sessions"),
+                &["backend"]
+            ).unwrap(),
+            registry,
+        };
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.http_requests.clone())).unwrap();
+        registry.register(Box::new(metrics.http_request_duration.clone())).unwrap();
+        registry.register(Box::new(metrics.http_requests_in_flight.clone())).unwrap();
+        registry.register(Box::new(metrics.mongo_command_duration.clone())).unwrap();
+        registry.register(Box::new(metrics.mongo_command_errors.clone())).unwrap();
+        registry.register(Box::new(metrics.qr_generated.clone())).unwrap();
+        registry.register(Box::new(metrics.qr_generation_duration.clone())).unwrap();
+        registry.register(Box::new(metrics.sessions_created.clone())).unwrap();
+        registry.register(Box::new(metrics.sessions_ended.clone())).unwrap();
+        registry.register(Box::new(metrics.sessions_active.clone())).unwrap();
+        metrics
+    }
+
+    /// All metrics in the Prometheus text format.
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+
+    /// Records a MongoDB command under the route of the request that ran it.
+    /// Set as the command event handler of the client, the driver calls it inline,
+    /// so it runs in the task of the request.
+    pub fn observe_mongo_command(&self, event: CommandEvent) {
+        let (command, duration, failed) = match &event {
+            CommandEvent::Succeeded(event) => (&event.command_name, event.duration, false),
+            CommandEvent::Failed(event) => (&event.command_name, event.duration, true),
+            _ => return,
+        };
+        let handler = ROUTE.try_with(Clone::clone).unwrap_or_else(|_| NO_ROUTE.to_owned());
+        let labels = [handler.as_str(), command.as_str()];
+        self.mongo_command_duration.with_label_values(&labels).observe(duration.as_secs_f64());
+        if failed {
+            self.mongo_command_errors.with_label_values(&labels).inc();
+        }
+    }
+
+    pub fn observe_qr(&self, format: &str, duration: Duration) {
+        self.qr_generated.with_label_values(&[format]).inc();
+        self.qr_generation_duration.with_label_values(&[format]).observe(duration.as_secs_f64());
+    }
+}
+
+/// Runs `future` with `route` as the handler label of the MongoDB commands it sends.
+pub async fn with_route<F: Future>(route: String, future: F) -> F::Output {
+    ROUTE.scope(route, future).await
+}
@@ -0,0 +1,263 @@
+use std::{
+    fmt::{ self, Display },
+    fs::OpenOptions,
+    future::Future,
+    io::{ self, Write },
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use anyhow::{ anyhow, Context as _ };
+use chrono::{ DateTime, SecondsFormat, Utc };
+use opentelemetry::{
+    global::{ self, BoxedTracer },
+    trace::{ FutureExt, SpanId, SpanKind, Status, TraceContextExt, Tracer },
+    Context,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    error::{ OTelSdkError, OTelSdkResult },
+    propagation::TraceContextPropagator,
+    trace::{ Sampler, SdkTracerProvider, SpanData, SpanExporter },
+    Resource,
+};
+use serde_json::{ json, Map, Value };
+
+use crate::configs::settings::{ TraceExporter, TracingSettings };
+
+const TRACER_NAME: &str = "actxol";
+
+/// Sets up the global tracer and the W3C `traceparent` propagator for `settings.exporter`.
+/// Returns the provider to shut down before exiting, so the last spans are exported,
+/// or `None` when tracing is off.
+pub fn init_tracer(settings: &TracingSettings) -> anyhow::Result<Option<SdkTracerProvider>> {
+    let sampler = Sampler::TraceIdRatioBased(settings.sample_ratio);
+    let resource = Resource::builder().with_service_name(settings.service_name.clone()).build();
+    let builder = SdkTracerProvider::builder()
+        .with_sampler(Sampler::ParentBased(Box::new(sampler)))
+        .with_resource(resource);
+    let builder = match settings.exporter {
+        TraceExporter::None => {
+            return Ok(None);
+        }
+        TraceExporter::Otlp => {
+            let exporter = opentelemetry_otlp::SpanExporter
+                ::builder()
+                .with_http()
+                .with_endpoint(settings.otlp_endpoint.clone())
+                .build()
+                .context("Error creating the OTLP exporter")?;
+            builder.with_batch_exporter(exporter)
+        }
+        TraceExporter::Stdout => builder.with_batch_exporter(JsonSpanExporter::new(io::stdout())),
+        TraceExporter::File => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&settings.file)
+                .map_err(|err| anyhow!("Error opening {}: {}", settings.file.display(), err))?;
+            builder.with_batch_exporter(JsonSpanExporter::new(file))
+        }
+    };
+    let provider = builder.build();
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider.clone());
+    Ok(Some(provider))
+}
+
+pub fn tracer() -> BoxedTracer {
+    global::tracer(TRACER_NAME)
+}
+
+/// Runs `future` in a client span, a child of the current span, like the request's.
+/// An error is recorded as the status of the span.
+pub async fn traced<T, E: Display>(
+    name: String,
+    attributes: Vec<KeyValue>,
+    future: impl Future<Output = Result<T, E>>
+) -> Result<T, E> {
+    let tracer = tracer();
+    let span = tracer
+        .span_builder(name)
+        .with_kind(SpanKind::Client)
+        .with_attributes(attributes)
+        .start(&tracer);
+    let cx = Context::current_with_span(span);
+    let result = future.with_context(cx.clone()).await;
+    let span = cx.span();
+    if let Err(err) = &result {
+        span.set_status(Status::error(err.to_string()));
+    }
+    span.end();
+    result
+}
+
+/// Runs `f` in an internal span, a child of the current span.
+pub fn in_span<T>(name: &'static str, f: impl FnOnce() -> T) -> T {
+    tracer().in_span(name, |_| f())
+}
+
+/// The trace id of the current span, if there is one.
+pub fn current_trace_id() -> Option<String> {
+    let cx = Context::current();
+    let span_context = cx.span().span_context().clone();
+    span_context.is_valid().then(|| span_context.trace_id().to_string())
+}
+
+// Writes every span as a JSON line, to look at traces without a collector.
+struct JsonSpanExporter {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonSpanExporter {
+    fn new(out: impl Write + Send + 'static) -> Self {
+        JsonSpanExporter { out: Mutex::new(Box::new(out)) }
+    }
+}
+
+impl fmt::Debug for JsonSpanExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("JsonSpanExporter")
+    }
+}
+
+impl SpanExporter for JsonSpanExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let failure = |err: io::Error| OTelSdkError::InternalFailure(err.to_string());
+        let mut out = self.out
+            .lock()
+            .map_err(|_| OTelSdkError::InternalFailure("Span output poisoned".to_owned()))?;
+        for span in &batch {
+            writeln!(out, "{}", span_json(span)).map_err(failure)?;
+        }
+        out.flush().map_err(failure)
+    }
+}
+
+fn span_json(span: &SpanData) -> Value {
+    let attributes: Map<String, Value> = span.attributes
+        .iter()
+        .map(|kv| (kv.key.to_string(), Value::String(kv.value.to_string())))
+        .collect();
+    let (status, status_message) = match &span.status {
+        Status::Unset => ("unset", None),
+        Status::Ok => ("ok", None),
+        Status::Error { description } => ("error", Some(description.to_string())),
+    };
+    let duration = span.end_time.duration_since(span.start_time).unwrap_or_default();
+    json!({
+        "trace_id": span.span_context.trace_id().to_string(),
+        "span_id": span.span_context.span_id().to_string(),
+        "parent_span_id": (span.parent_span_id != SpanId::INVALID)
+            .then(|| span.parent_span_id.to_string()),
+        "name": span.name,
+        "kind": format!("{:?}", span.span_kind).to_lowercase(),
+        "start": timestamp(span.start_time),
+        "duration_ms": duration.as_secs_f64() * 1000.0,
+        "status": status,
+        "status_message": status_message,
+        "attributes": attributes,
+    })
+}
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+// The JSON lines of the spans ended since `record_spans`.
+#[cfg(test)]
+static RECORDED_SPANS: std::sync::LazyLock<std::sync::Arc<Mutex<Vec<u8>>>> =
+    std::sync::LazyLock::new(Default::default);
+
+#[cfg(test)]
+struct RecordedSpans;
+
+#[cfg(test)]
+impl Write for RecordedSpans {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        RECORDED_SPANS.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Installs a global tracer that keeps every span as soon as it ends, for tests.
+#[cfg(test)]
+pub fn record_spans() {
+    static INSTALLED: std::sync::Once = std::sync::Once::new();
+    INSTALLED.call_once(|| {
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(JsonSpanExporter::new(RecordedSpans))
+            .build();
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        global::set_tracer_provider(provider);
+    });
+}
+
+/// The recorded spans of the trace `trace_id`, in the order they ended.
+#[cfg(test)]
+pub fn recorded_spans(trace_id: &str) -> Vec<Value> {
+    let recorded = RECORDED_SPANS.lock().unwrap();
+    String::from_utf8_lossy(&recorded)
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|span| span["trace_id"] == trace_id)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs `future` in a server span of a new trace and returns the trace id.
+    async fn in_request<F: Future>(future: F) -> String {
+        let tracer = tracer();
+        let span = tracer.span_builder("request").with_kind(SpanKind::Server).start(&tracer);
+        let cx = Context::current_with_span(span);
+        let trace_id = cx.span().span_context().trace_id().to_string();
+        future.with_context(cx.clone()).await;
+        cx.span().end();
+        trace_id
+    }
+
+    #[actix_web::test]
+    async fn database_calls_are_child_spans() {
+        record_spans();
+        let trace_id = in_request(async {
+            let attributes = vec![KeyValue::new("db.operation.name", "find")];
+            let found = traced("find users".to_owned(), attributes, async { Ok::<_, String>(1) });
+            assert_eq!(found.await, Ok(1));
+            let failed = traced("insert users".to_owned(), Vec::new(), async {
+                Err::<(), _>("duplicate key".to_owned())
+            });
+            assert!(failed.await.is_err());
+            in_span("qr.render", || assert!(current_trace_id().is_some()));
+        }).await;
+
+        let spans = recorded_spans(&trace_id);
+        let names: Vec<&str> = spans
+            .iter()
+            .map(|span| span["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["find users", "insert users", "qr.render", "request"]);
+        let request = &spans[3];
+        for child in &spans[..3] {
+            assert_eq!(child["parent_span_id"], request["span_id"]);
+        }
+        assert_eq!(spans[0]["kind"], "client");
+        assert_eq!(spans[0]["status"], "unset");
+        assert_eq!(spans[0]["attributes"]["db.operation.name"], "find");
+        assert_eq!(spans[1]["status"], "error");
+        assert_eq!(spans[1]["status_message"], "duplicate key");
+        assert_eq!(spans[2]["kind"], "internal");
+    }
+
+    #[test]
+    fn no_trace_id_outside_of_a_span() {
+        assert_eq!(current_trace_id(), None);
+    }
+}
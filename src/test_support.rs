@@ -0,0 +1,92 @@
+//! Shared setup for the handler and middleware tests, backed by the memory repositories.
+
+use std::sync::{ Arc, Mutex };
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::{
+    auth::{
+        password::hash_password,
+        token::hash_token,
+        totp::{ generate_recovery_codes, generate_secret, normalize_recovery_code },
+    },
+    configs::db::{ AppStates, DbStatus },
+    mailers::mailer::{ Email, MailError, Mailer },
+    models::{ role_model::Role, two_factor_model::TwoFactor, user_model::{ User, UserPayload } },
+    repositories::{
+        memory_api_key_repository::MemoryApiKeyRepository,
+        memory_refresh_token_repository::MemoryRefreshTokenRepository,
+        memory_two_factor_repository::MemoryTwoFactorRepository,
+        memory_used_token_repository::MemoryUsedTokenRepository,
+        memory_user_repository::MemoryUserRepository,
+        user_repository::UserKey,
+    },
+};
+
+pub const PASSWORD: &str = "correct horse battery staple";
+
+pub fn memory_states() -> AppStates {
+    let db_status = Arc::new(DbStatus::default());
+    db_status.set_ready(true);
+    AppStates {
+        users: Arc::new(MemoryUserRepository::default()),
+        refresh_tokens: Arc::new(MemoryRefreshTokenRepository::default()),
+        api_keys: Arc::new(MemoryApiKeyRepository::default()),
+        two_factor: Arc::new(MemoryTwoFactorRepository::default()),
+        used_tokens: Arc::new(MemoryUsedTokenRepository::default()),
+        sessions: None,
+        mongo: None,
+        postgres: None,
+        db_status,
+    }
+}
+
+/// Keeps the emails it is asked to send.
+#[derive(Default)]
+pub struct RecordingMailer {
+    pub sent: Mutex<Vec<Email>>,
+}
+
+#[async_trait]
+impl Mailer for RecordingMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        self.sent.lock().unwrap().push(email);
+        Ok(())
+    }
+}
+
+/// Stores a user with `role` that logs in with `PASSWORD`.
+pub async fn create_user(states: &AppStates, username: &str, role: Role) -> User {
+    let user = User::new(UserPayload {
+        first_name: "hello".to_owned(),
+        last_name: "world".to_owned(),
+        username: username.to_owned(),
+        email: format!("{username}@example.com"),
+    });
+    let password_hash = hash_password(PASSWORD).unwrap();
+    states.users.insert(user.clone(), Some(password_hash)).await.unwrap();
+    states.users.set_role(&UserKey::parse(user.id), role).await.unwrap().unwrap()
+}
+
+/// Turns two-factor authentication on for `user` and returns its secret and recovery codes.
+pub async fn enable_two_factor(states: &AppStates, user: &User) -> (String, Vec<String>) {
+    let secret = generate_secret();
+    let recovery_codes = generate_recovery_codes();
+    states.two_factor
+        .save(TwoFactor {
+            user_id: user.id.clone(),
+            secret: secret.clone(),
+            enabled: true,
+            recovery_code_hashes: recovery_codes
+                .iter()
+                .map(|code| hash_token(&normalize_recovery_code(code)))
+                .collect(),
+            last_used_step: 0,
+            failed_attempts: 0,
+            locked_until: 0,
+            created_at: Utc::now(),
+        }).await
+        .unwrap();
+    (secret, recovery_codes)
+}